@@ -0,0 +1,126 @@
+use calloop::channel::Sender;
+use eyre::{Context, Result};
+use log::{debug, warn};
+use palette::{IntoColor, Oklab, Srgb};
+use zbus::zvariant::{OwnedValue, Value};
+
+// https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.Settings.html
+const NAMESPACE: &str = "org.freedesktop.appearance";
+const KEY: &str = "accent-color";
+
+/// The accent color of the desktop from the settings portal, `None` if it doesn't have one.
+pub fn read() -> Result<Option<Oklab>> {
+    let connection =
+        zbus::blocking::Connection::session().wrap_err("connecting to the session bus")?;
+    let value: OwnedValue = settings_proxy(&connection)?
+        .call("ReadOne", &(NAMESPACE, KEY))
+        .wrap_err("reading the accent color from the settings portal")?;
+    to_accent(value)
+}
+
+/// Sends the accent color once and then whenever it changes, from a thread since zbus
+/// blocks. Gives up quietly without a settings portal, the gradient just stays as it is.
+pub fn watch(sender: Sender<Option<Oklab>>) {
+    std::thread::spawn(move || {
+        if let Err(err) = watch_blocking(&sender) {
+            debug!("Not following the accent color: {err:?}");
+        }
+    });
+}
+
+fn watch_blocking(sender: &Sender<Option<Oklab>>) -> Result<()> {
+    let connection =
+        zbus::blocking::Connection::session().wrap_err("connecting to the session bus")?;
+    let proxy = settings_proxy(&connection)?;
+    // subscribed before reading, so a change in between isn't lost
+    let changes = proxy
+        .receive_signal("SettingChanged")
+        .wrap_err("subscribing to SettingChanged")?;
+
+    let value: OwnedValue = proxy
+        .call("ReadOne", &(NAMESPACE, KEY))
+        .wrap_err("reading the accent color from the settings portal")?;
+    if sender.send(to_accent(value)?).is_err() {
+        return Ok(());
+    }
+
+    for message in changes {
+        let (namespace, key, value) =
+            match message.body().deserialize::<(String, String, OwnedValue)>() {
+                Ok(change) => change,
+                Err(err) => {
+                    warn!("Ignoring a malformed SettingChanged signal: {err}");
+                    continue;
+                }
+            };
+        if namespace != NAMESPACE || key != KEY {
+            continue;
+        }
+        let accent = to_accent(value).unwrap_or_else(|err| {
+            warn!("Ignoring the accent color: {err:?}");
+            None
+        });
+        // the app is gone
+        if sender.send(accent).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn settings_proxy(connection: &zbus::blocking::Connection) -> Result<zbus::blocking::Proxy<'_>> {
+    zbus::blocking::Proxy::new(
+        connection,
+        "org.freedesktop.portal.Desktop",
+        "/org/freedesktop/portal/desktop",
+        "org.freedesktop.portal.Settings",
+    )
+    .wrap_err("creating the settings portal proxy")
+}
+
+fn to_accent(value: OwnedValue) -> Result<Option<Oklab>> {
+    let (r, g, b) = <(f64, f64, f64)>::try_from(Value::from(value))
+        .wrap_err("the accent color isn't an (r, g, b) triple")?;
+    Ok(accent_color([r, g, b]))
+}
+
+/// The portal's sRGB accent color, components outside of `0..=1` mean that there is none.
+pub fn accent_color(rgb: [f64; 3]) -> Option<Oklab> {
+    if !rgb.iter().all(|component| (0.0..=1.0).contains(component)) {
+        return None;
+    }
+    let [r, g, b] = rgb.map(|component| component as f32);
+    Some(Srgb::new(r, g, b).into_linear().into_color())
+}
+
+/// How far the gradient is shifted toward the accent color, only in hue and chroma so
+/// `gradient.lightness` still applies.
+pub fn offset(accent: Option<Oklab>) -> Oklab {
+    match accent {
+        Some(accent) => Oklab::new(0.0, accent.a, accent.b),
+        None => Oklab::new(0.0, 0.0, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accent_color_of_portal_values() {
+        // what the portal sends when there is no accent color
+        assert!(accent_color([-1.0, -1.0, -1.0]).is_none());
+        assert!(accent_color([1.5, 0.0, 0.0]).is_none());
+        assert!(accent_color([f64::NAN, 0.0, 0.0]).is_none());
+
+        let white = accent_color([1.0, 1.0, 1.0]).unwrap();
+        assert!((white.l - 1.0).abs() < 1e-3);
+        assert!(white.a.abs() < 1e-3 && white.b.abs() < 1e-3);
+        let blue = accent_color([0.2, 0.4, 1.0]).unwrap();
+        assert!(blue.b < -0.1, "{blue:?}");
+
+        // only hue and chroma
+        assert_eq!(offset(Some(blue)).into_components(), (0.0, blue.a, blue.b));
+        assert_eq!(offset(None).into_components(), (0.0, 0.0, 0.0));
+    }
+}
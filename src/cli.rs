@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+
+use crate::{
+    config::{Config, ImageFit, LauncherBackend, RenderMode},
+    control::Command,
+};
+
+/// Color-based program-launching wallpaper for Wayland.
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// Print the discovered applications and their icon colors, then exit.
+    #[arg(long)]
+    pub list_apps: bool,
+
+    /// Write the discovered applications and their icon colors as JSON to the file, or to
+    /// stdout without one, then exit.
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    pub dump_json: Option<Option<PathBuf>>,
+
+    /// Send a command to the colouncher running on this Wayland display, print its reply and
+    /// exit.
+    #[arg(long, value_enum, value_name = "COMMAND")]
+    pub send: Option<Command>,
+
+    /// Render a single frame into the `--output` PNG and exit, without connecting to Wayland.
+    #[arg(long, requires = "output")]
+    pub once: bool,
+
+    /// Where `--once` writes the PNG.
+    #[arg(long, requires = "once")]
+    pub output: Option<PathBuf>,
+
+    /// Render `--once` on the GPU exactly like on screen, including icon sprites.
+    #[arg(long, requires = "once")]
+    pub gpu: bool,
+
+    /// Size of the frame rendered by `--once`, like `1920x1080`.
+    #[arg(long, value_parser = parse_size, default_value = "1920x1080")]
+    pub size: (u32, u32),
+
+    /// Path to the config file. Defaults to `$XDG_CONFIG_HOME/colouncher/config.toml`.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// How to render the wallpaper. Overrides the config file.
+    #[arg(long, value_enum)]
+    pub render_mode: Option<RenderMode>,
+
+    /// Image to show in the `image` render mode. Overrides the config file.
+    #[arg(long)]
+    pub image: Option<PathBuf>,
+
+    /// How to fit the image to the output. Overrides the config file.
+    #[arg(long, value_enum)]
+    pub image_fit: Option<ImageFit>,
+
+    /// Oklab lightness of the gradient, between 0 and 1. Overrides the config file.
+    #[arg(long)]
+    pub lightness: Option<f32>,
+
+    /// Saturation of the gradient, 1 is the default. Overrides the config file.
+    #[arg(long)]
+    pub chroma: Option<f32>,
+
+    /// Only show the wallpaper on the output with this name, can be given multiple times.
+    /// Overrides the config file.
+    #[arg(long, value_name = "NAME")]
+    pub only_output: Vec<String>,
+
+    /// Only look for .desktop files in this directory, can be given multiple times.
+    /// Overrides the config file.
+    #[arg(long, value_name = "PATH")]
+    pub app_dir: Vec<PathBuf>,
+
+    /// Start hidden and show up as an overlay with `--send toggle`. Overrides the config file.
+    #[arg(long)]
+    pub toggle: bool,
+
+    /// Disable dithering of the gradient. Overrides the config file.
+    #[arg(long)]
+    pub no_dither: bool,
+
+    /// How to launch programs. Overrides the config file.
+    #[arg(long, value_enum)]
+    pub launcher: Option<LauncherBackend>,
+
+    /// Log the matched app instead of launching it. Overrides the config file.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Search apps by typing after clicking on the wallpaper. Overrides the config file.
+    #[arg(long)]
+    pub keyboard: bool,
+
+    /// Log debug messages. Overrides `$RUST_LOG`.
+    #[arg(short, long, conflicts_with = "quiet")]
+    pub verbose: bool,
+
+    /// Only log warnings and errors. Overrides `$RUST_LOG`.
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Format of the log output.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {size}"))?;
+    let parse = |value: &str| match value.parse::<u32>() {
+        Ok(0) => Err("the size can't be zero".to_owned()),
+        Ok(value) => Ok(value),
+        Err(err) => Err(format!("invalid size {value}: {err}")),
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+impl Args {
+    pub fn apply_to(&self, config: &mut Config) {
+        if let Some(mode) = self.render_mode {
+            config.render.mode = mode;
+        }
+        if let Some(path) = &self.image {
+            config.image.path = Some(path.clone());
+        }
+        if let Some(fit) = self.image_fit {
+            config.image.fit = fit;
+        }
+        if let Some(lightness) = self.lightness {
+            config.gradient.lightness = lightness;
+        }
+        if let Some(chroma) = self.chroma {
+            config.gradient.chroma = chroma;
+        }
+        if !self.only_output.is_empty() {
+            config.render.only_outputs = self.only_output.clone();
+        }
+        if !self.app_dir.is_empty() {
+            config.discovery.app_dirs = self.app_dir.clone();
+            config.discovery.only_app_dirs = true;
+        }
+        if self.toggle {
+            config.surface.toggle = true;
+        }
+        if self.no_dither {
+            config.render.dither = false;
+        }
+        if let Some(backend) = self.launcher {
+            config.launcher.backend = backend;
+        }
+        if self.dry_run {
+            config.launcher.dry_run = true;
+        }
+        if self.keyboard {
+            config.keyboard.enabled = true;
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use palette::{Oklab, color_difference::EuclideanDistance};
+
+use crate::{color, config::MatchingConfig};
+
+/// Gives up on converging after this many rounds, the clusters are good enough by then.
+const MAX_ITERATIONS: usize = 100;
+
+/// Apps grouped by similar colors, so the voronoi diagram has fewer and larger cells.
+pub struct Clusters {
+    /// The mean color of every cluster.
+    pub centroids: Vec<Oklab>,
+    /// The indices of the colors in every cluster, in the same order as the centroids.
+    pub members: Vec<Vec<usize>>,
+}
+
+/// Groups the colors into at most `k` clusters with k-means. The initial centroids are the
+/// colors furthest away from the ones picked before, starting with the first color, so the
+/// same colors always give the same clusters.
+pub fn cluster(colors: &[Oklab], k: usize) -> Clusters {
+    let mut centroids = Vec::<Oklab>::new();
+    while centroids.len() < k.min(colors.len()) {
+        let furthest = colors
+            .iter()
+            .map(|&color| {
+                let distance = centroids
+                    .iter()
+                    .map(|centroid| centroid.distance_squared(color))
+                    .fold(f32::INFINITY, f32::min);
+                (color, distance)
+            })
+            .reduce(|a, b| if b.1 > a.1 { b } else { a });
+        match furthest {
+            Some((color, _)) => centroids.push(color),
+            None => break,
+        }
+    }
+
+    let mut assignments = vec![usize::MAX; colors.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let new_assignments = colors
+            .iter()
+            .map(|&color| nearest(&centroids, color).unwrap_or(0))
+            .collect::<Vec<_>>();
+        if new_assignments == assignments {
+            break;
+        }
+        assignments = new_assignments;
+
+        for (i, centroid) in centroids.iter_mut().enumerate() {
+            let members = colors
+                .iter()
+                .zip(&assignments)
+                .filter(|&(_, &cluster)| cluster == i)
+                .map(|(&color, _)| color)
+                .collect::<Vec<_>>();
+            // an empty cluster keeps its place, something might move to it later
+            if !members.is_empty() {
+                let sum = members
+                    .iter()
+                    .fold(Oklab::new(0.0, 0.0, 0.0), |sum, &color| sum + color);
+                *centroid = sum / members.len() as f32;
+            }
+        }
+    }
+
+    let mut members = vec![Vec::new(); centroids.len()];
+    for (index, &cluster) in assignments.iter().enumerate() {
+        members[cluster].push(index);
+    }
+    let (centroids, members) = centroids
+        .into_iter()
+        .zip(members)
+        .filter(|(_, members)| !members.is_empty())
+        .unzip();
+    Clusters { centroids, members }
+}
+
+/// Puts every color into the first group with a color closer than `distance`, so
+/// near-identical colors share a cell instead of one of them being impossible to hit.
+pub fn merge_duplicates(colors: &[Oklab], distance: f32) -> Clusters {
+    let mut members = Vec::<Vec<usize>>::new();
+    for (index, &color) in colors.iter().enumerate() {
+        let group = members.iter_mut().find(|group| {
+            group
+                .iter()
+                .any(|&member| colors[member].distance_squared(color) < distance * distance)
+        });
+        match group {
+            Some(group) => group.push(index),
+            None => members.push(vec![index]),
+        }
+    }
+    let centroids = members
+        .iter()
+        .map(|group| {
+            let sum = group
+                .iter()
+                .fold(Oklab::new(0.0, 0.0, 0.0), |sum, &member| {
+                    sum + colors[member]
+                });
+            sum / group.len() as f32
+        })
+        .collect();
+    Clusters { centroids, members }
+}
+
+/// The index of the color closest to `color` in Oklab.
+pub fn nearest(colors: &[Oklab], color: Oklab) -> Option<usize> {
+    colors
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
+            a.distance_squared(color)
+                .total_cmp(&b.distance_squared(color))
+        })
+        .map(|(index, _)| index)
+}
+
+/// The index of the color closest to `color` with the matching metric, with the weight of
+/// every color taken off its squared distance. Missing weights are 0.
+// keep it in sync with the gpu implementation
+pub fn nearest_weighted(
+    colors: &[Oklab],
+    weights: &[f32],
+    color: Oklab,
+    matching: &MatchingConfig,
+) -> Option<usize> {
+    let score = |index: usize| {
+        color::match_distance_squared(colors[index], color, matching)
+            - weights.get(index).copied().unwrap_or(0.0)
+    };
+    (0..colors.len())
+        .min_by(|&a, &b| score(a).total_cmp(&score(b)))
+        .filter(|&index| color::is_close_enough(colors[index], color, matching))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_color() {
+        let colors = [
+            Oklab::new(0.5, 0.2, 0.0),
+            Oklab::new(0.5, -0.2, 0.0),
+            Oklab::new(0.9, 0.0, 0.1),
+        ];
+        assert_eq!(nearest(&colors, Oklab::new(0.5, -0.15, 0.0)), Some(1));
+        assert_eq!(nearest(&colors, Oklab::new(0.8, 0.0, 0.1)), Some(2));
+        assert_eq!(nearest(&[], Oklab::new(0.5, 0.0, 0.0)), None);
+    }
+
+    fn similar_pairs() -> [Oklab; 4] {
+        [
+            Oklab::new(0.5, 0.2, 0.0),
+            Oklab::new(0.5, -0.2, 0.0),
+            Oklab::new(0.5, 0.19, 0.0),
+            Oklab::new(0.5, -0.21, 0.0),
+        ]
+    }
+
+    #[test]
+    fn cluster_groups_similar_colors() {
+        let clusters = cluster(&similar_pairs(), 2);
+        assert_eq!(clusters.members, [vec![0, 2], vec![1, 3]]);
+        assert!((clusters.centroids[0].a - 0.195).abs() < 1e-6);
+        assert!((clusters.centroids[1].a + 0.205).abs() < 1e-6);
+
+        // never more clusters than colors
+        assert_eq!(cluster(&similar_pairs(), 10).members.len(), 4);
+        assert!(cluster(&[], 3).members.is_empty());
+    }
+
+    #[test]
+    fn merge_duplicates_within_the_distance() {
+        let merged = merge_duplicates(&similar_pairs(), 0.05);
+        assert_eq!(merged.members, [vec![0, 2], vec![1, 3]]);
+        assert!((merged.centroids[0].a - 0.195).abs() < 1e-6);
+
+        let apart = merge_duplicates(&similar_pairs(), 0.001);
+        assert_eq!(apart.members.len(), 4);
+    }
+}
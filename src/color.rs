@@ -0,0 +1,185 @@
+use palette::{Clamp, IntoColor, Lab, LinSrgb, Oklab, Srgb, Xyz, white_point::D65};
+
+use crate::config::{ColorMetric, MatchingConfig};
+
+/// Converts to 8-bit sRGB, clamping colors outside of the sRGB gamut to its boundary first.
+///
+/// Many Oklab colors (like the corners of the gradient) aren't representable in sRGB,
+/// and quantizing them unclamped gives wrong bytes.
+// keep it in sync with the gpu implementation, which clamps before quantizing
+pub fn to_srgb8(color: Oklab) -> Srgb<u8> {
+    let linear: LinSrgb = color.into_color();
+    Srgb::from_linear(linear.clamp())
+}
+
+/// Like [`to_srgb8`], but moved by less than half an 8-bit step before rounding. With the
+/// thresholds of [`dither_threshold`], a flat color spreads over its two nearest levels.
+// keep it in sync with the gpu implementation
+pub fn to_srgb8_dithered(color: Oklab, threshold: f32) -> Srgb<u8> {
+    let linear: LinSrgb = color.into_color();
+    let srgb: Srgb = Srgb::from_linear(linear.clamp());
+    let offset = (threshold - 0.5) / 255.0;
+    let quantize = |component: f32| ((component + offset).clamp(0.0, 1.0) * 255.0).round() as u8;
+    Srgb::new(
+        quantize(srgb.red),
+        quantize(srgb.green),
+        quantize(srgb.blue),
+    )
+}
+
+/// Ordered (4x4 Bayer) dithering threshold of a pixel, in (0, 1) and 0.5 on average.
+// keep it in sync with the gpu implementation
+pub fn dither_threshold(x: u32, y: u32) -> f32 {
+    const BAYER: [u8; 16] = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5];
+    (f32::from(BAYER[(y % 4 * 4 + x % 4) as usize]) + 0.5) / 16.0
+}
+
+/// Where the color is in the space the closest app is found in, distances there are Euclidean.
+// keep it in sync with the gpu implementation
+pub fn match_coordinates(color: Oklab, matching: &MatchingConfig) -> [f32; 3] {
+    match matching.metric {
+        ColorMetric::Oklab => [color.l, color.a, color.b],
+        ColorMetric::Cielab => {
+            let xyz: Xyz<D65, f32> = color.into_color();
+            let lab: Lab<D65, f32> = xyz.into_color();
+            // about the range of Oklab, so weights and thresholds mean roughly the same
+            [lab.l / 100.0, lab.a / 100.0, lab.b / 100.0]
+        }
+        ColorMetric::WeightedOklab => {
+            [color.l * matching.lightness_weight.sqrt(), color.a, color.b]
+        }
+    }
+}
+
+/// Multiplies the color with its alpha in linear light and encodes it as sRGB again, which is
+/// what a filtered sRGB texture needs so transparent pixels don't darken the edges around them.
+pub fn premultiply([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    let alpha = f32::from(a) / 255.0;
+    let linear = Srgb::new(r, g, b).into_linear::<f32>() * alpha;
+    let srgb: Srgb<u8> = Srgb::from_linear(linear);
+    [srgb.red, srgb.green, srgb.blue, a]
+}
+
+/// Whether the colors are within `matching.max_distance` of each other.
+// keep it in sync with the gpu implementation
+pub fn is_close_enough(a: Oklab, b: Oklab, matching: &MatchingConfig) -> bool {
+    matching
+        .max_distance
+        .is_none_or(|max| match_distance_squared(a, b, matching) <= max * max)
+}
+
+/// Converts a Display P3 color to sRGB, both with the sRGB transfer function. Colors outside of
+/// the sRGB gamut are clamped to it.
+pub fn display_p3_to_srgb8([r, g, b]: [u8; 3]) -> [u8; 3] {
+    // the primaries differ, the white point and transfer function don't
+    const P3_TO_SRGB: [[f32; 3]; 3] = [
+        [1.224_940_2, -0.224_940_4, 0.0],
+        [-0.042_056_955, 1.042_057_1, 0.0],
+        [-0.019_637_555, -0.078_636_05, 1.098_273_6],
+    ];
+    let p3 = Srgb::new(r, g, b).into_linear::<f32>();
+    let p3 = [p3.red, p3.green, p3.blue];
+    let [r, g, b] = P3_TO_SRGB.map(|row| (0..3).map(|i| row[i] * p3[i]).sum::<f32>());
+    let srgb: Srgb<u8> = Srgb::from_linear(LinSrgb::new(r, g, b).clamp());
+    [srgb.red, srgb.green, srgb.blue]
+}
+
+pub fn match_distance_squared(a: Oklab, b: Oklab, matching: &MatchingConfig) -> f32 {
+    let (a, b) = (
+        match_coordinates(a, matching),
+        match_coordinates(b, matching),
+    );
+    (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_p3_to_srgb8_keeps_white_and_clamps() {
+        assert_eq!(display_p3_to_srgb8([255, 255, 255]), [255, 255, 255]);
+        assert_eq!(display_p3_to_srgb8([0, 0, 0]), [0, 0, 0]);
+        // the purest P3 red is outside of sRGB
+        assert_eq!(display_p3_to_srgb8([255, 0, 0]), [255, 0, 0]);
+        let [r, g, b] = display_p3_to_srgb8([128, 128, 128]);
+        assert!(r.abs_diff(128) <= 1 && g.abs_diff(128) <= 1 && b.abs_diff(128) <= 1);
+    }
+
+    #[test]
+    fn premultiply_in_linear_light() {
+        assert_eq!(premultiply([255, 128, 0, 255]), [255, 128, 0, 255]);
+        assert_eq!(premultiply([255, 255, 255, 0]), [0, 0, 0, 0]);
+        // half of white in linear light is much brighter than half of 255 in sRGB
+        let [r, g, b, a] = premultiply([255, 255, 255, 128]);
+        assert_eq!(a, 128);
+        assert!(r == g && g == b && (186..=190).contains(&r));
+    }
+
+    #[test]
+    fn dither_threshold_covers_the_step_evenly() {
+        let mut thresholds = (0..4)
+            .flat_map(|y| (0..4).map(move |x| dither_threshold(x, y)))
+            .collect::<Vec<_>>();
+        let mean = thresholds.iter().sum::<f32>() / 16.0;
+        assert!((mean - 0.5).abs() < 1e-6);
+        thresholds.sort_by(f32::total_cmp);
+        for (i, threshold) in thresholds.iter().enumerate() {
+            assert_eq!(*threshold, (i as f32 + 0.5) / 16.0);
+        }
+        // it repeats every 4 pixels
+        assert_eq!(dither_threshold(5, 6), dither_threshold(1, 2));
+    }
+
+    #[test]
+    fn dithering_spreads_a_flat_color_over_neighboring_levels() {
+        // halfway between the 8-bit levels 100 and 101
+        let value = 100.5 / 255.0;
+        let color: Oklab = Srgb::new(value, value, value).into_linear().into_color();
+        let reds = (0..4)
+            .flat_map(|y| (0..4).map(move |x| to_srgb8_dithered(color, dither_threshold(x, y)).red))
+            .collect::<Vec<_>>();
+        assert!(reds.iter().all(|red| (100..=101).contains(red)));
+        let high = reds.iter().filter(|&&red| red == 101).count();
+        assert_eq!(high, 8);
+        // without dithering it's a single level
+        assert!((100..=101).contains(&to_srgb8(color).red));
+    }
+
+    #[test]
+    fn to_srgb8_clamps_to_the_gamut_boundary() {
+        // far too red for sRGB: linear red is about 1.7 and green slightly negative
+        let color = to_srgb8(Oklab::new(0.7, 0.4, 0.0));
+        assert_eq!((color.red, color.green), (255, 0));
+        assert!(color.blue.abs_diff(148) <= 1);
+
+        let white = to_srgb8(Oklab::new(2.0, 0.0, 0.0));
+        assert_eq!(white.into_components(), (255, 255, 255));
+        let black = to_srgb8(Oklab::new(-1.0, 0.0, 0.0));
+        assert_eq!(black.into_components(), (0, 0, 0));
+        let nan = to_srgb8(Oklab::new(f32::NAN, 0.0, 0.0));
+        assert_eq!(nan.into_components(), (0, 0, 0));
+    }
+
+    #[test]
+    fn is_close_enough_within_max_distance() {
+        let app = Oklab::new(0.6, 0.1, 0.0);
+        let unlimited = MatchingConfig::default();
+        assert!(is_close_enough(app, Oklab::new(0.6, -0.9, 0.5), &unlimited));
+
+        let matching = MatchingConfig {
+            max_distance: Some(0.1),
+            ..MatchingConfig::default()
+        };
+        assert!(is_close_enough(app, Oklab::new(0.6, 0.19, 0.0), &matching));
+        assert!(!is_close_enough(app, Oklab::new(0.6, 0.21, 0.0), &matching));
+        // with the matching metric, lightness counts less with weighted-oklab
+        let weighted = MatchingConfig {
+            metric: crate::config::ColorMetric::WeightedOklab,
+            lightness_weight: 0.25,
+            ..matching.clone()
+        };
+        assert!(!is_close_enough(app, Oklab::new(0.75, 0.1, 0.0), &matching));
+        assert!(is_close_enough(app, Oklab::new(0.75, 0.1, 0.0), &weighted));
+    }
+}
@@ -0,0 +1,962 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub render: RenderConfig,
+    pub surface: SurfaceConfig,
+    pub gradient: GradientConfig,
+    pub image: ImageConfig,
+    pub discovery: DiscoveryConfig,
+    pub icons: IconConfig,
+    pub launcher: LauncherConfig,
+    pub matching: MatchingConfig,
+    pub categories: CategoryConfig,
+    pub keyboard: KeyboardConfig,
+    pub recent: RecentConfig,
+    pub hint: HintConfig,
+    pub time_of_day: TimeOfDayConfig,
+    /// Overrides for specific outputs.
+    pub outputs: Vec<OutputRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderConfig {
+    pub mode: RenderMode,
+    /// Dither the output to hide banding in the 8-bit gradient.
+    pub dither: bool,
+    /// Always render at this many pixels per logical pixel and let the compositor scale the
+    /// result, instead of reallocating for every scale change. Needs `wp_viewporter`.
+    pub buffer_scale: Option<f32>,
+    /// Render in the output's own orientation on rotated outputs, so the compositor doesn't
+    /// have to rotate the wallpaper every time it draws it.
+    pub match_transform: bool,
+    /// How long gradient changes from a config reload fade in, in milliseconds. 0 switches
+    /// right away. Switching between render modes never fades.
+    pub crossfade_ms: u64,
+    /// Group the apps into this many clusters of similar colors, so the voronoi cells stay
+    /// large enough to hit with many apps. Clicking a cluster lists its apps.
+    pub clusters: Option<usize>,
+    /// Makes the voronoi cells of often launched apps larger, between 0 (off) and 1.
+    /// The cell sizes are only updated on startup.
+    pub popularity: f32,
+    /// After how many days a launch only counts half.
+    pub popularity_half_life_days: f32,
+    /// Only show the wallpaper on outputs with these names (globs like `DP-*`), leaving the
+    /// others to other wallpaper tools. Empty means every output.
+    pub only_outputs: Vec<String>,
+    /// Film grain over the gradient, so it looks less flat. How far it moves the Oklab
+    /// lightness, 0 disables it and 0.03 is subtle.
+    pub grain: f32,
+    pub present_mode: PresentMode,
+    /// How many frames can be queued before rendering waits for the compositor. Lower means
+    /// less latency, higher means fewer stalls.
+    pub frame_latency: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PresentMode {
+    /// Mailbox if the surface supports it, fifo otherwise.
+    #[default]
+    Auto,
+    /// Newer frames replace queued ones, the smoothest.
+    Mailbox,
+    /// Every frame waits for its turn, which uses the least power.
+    Fifo,
+    /// Like fifo, but late frames are shown right away.
+    FifoRelaxed,
+    /// Frames are shown right away, which might tear.
+    Immediate,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenderMode {
+    /// The plain gradient, with the voronoi diagram shown while right-clicking.
+    #[default]
+    Gradient,
+    /// Always show the voronoi diagram of the app colors.
+    Voronoi,
+    /// Show the image from `image.path`, clicking picks the app closest to the clicked pixel.
+    Image,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SurfaceConfig {
+    /// Which layer the surface is on. `keyboard.enabled` lets it take keyboard focus when
+    /// clicked on any layer, which on `top` and `overlay` works like focusing a window.
+    pub layer: SurfaceLayer,
+    /// The edges of the output the surface sticks to. Defaults to all of them, covering the
+    /// whole output, or to none with a `size`, which centers it.
+    pub anchor: Option<Vec<Edge>>,
+    /// Distance from the anchored edges in logical pixels, as top, right, bottom and left.
+    pub margin: [i32; 4],
+    /// Width and height in logical pixels, to make it a panel instead of the whole output.
+    pub size: Option<[u32; 2]>,
+    /// Start hidden and show up on the overlay layer with `colouncher --send toggle`, taking
+    /// all keyboard input until an app is launched or Escape is pressed.
+    pub toggle: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SurfaceLayer {
+    /// Behind all windows, like a wallpaper.
+    #[default]
+    Background,
+    /// Behind all windows, but in front of other wallpapers.
+    Bottom,
+    /// In front of windows, but behind fullscreen windows.
+    Top,
+    /// In front of everything, for using it as a launcher that's shown on demand.
+    Overlay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GradientConfig {
+    /// Oklab lightness of the whole gradient, between 0 and 1.
+    pub lightness: f32,
+    /// Scales the a/b range of the gradient, higher is more saturated.
+    pub chroma: f32,
+    /// Moves the center of the gradient in the a/b plane.
+    pub chroma_offset: [f32; 2],
+    /// Also move it toward the accent color of the desktop, from the settings portal.
+    /// Only the top-level setting counts, not the one of outputs.
+    pub accent: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageConfig {
+    pub path: Option<PathBuf>,
+    pub fit: ImageFit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageFit {
+    /// Scale the image to fill the whole output, cropping what doesn't fit.
+    #[default]
+    Cover,
+    /// Scale the image to fit into the output, showing the gradient around it.
+    Contain,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// How many directories deep to look for .desktop files below each `applications` directory.
+    pub max_depth: usize,
+    /// Only keep one of several entries with the same `StartupWMClass`.
+    pub dedup_wm_class: bool,
+    /// Entries matching any of these are ignored.
+    pub exclude: Vec<ExcludeRule>,
+    /// Directories with .desktop files to look in before the `applications` directories of
+    /// the XDG data directories, so their entries shadow the usual ones.
+    pub app_dirs: Vec<PathBuf>,
+    /// Only look in `app_dirs`, not in the XDG data directories.
+    pub only_app_dirs: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExcludeRule {
+    /// The desktop file id, `*` and `?` work as wildcards.
+    Id(String),
+    /// A regex matched against the app's name.
+    Name(String),
+    /// The exact `StartupWMClass`.
+    WmClass(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconConfig {
+    /// Pixels with an alpha below this are ignored when averaging icon colors.
+    pub alpha_cutoff: f32,
+    /// Scale icons down to at most this width and height before averaging their colors,
+    /// which is a lot faster for large icons. Full resolution if unset.
+    pub average_size: Option<u32>,
+    /// How much more pixels in the center of an icon count than the ones at the edges when
+    /// averaging. 0 weighs all pixels the same, 2 makes the corners count about 7 times less.
+    pub center_weight: f32,
+    /// Draw every app's icon at its place in the voronoi diagram.
+    pub sprites: bool,
+    /// Keep apps whose icon can't be found, with a muted color derived from their
+    /// id. They are dropped otherwise.
+    pub fallback_color: bool,
+    /// What icons are composited over before averaging their colors, so the color matches how
+    /// they look on it.
+    pub background: IconBackground,
+    /// Show the plain gradient right away and load the apps and their icons in the
+    /// background, fading in their colors once they're ready.
+    pub load_in_background: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconBackground {
+    /// Nothing, every pixel counts as much as it is opaque.
+    #[default]
+    None,
+    White,
+    Black,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LauncherConfig {
+    pub backend: LauncherBackend,
+    /// Only log what would be launched, to try out the color matching.
+    pub dry_run: bool,
+    /// Launching the same app again within this many milliseconds is ignored, like from a
+    /// double click.
+    /// 0 turns it off.
+    pub debounce_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LauncherBackend {
+    /// `niri msg action spawn`, so niri can track the launched program.
+    #[default]
+    Niri,
+    /// Spawn the program as a child process directly.
+    Direct,
+    /// `systemd-run --user --scope`, which puts every program into its own cgroup.
+    /// Falls back to `direct` if systemd-run is missing.
+    SystemdRun,
+    /// `swaymsg exec`, so sway starts the program like one from its own config.
+    /// Falls back to `direct` if swaymsg is missing.
+    Sway,
+    /// `hyprctl dispatch exec`, so Hyprland starts the program like one from its own config.
+    /// Falls back to `direct` if hyprctl is missing.
+    Hyprland,
+    /// `sway` or `hyprland` when running in them, otherwise `systemd-run` if there is a user systemd
+    /// instance and `direct` if not.
+    Auto,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchingConfig {
+    /// How the distance between colors is measured when finding the closest app.
+    pub metric: ColorMetric,
+    /// How much lightness differences count compared to hue and chroma, for `weighted-oklab`.
+    pub lightness_weight: f32,
+    /// What happens to apps with almost the same color, where one would always win.
+    pub duplicates: DuplicateStrategy,
+    /// Apps whose colors are closer than this in Oklab count as duplicates.
+    pub duplicate_distance: f32,
+    /// Colors farther than this from every app, with the matching metric, don't launch
+    /// anything and are drawn washed out. Unlimited if unset.
+    pub max_distance: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CategoryConfig {
+    /// How far the colors of apps are turned toward the hue of their category, so related apps
+    /// end up next to each other. 0 leaves them alone, 1 gives them the category's hue.
+    pub bias: f32,
+    /// The Oklch hue in degrees of each category from the `Categories` key, like
+    /// `Development = 220`. An app belongs to the first of its categories listed here.
+    pub hues: HashMap<String, f32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateStrategy {
+    /// Leave them as they are.
+    #[default]
+    Keep,
+    /// Move the colors apart, by the same amount in the same direction every time.
+    Jitter,
+    /// Share one voronoi cell, clicking it lists the apps like a cluster does.
+    Merge,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMetric {
+    #[default]
+    Oklab,
+    /// CIE L*a*b* with a D65 white point.
+    Cielab,
+    /// Oklab with lightness scaled by `lightness_weight`, so icons that are darker or lighter
+    /// than the gradient still match their hue.
+    WeightedOklab,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    /// Take keyboard focus when clicked, so apps can be searched by typing their name.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecentConfig {
+    /// Show this many of the most recently launched apps along an edge, 0 turns it off.
+    pub count: usize,
+    /// The edge the recent apps are shown along.
+    pub edge: Edge,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HintConfig {
+    /// Show a hint in a corner for this many seconds after starting, 0 turns it off.
+    pub duration_secs: f32,
+    /// The text of the hint, `{count}` is replaced with the number of apps.
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeOfDayConfig {
+    /// Shift the gradient's lightness and hue with the local time.
+    pub enabled: bool,
+    /// How often the shift is recomputed, in seconds.
+    pub update_interval: u64,
+    pub interpolation: Interpolation,
+    /// The offsets at specific times of the day, wrapping around midnight.
+    pub keypoints: Vec<TimeKeypoint>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interpolation {
+    Linear,
+    /// Eases in and out of every keypoint.
+    #[default]
+    Smooth,
+    /// Jumps to the next keypoint once its time is reached.
+    Step,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeKeypoint {
+    /// The hour of the day, fractions are allowed.
+    pub hour: f32,
+    /// Added to the Oklab lightness of the gradient.
+    #[serde(default)]
+    pub lightness: f32,
+    /// Added to the Oklab a (green-red) of the gradient.
+    #[serde(default)]
+    pub a: f32,
+    /// Added to the Oklab b (blue-yellow) of the gradient.
+    #[serde(default)]
+    pub b: f32,
+}
+
+/// Applies to every output matching all of its patterns, later rules override earlier ones.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputRule {
+    /// The connector name like `DP-1`, `*` and `?` work as wildcards.
+    pub name: Option<String>,
+    /// The make and model of the monitor, `*` and `?` work as wildcards.
+    pub description: Option<String>,
+
+    pub mode: Option<RenderMode>,
+    pub dither: Option<bool>,
+    pub lightness: Option<f32>,
+    pub chroma: Option<f32>,
+    pub fit: Option<ImageFit>,
+}
+
+impl OutputRule {
+    fn matches(&self, name: Option<&str>, description: Option<&str>) -> bool {
+        let matches = |pattern: &Option<String>, value: Option<&str>| match pattern {
+            Some(pattern) => value.is_some_and(|value| glob_match(pattern, value)),
+            None => true,
+        };
+        matches(&self.name, name) && matches(&self.description, description)
+    }
+
+    fn apply_to(&self, config: &mut Config) {
+        if let Some(mode) = self.mode {
+            config.render.mode = mode;
+        }
+        if let Some(dither) = self.dither {
+            config.render.dither = dither;
+        }
+        if let Some(lightness) = self.lightness {
+            config.gradient.lightness = lightness;
+        }
+        if let Some(chroma) = self.chroma {
+            config.gradient.chroma = chroma;
+        }
+        if let Some(fit) = self.fit {
+            config.image.fit = fit;
+        }
+    }
+}
+
+impl Default for LauncherConfig {
+    fn default() -> Self {
+        Self {
+            backend: LauncherBackend::default(),
+            dry_run: false,
+            debounce_ms: 400,
+        }
+    }
+}
+
+impl Default for MatchingConfig {
+    fn default() -> Self {
+        Self {
+            metric: ColorMetric::default(),
+            lightness_weight: 0.5,
+            duplicates: DuplicateStrategy::default(),
+            duplicate_distance: 0.01,
+            max_distance: None,
+        }
+    }
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            mode: RenderMode::default(),
+            dither: true,
+            buffer_scale: None,
+            match_transform: false,
+            crossfade_ms: 250,
+            clusters: None,
+            popularity: 0.0,
+            popularity_half_life_days: 30.0,
+            only_outputs: Vec::new(),
+            grain: 0.0,
+            present_mode: PresentMode::default(),
+            frame_latency: 2,
+        }
+    }
+}
+
+impl GradientConfig {
+    /// The gradient `t` of the way from `self` to `other`.
+    pub fn lerp(&self, other: &GradientConfig, t: f32) -> GradientConfig {
+        let lerp = |from: f32, to: f32| from + (to - from) * t;
+        GradientConfig {
+            lightness: lerp(self.lightness, other.lightness),
+            chroma: lerp(self.chroma, other.chroma),
+            chroma_offset: [
+                lerp(self.chroma_offset[0], other.chroma_offset[0]),
+                lerp(self.chroma_offset[1], other.chroma_offset[1]),
+            ],
+            accent: other.accent,
+        }
+    }
+}
+
+impl Default for GradientConfig {
+    fn default() -> Self {
+        Self {
+            lightness: 0.7,
+            chroma: 1.0,
+            chroma_offset: [0.0, 0.0],
+            accent: false,
+        }
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            dedup_wm_class: false,
+            exclude: Vec::new(),
+            app_dirs: Vec::new(),
+            only_app_dirs: false,
+        }
+    }
+}
+
+impl IconConfig {
+    /// Whether decoded icons need to be kept around after discovery. They are dropped by
+    /// default since they aren't free for hundreds of apps.
+    pub fn keep_thumbnails(&self) -> bool {
+        self.sprites
+    }
+}
+
+impl Default for RecentConfig {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            edge: Edge::Bottom,
+        }
+    }
+}
+
+impl Default for CategoryConfig {
+    fn default() -> Self {
+        // the main categories of the desktop menu spec
+        let hues = [
+            ("AudioVideo", 300.0),
+            ("Development", 220.0),
+            ("Education", 60.0),
+            ("Game", 0.0),
+            ("Graphics", 330.0),
+            ("Network", 190.0),
+            ("Office", 30.0),
+            ("Science", 150.0),
+            ("Settings", 260.0),
+            ("System", 240.0),
+            ("Utility", 100.0),
+        ];
+        Self {
+            bias: 0.0,
+            hues: hues
+                .into_iter()
+                .map(|(category, hue)| (category.to_owned(), hue))
+                .collect(),
+        }
+    }
+}
+
+impl Default for HintConfig {
+    fn default() -> Self {
+        Self {
+            duration_secs: 0.0,
+            text: "{count} apps, click a color to launch one".into(),
+        }
+    }
+}
+
+impl Default for TimeOfDayConfig {
+    fn default() -> Self {
+        let keypoint = |hour, lightness, a, b| TimeKeypoint {
+            hour,
+            lightness,
+            a,
+            b,
+        };
+        Self {
+            enabled: false,
+            update_interval: 5 * 60,
+            interpolation: Interpolation::default(),
+            keypoints: vec![
+                // warm and dim at night, cool and bright around midday
+                keypoint(0.0, -0.15, 0.02, 0.05),
+                keypoint(7.0, -0.05, 0.01, 0.03),
+                keypoint(13.0, 0.05, -0.01, -0.03),
+                keypoint(19.0, -0.05, 0.03, 0.05),
+            ],
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from `path`, or from the default location if `None`.
+    /// A missing file at the default location is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("reading {}", path.display()));
+            }
+        };
+
+        Self::parse(&contents).wrap_err_with(|| format!("parsing {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Whether the wallpaper goes on the output, see `render.only_outputs`.
+    pub fn shows_output(&self, name: Option<&str>) -> bool {
+        let patterns = &self.render.only_outputs;
+        patterns.is_empty()
+            || name.is_some_and(|name| patterns.iter().any(|pattern| glob_match(pattern, name)))
+    }
+
+    /// The config for an output, with all matching rules applied.
+    pub fn for_output(&self, name: Option<&str>, description: Option<&str>) -> Config {
+        let mut config = self.clone();
+        for rule in &self.outputs {
+            if rule.matches(name, description) {
+                rule.apply_to(&mut config);
+            }
+        }
+        config
+    }
+
+    /// Catches values that would produce nonsense, called after the CLI overrides are applied.
+    /// Colors outside of the sRGB gamut are fine, they are clamped when converting.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(scale) = self.render.buffer_scale
+            && !(scale.is_finite() && scale > 0.0)
+        {
+            bail!("render.buffer_scale must be positive, got {scale}");
+        }
+        if let Some([width, height]) = self.surface.size
+            && (width == 0 || height == 0)
+        {
+            bail!("surface.size can't be zero, got {width}x{height}");
+        }
+        if self.surface.size.is_none()
+            && let Some(anchor) = &self.surface.anchor
+            && !([Edge::Top, Edge::Bottom, Edge::Left, Edge::Right]
+                .iter()
+                .all(|edge| anchor.contains(edge)))
+        {
+            bail!("without a surface.size, the surface has to be anchored to all edges");
+        }
+        if self.render.clusters == Some(0) {
+            bail!("render.clusters must be at least 1");
+        }
+        if !(0.0..=1.0).contains(&self.render.popularity) {
+            bail!(
+                "render.popularity must be between 0 and 1, got {}",
+                self.render.popularity
+            );
+        }
+        if !(self.render.popularity_half_life_days.is_finite()
+            && self.render.popularity_half_life_days > 0.0)
+        {
+            bail!(
+                "render.popularity_half_life_days must be positive, got {}",
+                self.render.popularity_half_life_days
+            );
+        }
+        if !(0.0..=1.0).contains(&self.render.grain) {
+            bail!(
+                "render.grain must be between 0 and 1, got {}",
+                self.render.grain
+            );
+        }
+        if self.render.frame_latency == 0 {
+            bail!("render.frame_latency must be at least 1");
+        }
+        // the timer would fire again right away, forever
+        if self.time_of_day.update_interval == 0 {
+            bail!("time_of_day.update_interval must be at least 1");
+        }
+        if !(self.matching.lightness_weight.is_finite() && self.matching.lightness_weight >= 0.0) {
+            bail!(
+                "matching.lightness_weight can't be negative, got {}",
+                self.matching.lightness_weight
+            );
+        }
+        if !(self.hint.duration_secs.is_finite() && self.hint.duration_secs >= 0.0) {
+            bail!(
+                "hint.duration_secs can't be negative, got {}",
+                self.hint.duration_secs
+            );
+        }
+        if !(0.0..=1.0).contains(&self.categories.bias) {
+            bail!(
+                "categories.bias must be between 0 and 1, got {}",
+                self.categories.bias
+            );
+        }
+        if let Some(distance) = self.matching.max_distance
+            && !(distance.is_finite() && distance > 0.0)
+        {
+            bail!("matching.max_distance must be positive, got {distance}");
+        }
+        if !(self.matching.duplicate_distance.is_finite() && self.matching.duplicate_distance > 0.0)
+        {
+            bail!(
+                "matching.duplicate_distance must be positive, got {}",
+                self.matching.duplicate_distance
+            );
+        }
+        if !(self.icons.center_weight.is_finite() && self.icons.center_weight >= 0.0) {
+            bail!(
+                "icons.center_weight can't be negative, got {}",
+                self.icons.center_weight
+            );
+        }
+        for rule in &self.outputs {
+            let mut config = self.clone();
+            rule.apply_to(&mut config);
+            config.validate_gradient().wrap_err("in an output rule")?;
+        }
+        self.validate_gradient()
+    }
+
+    fn validate_gradient(&self) -> Result<()> {
+        let gradient = &self.gradient;
+        if !(0.0..=1.0).contains(&gradient.lightness) {
+            bail!(
+                "gradient.lightness must be between 0 and 1, got {}",
+                gradient.lightness
+            );
+        }
+        if !(gradient.chroma.is_finite() && gradient.chroma > 0.0) {
+            bail!("gradient.chroma must be positive, got {}", gradient.chroma);
+        }
+        if !gradient
+            .chroma_offset
+            .iter()
+            .all(|offset| offset.is_finite())
+        {
+            bail!("gradient.chroma_offset must be finite");
+        }
+        Ok(())
+    }
+}
+
+/// Matches `*` to any number of characters and `?` to exactly one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // where to continue if the current attempt after a `*` fails
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("colouncher").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("org.gnome.*", "org.gnome.Nautilus.desktop"));
+        assert!(glob_match("*steam*", "steam_app_1234.desktop"));
+        assert!(glob_match("app?.desktop", "app2.desktop"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("app?.desktop", "app.desktop"));
+        assert!(!glob_match("org.gnome.*", "org.kde.dolphin.desktop"));
+        assert!(!glob_match("a*b", "acbd"));
+    }
+
+    #[test]
+    fn parse_sample_config() {
+        let config = Config::parse(
+            r#"
+            [render]
+            mode = "voronoi"
+            dither = false
+
+            [gradient]
+            lightness = 0.6
+
+            [icons]
+            alpha_cutoff = 0.25
+
+            [launcher]
+            backend = "systemd-run"
+            debounce_ms = 0
+
+            [[outputs]]
+            name = "DP-*"
+            mode = "gradient"
+            "#,
+        )
+        .unwrap();
+
+        let mut expected = Config::default();
+        expected.render.mode = RenderMode::Voronoi;
+        expected.render.dither = false;
+        expected.gradient.lightness = 0.6;
+        expected.icons.alpha_cutoff = 0.25;
+        expected.launcher.backend = LauncherBackend::SystemdRun;
+        expected.launcher.debounce_ms = 0;
+        expected.outputs.push(OutputRule {
+            name: Some("DP-*".into()),
+            description: None,
+            mode: Some(RenderMode::Gradient),
+            dither: None,
+            lightness: None,
+            chroma: None,
+            fit: None,
+        });
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn parse_empty_config_is_default() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert_eq!(Config::parse("[render]\n").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_fields() {
+        assert!(Config::parse("[render]\nditer = false\n").is_err());
+        assert!(Config::parse("[rendr]\n").is_err());
+        assert!(Config::parse("[[outputs]]\nnmae = \"DP-1\"\n").is_err());
+    }
+
+    #[test]
+    fn load_missing_explicit_path_is_an_error() {
+        let path = std::env::temp_dir().join(format!(
+            "colouncher-test-{}-missing.toml",
+            std::process::id()
+        ));
+        assert!(Config::load(Some(&path)).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_a_zero_update_interval() {
+        let mut config = Config::default();
+        config.validate().unwrap();
+        config.time_of_day.update_interval = 0;
+        assert!(config.validate().is_err());
+        config.time_of_day.update_interval = 1;
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn validate_gradient_rejects_bad_lightness_and_chroma() {
+        Config::default().validate().unwrap();
+
+        for lightness in [-0.1, 1.1, f32::NAN, f32::INFINITY] {
+            let mut config = Config::default();
+            config.gradient.lightness = lightness;
+            assert!(config.validate().is_err(), "lightness {lightness}");
+        }
+        for chroma in [0.0, -0.1, f32::NAN, f32::INFINITY] {
+            let mut config = Config::default();
+            config.gradient.chroma = chroma;
+            assert!(config.validate().is_err(), "chroma {chroma}");
+        }
+
+        let mut config = Config::default();
+        config.gradient.chroma_offset[0] = f32::NAN;
+        assert!(config.validate().is_err());
+
+        // an output rule is checked after applying it to the base config
+        let mut config = Config::default();
+        config.outputs.push(OutputRule {
+            name: Some("DP-1".into()),
+            description: None,
+            mode: None,
+            dither: None,
+            lightness: Some(2.0),
+            chroma: None,
+            fit: None,
+        });
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn for_output_applies_matching_rules_in_order() {
+        let config = Config::parse(
+            r#"
+            [gradient]
+            chroma = 0.8
+
+            [[outputs]]
+            name = "DP-*"
+            mode = "voronoi"
+            lightness = 0.5
+
+            [[outputs]]
+            description = "Dell *"
+            lightness = 0.6
+
+            [[outputs]]
+            name = "DP-2"
+            description = "Dell *"
+            dither = false
+            "#,
+        )
+        .unwrap();
+
+        let dp1 = config.for_output(Some("DP-1"), Some("LG 27GL850"));
+        assert_eq!(dp1.render.mode, RenderMode::Voronoi);
+        assert_eq!(dp1.gradient.lightness, 0.5);
+        // not overridden, so it's the base config's
+        assert_eq!(dp1.gradient.chroma, 0.8);
+        assert!(dp1.render.dither);
+
+        let hdmi = config.for_output(Some("HDMI-A-1"), Some("Dell U2720Q"));
+        assert_eq!(hdmi.render.mode, RenderMode::Gradient);
+        assert_eq!(hdmi.gradient.lightness, 0.6);
+
+        // every rule matches, the later ones win
+        let dp2 = config.for_output(Some("DP-2"), Some("Dell U2720Q"));
+        assert_eq!(dp2.render.mode, RenderMode::Voronoi);
+        assert_eq!(dp2.gradient.lightness, 0.6);
+        assert!(!dp2.render.dither);
+
+        // patterns never match outputs that don't say their name
+        let unknown = config.for_output(None, None);
+        assert_eq!(unknown.render.mode, RenderMode::Gradient);
+        assert_eq!(unknown.gradient.lightness, 0.7);
+        assert_eq!(unknown.gradient.chroma, 0.8);
+    }
+
+    #[test]
+    fn shows_output_filters_by_name() {
+        let mut config = Config::default();
+        // no filter shows everything, even outputs without a name
+        assert!(config.shows_output(Some("DP-1")));
+        assert!(config.shows_output(None));
+
+        config.render.only_outputs = vec!["DP-*".into(), "eDP-1".into()];
+        assert!(config.shows_output(Some("DP-1")));
+        assert!(config.shows_output(Some("eDP-1")));
+        assert!(!config.shows_output(Some("HDMI-A-1")));
+        assert!(!config.shows_output(None));
+    }
+}
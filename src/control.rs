@@ -0,0 +1,103 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use eyre::{Context, Result, bail, eyre};
+
+/// How long a connection gets to send its command before it's dropped, the event loop waits
+/// for it meanwhile.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// What another process asks the running instance to do, with `--send`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Command {
+    /// Show the surfaces if they are hidden with `surface.toggle`, hide them otherwise.
+    Toggle,
+    /// Reply with what's going on as JSON, like the apps, outputs and recent launches.
+    Status,
+}
+
+/// One socket per Wayland display, so instances on different displays don't get in each
+/// other's way.
+fn socket_path() -> Result<PathBuf> {
+    let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty())
+    else {
+        bail!("$XDG_RUNTIME_DIR isn't set");
+    };
+    let display = std::env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".into());
+    // it can also be an absolute path to the socket
+    let display = Path::new(&display).file_name().map_or_else(
+        || display.clone(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    Ok(Path::new(&runtime_dir).join(format!("colouncher-{display}.sock")))
+}
+
+/// Listens for commands, replacing the socket an instance that's gone left behind.
+pub fn listen() -> Result<UnixListener> {
+    let path = socket_path()?;
+    if UnixStream::connect(&path).is_ok() {
+        bail!(
+            "another instance is already listening on {}",
+            path.display()
+        );
+    }
+    match std::fs::remove_file(&path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            return Err(err).wrap_err_with(|| format!("removing {}", path.display()));
+        }
+        _ => {}
+    }
+    let listener =
+        UnixListener::bind(&path).wrap_err_with(|| format!("binding {}", path.display()))?;
+    listener
+        .set_nonblocking(true)
+        .wrap_err("making the control socket non-blocking")?;
+    Ok(listener)
+}
+
+/// Reads the command a connection sends, a single line with its name.
+pub fn read_command(stream: &UnixStream) -> Result<Command> {
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .wrap_err("setting the read timeout")?;
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .wrap_err("reading the command")?;
+    Command::from_str(line.trim(), false).map_err(|err| eyre!("unknown command: {err}"))
+}
+
+/// Sends the command to the running instance and returns its reply.
+pub fn send(command: Command) -> Result<String> {
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path).wrap_err_with(|| {
+        format!(
+            "connecting to {}, is colouncher running on this display?",
+            path.display()
+        )
+    })?;
+    let name = command
+        .to_possible_value()
+        .expect("no command is skipped")
+        .get_name()
+        .to_owned();
+    writeln!(stream, "{name}").wrap_err("sending the command")?;
+    stream
+        .shutdown(Shutdown::Write)
+        .wrap_err("sending the command")?;
+
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .wrap_err("reading the reply")?;
+    match reply.strip_prefix("error: ") {
+        Some(err) => bail!("{}", err.trim()),
+        None => Ok(reply),
+    }
+}
@@ -0,0 +1,182 @@
+use image::{Rgba, RgbaImage};
+use palette::{Oklab, color_difference::EuclideanDistance};
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use crate::{color, desktop::DesktopEntry, text};
+
+/// How many of the apps nearest to the color scrolling goes through.
+pub const CANDIDATES: usize = 5;
+/// Pointing at a color further away than this from where scrolling started starts over.
+const RESET_DISTANCE: f32 = 0.03;
+
+const PADDING: u32 = 8;
+const SWATCH_SIZE: u32 = text::GLYPH_SIZE;
+const ROW_HEIGHT: u32 = text::GLYPH_SIZE + PADDING;
+/// Keeps the list from covering the pointer.
+const CURSOR_OFFSET: u32 = 16;
+
+const BACKGROUND: Rgba<u8> = Rgba([30, 30, 30, 230]);
+const SELECTED_BACKGROUND: Rgba<u8> = Rgba([80, 80, 80, 240]);
+const TEXT_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Scrolling over the wallpaper to pick one of the apps with a similar color, for when the
+/// closest app isn't the one you're looking for.
+pub struct ScrollCycle {
+    pub surface: WlSurface,
+    /// Top left corner in surface coordinates.
+    pub origin: (u32, u32),
+    /// The color that was pointed at when scrolling started.
+    color: Oklab,
+    /// The id, name and color of the nearest apps, nearest first.
+    items: Vec<(String, String, Oklab)>,
+    selected: usize,
+    /// Scrolling that didn't add up to a whole step yet, in 120ths of a step.
+    remainder_120: i32,
+}
+
+impl ScrollCycle {
+    /// Starts at the first app, next to the cursor and moved so it stays on the surface.
+    pub fn new(
+        surface: WlSurface,
+        entries: Vec<&DesktopEntry>,
+        color: Oklab,
+        cursor: (u32, u32),
+        surface_size: (u32, u32),
+    ) -> Self {
+        let mut cycle = Self {
+            surface,
+            origin: (0, 0),
+            color,
+            items: entries
+                .into_iter()
+                .map(|entry| {
+                    (
+                        entry.id.clone(),
+                        entry.file.entry.name.default.clone(),
+                        entry.avg_icon_color,
+                    )
+                })
+                .collect(),
+            selected: 0,
+            remainder_120: 0,
+        };
+        let (width, height) = cycle.size();
+        cycle.origin = (
+            (cursor.0 + CURSOR_OFFSET).min(surface_size.0.saturating_sub(width)),
+            (cursor.1 + CURSOR_OFFSET).min(surface_size.1.saturating_sub(height)),
+        );
+        cycle
+    }
+
+    /// Whether the color is still close enough to the one scrolling started at.
+    pub fn is_near(&self, color: Oklab) -> bool {
+        self.color.distance_squared(color) <= RESET_DISTANCE * RESET_DISTANCE
+    }
+
+    /// Moves the selection by the scrolled amount, wrapping around at the ends.
+    pub fn scroll(&mut self, amount_120: i32) {
+        self.selected = scroll_selection(
+            self.selected,
+            self.items.len(),
+            &mut self.remainder_120,
+            amount_120,
+        );
+    }
+
+    /// The id of the selected app.
+    pub fn selected(&self) -> Option<&str> {
+        self.items.get(self.selected).map(|(id, _, _)| id.as_str())
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        let text_width = self
+            .items
+            .iter()
+            .map(|(_, name, _)| text::text_width(name))
+            .max()
+            .unwrap_or(0);
+        (
+            PADDING + SWATCH_SIZE + PADDING + text_width + PADDING,
+            self.items.len() as u32 * ROW_HEIGHT + PADDING,
+        )
+    }
+
+    pub fn render(&self) -> RgbaImage {
+        let (width, height) = self.size();
+        let mut image = RgbaImage::from_pixel(width, height, BACKGROUND);
+
+        for (i, (_, name, color)) in self.items.iter().enumerate() {
+            let row_y = PADDING / 2 + i as u32 * ROW_HEIGHT;
+
+            if self.selected == i {
+                for y in row_y..row_y + ROW_HEIGHT {
+                    for x in 0..width {
+                        image.put_pixel(x, y, SELECTED_BACKGROUND);
+                    }
+                }
+            }
+
+            let content_y = row_y + PADDING / 2;
+            let swatch = color::to_srgb8(*color);
+            let swatch = Rgba([swatch.red, swatch.green, swatch.blue, 255]);
+            for y in content_y..content_y + SWATCH_SIZE {
+                for x in PADDING..PADDING + SWATCH_SIZE {
+                    image.put_pixel(x, y, swatch);
+                }
+            }
+
+            text::draw_text(
+                &mut image,
+                PADDING + SWATCH_SIZE + PADDING,
+                content_y,
+                name,
+                TEXT_COLOR,
+            );
+        }
+
+        image
+    }
+}
+
+/// The selection of `len` items after scrolling, keeping what doesn't add up to a whole step
+/// in `remainder_120`.
+fn scroll_selection(
+    selected: usize,
+    len: usize,
+    remainder_120: &mut i32,
+    amount_120: i32,
+) -> usize {
+    *remainder_120 += amount_120;
+    let steps = *remainder_120 / 120;
+    *remainder_120 %= 120;
+    if len == 0 {
+        return selected;
+    }
+    (selected as i64 + steps as i64).rem_euclid(len as i64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scroll_selection_wraps_around() {
+        let mut remainder = 0;
+        assert_eq!(scroll_selection(0, 5, &mut remainder, 120), 1);
+        assert_eq!(scroll_selection(4, 5, &mut remainder, 240), 1);
+        assert_eq!(scroll_selection(0, 5, &mut remainder, -120), 4);
+        assert_eq!(remainder, 0);
+    }
+
+    #[test]
+    fn scroll_selection_adds_up_partial_steps() {
+        let mut remainder = 0;
+        assert_eq!(scroll_selection(2, 5, &mut remainder, 60), 2);
+        assert_eq!(remainder, 60);
+        assert_eq!(scroll_selection(2, 5, &mut remainder, 90), 3);
+        assert_eq!(remainder, 30);
+        assert_eq!(scroll_selection(3, 5, &mut remainder, -90), 3);
+        assert_eq!(remainder, -60);
+        assert_eq!(scroll_selection(3, 0, &mut remainder, -60), 3);
+    }
+}
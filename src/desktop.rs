@@ -1,60 +1,365 @@
-use eyre::{Context, Result};
-use freedesktop_file_parser::{DesktopFile, EntryType};
-use palette::{IntoColor, Oklab, Oklaba, color_difference::EuclideanDistance};
-use std::{collections::HashMap, ffi::OsStr, fs::DirEntry, path::Path};
+use eyre::{Context, Result, bail};
+use freedesktop_file_parser::{DesktopFile, EntryType, IconString};
+use image::{ImageDecoder, imageops::FilterType};
+use log::{debug, warn};
+use palette::{
+    IntoColor, LinSrgba, Oklab, Oklaba, Oklch, Srgba, color_difference::EuclideanDistance,
+};
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs::DirEntry,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    color,
+    config::{
+        CategoryConfig, Config, DuplicateStrategy, ExcludeRule, IconBackground, IconConfig,
+        MatchingConfig, glob_match,
+    },
+    icon_cache::{IconCache, ProcessedEntry},
+};
+
+/// The width and height of icon thumbnails.
+pub const THUMBNAIL_SIZE: u32 = 64;
+/// The size of themed icons to look for at scale 1.
+const ICON_SIZE: u16 = 48;
 
 pub struct DesktopEntries {
     entries: Vec<DesktopEntry>,
+    /// How the closest entry to a color is found.
+    matching: MatchingConfig,
 }
 
 pub struct DesktopEntry {
-    pub _id: String,
+    pub id: String,
     pub file: DesktopFile,
+    /// `None` for apps without a usable icon, which get a fallback color.
+    pub icon_path: Option<PathBuf>,
     pub avg_icon_color: Oklab,
+    pub actions: Vec<EntryAction>,
+    /// The icon scaled down to [`THUMBNAIL_SIZE`], only kept if something needs it.
+    pub thumbnail: Option<image::RgbaImage>,
+}
+
+/// An additional way to launch an app from a `[Desktop Action]` group.
+pub struct EntryAction {
+    pub id: String,
+    pub name: String,
+    pub exec: Option<String>,
+    /// The color of the action's own icon, or of the app's icon if the action has none.
+    pub color: Oklab,
 }
 
 impl DesktopEntries {
+    /// No apps, while they are still loading.
+    pub fn empty(matching: &MatchingConfig) -> Self {
+        Self {
+            entries: Vec::new(),
+            matching: matching.clone(),
+        }
+    }
     pub fn count(&self) -> usize {
         self.entries.len()
     }
+    pub fn iter(&self) -> impl Iterator<Item = &DesktopEntry> {
+        self.entries.iter()
+    }
+    pub fn get(&self, id: &str) -> Option<&DesktopEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+    /// The entry at `index` in the order of [`Self::colors`].
+    pub fn nth(&self, index: usize) -> Option<&DesktopEntry> {
+        self.entries.get(index)
+    }
     pub fn colors(&self) -> impl Iterator<Item = Oklab> + ExactSizeIterator {
         self.entries.iter().map(|entry| entry.avg_icon_color)
     }
     pub fn find_entry(&self, color: Oklab) -> Option<&DesktopEntry> {
         self.entries
             .iter()
-            .min_by_key(|x| OrdFloat(diff_color(x.avg_icon_color, color)))
+            .min_by_key(|x| OrdFloat(self.diff_color(x.avg_icon_color, color)))
+            .filter(|entry| color::is_close_enough(entry.avg_icon_color, color, &self.matching))
+    }
+    /// The `count` entries closest to the color, closest first, leaving out the ones beyond
+    /// `matching.max_distance`.
+    pub fn nearest(&self, color: Oklab, count: usize) -> Vec<&DesktopEntry> {
+        let mut entries = self
+            .entries
+            .iter()
+            .filter(|entry| color::is_close_enough(entry.avg_icon_color, color, &self.matching))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|x| OrdFloat(self.diff_color(x.avg_icon_color, color)));
+        entries.truncate(count);
+        entries
+    }
+
+    // keep it in sync with the gpu implementation
+    pub fn diff_color(&self, icon: Oklab, color: Oklab) -> f32 {
+        color::match_distance_squared(icon, color, &self.matching)
     }
 }
 
-// keep it in sync with the gpu implementation
-fn diff_color(icon: Oklab, color: Oklab) -> f32 {
-    icon.distance_squared(color)
+impl DesktopEntry {
+    pub fn wm_class(&self) -> Option<&str> {
+        match &self.file.entry.entry_type {
+            EntryType::Application(app) => app.startup_wm_class.as_deref(),
+            _ => None,
+        }
+    }
 }
 
-fn walkdir(path: &Path, f: &mut impl FnMut(&DirEntry) -> Result<()>) -> Result<()> {
-    for entry in path.read_dir()? {
+#[cfg(test)]
+impl DesktopEntries {
+    /// The entries in the given order, for tests.
+    pub fn from_entries(entries: Vec<DesktopEntry>, matching: &MatchingConfig) -> Self {
+        Self {
+            entries,
+            matching: matching.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl DesktopEntry {
+    /// An app without an icon, parsed from the keys of its `[Desktop Entry]` group and any
+    /// groups after it, for tests.
+    pub fn for_test(id: &str, keys: &str, color: Oklab) -> Self {
+        let contents = format!("[Desktop Entry]\nType=Application\n{keys}\n");
+        Self {
+            id: id.to_owned(),
+            file: freedesktop_file_parser::parse(&contents).unwrap(),
+            icon_path: None,
+            avg_icon_color: color,
+            actions: Vec::new(),
+            thumbnail: None,
+        }
+    }
+}
+
+struct ExcludeFilter<'a> {
+    rules: Vec<(&'a ExcludeRule, Option<Regex>)>,
+}
+
+impl<'a> ExcludeFilter<'a> {
+    fn new(rules: &'a [ExcludeRule]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| match rule {
+                ExcludeRule::Name(pattern) => Regex::new(pattern)
+                    .wrap_err_with(|| format!("invalid exclude regex {pattern:?}"))
+                    .map(|regex| (rule, Some(regex))),
+                _ => Ok((rule, None)),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    fn matches(&self, entry: &DesktopEntry) -> bool {
+        self.rules.iter().any(|(rule, regex)| match rule {
+            ExcludeRule::Id(pattern) => glob_match(pattern, &entry.id),
+            ExcludeRule::Name(_) => regex
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(&entry.file.entry.name.default)),
+            ExcludeRule::WmClass(class) => entry.wm_class() == Some(class.as_str()),
+        })
+    }
+}
+
+fn walkdir(
+    path: &Path,
+    max_depth: usize,
+    f: &mut impl FnMut(&DirEntry) -> Result<()>,
+) -> Result<()> {
+    walkdir_inner(path, 0, max_depth, &mut HashSet::new(), f)
+}
+
+fn walkdir_inner(
+    path: &Path,
+    depth: usize,
+    max_depth: usize,
+    visited: &mut HashSet<PathBuf>,
+    f: &mut impl FnMut(&DirEntry) -> Result<()>,
+) -> Result<()> {
+    // symlinks to directories are followed, so make sure we don't go in circles
+    let canonical = match path.canonicalize() {
+        Ok(canonical) => canonical,
+        // one unreadable directory shouldn't take all the others with it
+        Err(err) => {
+            warn!("Skipping {}: {err}", path.display());
+            return Ok(());
+        }
+    };
+    if !visited.insert(canonical) {
+        debug!("Skipping already visited directory {}", path.display());
+        return Ok(());
+    }
+
+    let entries = match path.read_dir() {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Skipping {}: {err}", path.display());
+            return Ok(());
+        }
+    };
+    for entry in entries {
         let entry = entry?;
         f(&entry).wrap_err_with(|| format!("{}", entry.path().display()))?;
-        if entry.file_type()?.is_dir() {
-            walkdir(&entry.path(), f).wrap_err_with(|| format!("{}", path.display()))?;
+
+        let file_type = entry.file_type()?;
+        let is_dir = if file_type.is_symlink() {
+            // dangling symlinks are not our problem
+            std::fs::metadata(entry.path()).is_ok_and(|meta| meta.is_dir())
+        } else {
+            file_type.is_dir()
+        };
+        if is_dir {
+            if depth >= max_depth {
+                debug!(
+                    "Not descending into {}, maximum depth of {max_depth} reached",
+                    entry.path().display()
+                );
+                continue;
+            }
+            walkdir_inner(&entry.path(), depth + 1, max_depth, visited, f)
+                .wrap_err_with(|| format!("{}", path.display()))?;
         }
     }
     Ok(())
 }
 
-pub(crate) fn find_desktop_files() -> Result<DesktopEntries> {
+/// The data directories in order of precedence, the user's data directory first.
+fn data_dirs() -> Vec<PathBuf> {
+    data_dirs_from(
+        std::env::var_os("XDG_DATA_HOME").as_deref(),
+        std::env::var_os("XDG_DATA_DIRS").as_deref(),
+        std::env::var_os("HOME").as_deref().map(Path::new),
+    )
+}
+
+/// [`data_dirs`] from the values of `$XDG_DATA_HOME`, `$XDG_DATA_DIRS` and `$HOME`.
+// https://specifications.freedesktop.org/basedir/latest/
+fn data_dirs_from(
+    xdg_data_home: Option<&OsStr>,
+    xdg_data_dirs: Option<&OsStr>,
+    home: Option<&Path>,
+) -> Vec<PathBuf> {
+    let data_home = match xdg_data_home {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => home.map(|home| home.join(".local/share")),
+    };
+
+    // set but empty is a misconfiguration, but the spec says to treat it like unset
+    let default_data_dirs = OsStr::new("/usr/local/share/:/usr/share/");
+    let data_dirs = match xdg_data_dirs {
+        Some(dirs) if is_blank(dirs) => {
+            warn!("$XDG_DATA_DIRS is empty, using /usr/local/share/:/usr/share/ instead");
+            default_data_dirs
+        }
+        Some(dirs) => dirs,
+        None => default_data_dirs,
+    };
+
+    // Flatpak adds its exports to XDG_DATA_DIRS in a profile script, which not every session
+    // sources. The user's installation shadows the system one, like flatpak's own order.
+    let flatpak_exports = data_home
+        .iter()
+        .map(|data_home| data_home.join("flatpak/exports/share"))
+        .chain([PathBuf::from("/var/lib/flatpak/exports/share")])
+        .filter(|dir| dir.is_dir())
+        .collect::<Vec<_>>();
+
+    let mut dirs = Vec::<PathBuf>::new();
+    for dir in data_home
+        .into_iter()
+        .chain(flatpak_exports)
+        .chain(std::env::split_paths(data_dirs))
+    {
+        if !dirs.iter().any(|existing| same_dir(existing, &dir)) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+fn is_blank(value: &OsStr) -> bool {
+    value
+        .as_encoded_bytes()
+        .iter()
+        .all(|byte| byte.is_ascii_whitespace())
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    a.components().eq(b.components())
+}
+
+/// The size of themed icons to look for when the largest output has the scale, the next
+/// integer scale up for fractional ones.
+pub fn icon_size(scale_120: u32) -> u16 {
+    let scale = scale_120.div_ceil(120).clamp(1, 8);
+    ICON_SIZE * scale as u16
+}
+
+/// Finds the icon file, of the size closest to `size` if it comes from the icon theme.
+fn icon_path(icon: &IconString, size: u16) -> Option<PathBuf> {
+    let path = Path::new(&icon.content);
+    if path.is_absolute() {
+        return path.is_file().then(|| path.to_owned());
+    }
+    freedesktop_icons::lookup(&icon.content)
+        .with_size(size)
+        .with_cache()
+        .find()
+}
+
+/// Looks for an icon in the `hicolor` theme of a data dir, largest first. Icons exported by
+/// Flatpak are only found by the regular lookup if the export is in `XDG_DATA_DIRS`.
+fn exported_icon(data_dir: &Path, name: &str) -> Option<PathBuf> {
+    ["512x512", "256x256", "128x128", "96x96", "64x64", "48x48"]
+        .iter()
+        .map(|size| {
+            data_dir
+                .join("icons/hicolor")
+                .join(size)
+                .join("apps")
+                .join(format!("{name}.png"))
+        })
+        .find(|path| path.is_file())
+}
+
+/// `scale_120` is the largest scale of the outputs in 120ths, themed icons are looked up for
+/// it.
+pub(crate) fn find_desktop_files(config: &Config, scale_120: u32) -> Result<DesktopEntries> {
+    let icon_size = icon_size(scale_120);
     // https://specifications.freedesktop.org/desktop-entry/latest/file-naming.html
-    let paths = std::env::var("XDG_DATA_DIRS").unwrap_or("/usr/local/share/:/usr/share/".into());
-    let paths = std::env::split_paths(&paths);
+    // The first entry with an id wins, so user entries shadow system entries.
+    let mut seen_ids = HashSet::new();
     let mut results = HashMap::new();
+    let mut cache = IconCache::load(config, icon_size);
 
-    for data_dir in paths {
-        let base = data_dir.join("applications");
+    let xdg_dirs = if config.discovery.only_app_dirs {
+        Vec::new()
+    } else {
+        data_dirs()
+            .into_iter()
+            .map(|data_dir| data_dir.join("applications"))
+            .collect()
+    };
+    for base in config.discovery.app_dirs.iter().cloned().chain(xdg_dirs) {
         if !base.try_exists()? {
+            if config.discovery.app_dirs.contains(&base) {
+                warn!(
+                    "discovery.app_dirs contains {}, which doesn't exist",
+                    base.display()
+                );
+            }
             continue;
         }
-        walkdir(&base, &mut |file| {
+        // the exported icons of flatpak are next to its applications
+        let data_dir = base.parent().unwrap_or(&base).to_path_buf();
+        walkdir(&base, config.discovery.max_depth, &mut |file| {
             if file.path().extension() != Some(OsStr::new("desktop")) {
                 return Ok(());
             }
@@ -66,30 +371,80 @@ pub(crate) fn find_desktop_files() -> Result<DesktopEntries> {
                 .unwrap()
                 .replace('/', "-");
 
+            // a hidden entry still shadows entries with the same id further down
+            if !seen_ids.insert(id.clone()) {
+                return Ok(());
+            }
+
+            let cached = cache.get_entry(&path);
+            if cached.as_ref().is_some_and(|cached| !cached.shown) {
+                return Ok(());
+            }
+
             let contents = std::fs::read_to_string(&path)?;
 
             let file =
                 freedesktop_file_parser::parse(&contents).wrap_err("parsing .desktop file")?;
 
-            if !results.contains_key(&id)
-                && file.entry.no_display != Some(true)
+            if file.entry.no_display != Some(true)
                 && file.entry.hidden != Some(true)
                 && let EntryType::Application(_) = file.entry.entry_type
-                && let Some(icon) = &file.entry.icon
-                && let Some(icon) = icon.get_icon_path()
-                && icon.extension() != Some(OsStr::new("svg"))
             {
-                let icon: image::DynamicImage = image::ImageReader::open(&icon)
-                    .wrap_err_with(|| format!("{}", icon.display()))?
-                    .decode()
-                    .wrap_err_with(|| format!("decoding {}", icon.display()))?;
-                let color = average_color(&icon);
+                // the icon might have been uninstalled since, then it's looked up again
+                let mut icon_path = cached
+                    .and_then(|cached| cached.icon_path)
+                    .filter(|path| path.is_file())
+                    .or_else(|| {
+                        file.entry.icon.as_ref().and_then(|icon| {
+                            icon_path(icon, icon_size)
+                                .filter(|path| path.extension() != Some(OsStr::new("svg")))
+                                .or_else(|| exported_icon(&data_dir, &icon.content))
+                        })
+                    });
+                cache.insert_entry(
+                    &path,
+                    ProcessedEntry {
+                        shown: true,
+                        icon_path: icon_path.clone(),
+                    },
+                );
+                let processed = icon_path.as_ref().and_then(|icon_path| {
+                    let keep_thumbnail = config.icons.keep_thumbnails();
+                    match process_icon(icon_path, &config.icons, &mut cache, keep_thumbnail) {
+                        Ok(processed) => Some(processed),
+                        // one broken icon shouldn't take all the others with it
+                        Err(err) => {
+                            warn!("Ignoring the icon of {id}: {err:?}");
+                            None
+                        }
+                    }
+                });
+                if processed.is_none() {
+                    icon_path = None;
+                }
+                let Some((color, thumbnail)) =
+                    icon_or_fallback(&id, processed, config.icons.fallback_color)
+                else {
+                    return Ok(());
+                };
+                let actions = entry_actions(&file, color, config, icon_size, &mut cache);
                 results.insert(
                     id.clone(),
                     DesktopEntry {
-                        _id: id,
+                        id,
                         file,
+                        icon_path,
                         avg_icon_color: color,
+                        actions,
+                        thumbnail,
+                    },
+                );
+            } else {
+                cache.insert_entry(
+                    &path,
+                    ProcessedEntry {
+                        shown: false,
+                        icon_path: None,
                     },
                 );
             }
@@ -98,32 +453,363 @@ pub(crate) fn find_desktop_files() -> Result<DesktopEntries> {
         })
         .wrap_err_with(|| format!("{}", base.display()))?;
     }
+    cache.save();
 
     let mut entries = results.into_values().collect::<Vec<_>>();
 
+    let exclude = ExcludeFilter::new(&config.discovery.exclude)?;
+    entries.retain(|entry| {
+        let excluded = exclude.matches(entry);
+        if excluded {
+            debug!("Excluding {}", entry.id);
+        }
+        !excluded
+    });
+
+    if config.discovery.dedup_wm_class {
+        dedup_wm_class(&mut entries);
+    }
+
+    if config.categories.bias > 0.0 {
+        bias_toward_categories(&mut entries, &config.categories);
+    }
+    if config.matching.duplicates == DuplicateStrategy::Jitter {
+        jitter_duplicates(&mut entries, config.matching.duplicate_distance);
+    }
+
     entries.sort_by_key(|entry| {
         let (l, a, b) = entry.avg_icon_color.into_components();
         (OrdFloat(a), OrdFloat(b), OrdFloat(l))
     });
 
-    Ok(DesktopEntries { entries })
+    Ok(DesktopEntries {
+        entries,
+        matching: config.matching.clone(),
+    })
 }
 
-fn average_color(image: &image::DynamicImage) -> palette::Oklab {
-    use palette::cast::FromComponents;
+/// Keeps one entry of those with the same `StartupWMClass`, preferring ones with an icon over
+/// ones with a fallback color, and the first by id among those.
+fn dedup_wm_class(entries: &mut Vec<DesktopEntry>) {
+    // sort first so the same entry survives every time
+    entries.sort_by(|a, b| (a.icon_path.is_none(), &a.id).cmp(&(b.icon_path.is_none(), &b.id)));
+    let mut seen_classes = HashSet::new();
+    entries.retain(|entry| match entry.wm_class() {
+        Some(class) => {
+            let keep = seen_classes.insert(class.to_owned());
+            if !keep {
+                debug!(
+                    "Dropping {}, its WM class {class} is already taken",
+                    entry.id
+                );
+            }
+            keep
+        }
+        None => true,
+    });
+}
+
+/// The `[Desktop Action]` groups of the app, sorted by id. They are offered in the menu of the
+/// app's cell rather than as cells of their own: they mostly share the app's color, so their
+/// cells would split the app's cell between it and its actions.
+fn entry_actions(
+    file: &DesktopFile,
+    app_color: Oklab,
+    config: &Config,
+    icon_size: u16,
+    cache: &mut IconCache,
+) -> Vec<EntryAction> {
+    let mut actions = file
+        .actions
+        .iter()
+        .map(|(id, action)| {
+            let color = action
+                .icon
+                .as_ref()
+                .and_then(|icon| icon_path(icon, icon_size))
+                .filter(|path| path.extension() != Some(OsStr::new("svg")))
+                .and_then(
+                    |path| match process_icon(&path, &config.icons, cache, false) {
+                        Ok((color, _)) => Some(color),
+                        Err(err) => {
+                            debug!("Failed to load action icon, using the app icon: {err:?}");
+                            None
+                        }
+                    },
+                )
+                .unwrap_or(app_color);
+            EntryAction {
+                id: id.clone(),
+                name: action.name.default.clone(),
+                exec: action.exec.clone(),
+                color,
+            }
+        })
+        .collect::<Vec<_>>();
+    actions.sort_by(|a, b| a.id.cmp(&b.id));
+    actions
+}
+
+/// The color and thumbnail of the processed icon, or the fallback color if there is no
+/// usable icon and `fallback` is on. `None` leaves the entry out.
+fn icon_or_fallback(
+    id: &str,
+    processed: Option<(Oklab, Option<image::RgbaImage>)>,
+    fallback: bool,
+) -> Option<(Oklab, Option<image::RgbaImage>)> {
+    match processed {
+        Some(processed) => Some(processed),
+        None if fallback => {
+            debug!("No usable icon for {id}, using a fallback color");
+            Some((fallback_color(id), None))
+        }
+        None => None,
+    }
+}
+
+/// A muted color that only depends on the desktop file id, so it's the same on every run.
+/// The low chroma keeps it apart from the colors of actual icons.
+fn fallback_color(id: &str) -> Oklab {
+    let hue = (stable_hash(id) % 360) as f32;
+    Oklch::new(0.6, 0.05, hue).into_color()
+}
 
+/// FNV-1a, unlike std's hashers it's guaranteed to never change.
+fn stable_hash(id: &str) -> u64 {
+    id.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Turns the hue of every app with a configured category toward the category's hue, keeping
+/// its lightness and chroma.
+fn bias_toward_categories(entries: &mut [DesktopEntry], config: &CategoryConfig) {
+    for entry in entries {
+        let Some((category, hue)) = main_category(entry, config) else {
+            continue;
+        };
+        let color: Oklch = entry.avg_icon_color.into_color();
+        // the shorter way around the circle
+        let difference =
+            (hue - color.hue.into_positive_degrees() + 180.0).rem_euclid(360.0) - 180.0;
+        let biased = Oklch::new(
+            color.l,
+            color.chroma,
+            color.hue.into_positive_degrees() + difference * config.bias,
+        );
+        debug!("Turning the color of {} toward {category}", entry.id);
+        entry.avg_icon_color = biased.into_color();
+    }
+}
+
+/// The first of the app's `Categories` that has a hue, and that hue.
+fn main_category<'a>(entry: &'a DesktopEntry, config: &CategoryConfig) -> Option<(&'a str, f32)> {
+    let EntryType::Application(app) = &entry.file.entry.entry_type else {
+        return None;
+    };
+    app.categories
+        .iter()
+        .flatten()
+        .find_map(|category| Some((category.as_str(), *config.hues.get(category)?)))
+}
+
+/// Moves every color that is closer than `distance` to one before it to exactly `distance`
+/// away from that one, so both can be clicked. The direction only depends on the id, so the
+/// colors end up in the same place every run.
+fn jitter_duplicates(entries: &mut [DesktopEntry], distance: f32) {
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+    for i in 1..entries.len() {
+        let (earlier, rest) = entries.split_at_mut(i);
+        let entry = &mut rest[0];
+        let hash = stable_hash(&entry.id);
+        // moving away from one color might move it close to another, so try a few directions
+        for attempt in 0..8 {
+            let Some(close) = earlier.iter().find(|other| {
+                other.avg_icon_color.distance_squared(entry.avg_icon_color) < distance * distance
+            }) else {
+                break;
+            };
+            let angle = ((hash + attempt * 45) % 360) as f32;
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let close = close.avg_icon_color;
+            entry.avg_icon_color =
+                Oklab::new(close.l, close.a + distance * cos, close.b + distance * sin);
+            debug!(
+                "Moving the color of {} away from a near duplicate",
+                entry.id
+            );
+        }
+    }
+}
+
+/// The average color of the icon and, if asked for, its thumbnail. The icon is only decoded
+/// if its color isn't cached yet or the thumbnail is needed.
+fn process_icon(
+    path: &Path,
+    config: &IconConfig,
+    cache: &mut IconCache,
+    keep_thumbnail: bool,
+) -> Result<(Oklab, Option<image::RgbaImage>)> {
+    if !keep_thumbnail && let Some(color) = cache.get(path) {
+        return Ok((color, None));
+    }
+    let icon = load_icon(path)?;
+    let Some(color) = average_color(&icon, config) else {
+        bail!(
+            "no pixel of {} is opaque enough to count, see icons.alpha_cutoff",
+            path.display()
+        );
+    };
+    cache.insert(path, color);
+    Ok((color, keep_thumbnail.then(|| thumbnail(&icon))))
+}
+
+/// Multi-size ICO files are decoded as their largest entry and animated images as their
+/// first frame, which gives the most representative colors. Both are what image's decoders
+/// do on their own, the tests make sure it stays that way. Display P3 icons are converted to
+/// sRGB.
+fn load_icon(path: &Path) -> Result<image::DynamicImage> {
+    // left behind by interrupted package installs, the decoder's error for them is cryptic
+    if std::fs::metadata(path).is_ok_and(|metadata| metadata.len() == 0) {
+        bail!("{} is empty", path.display());
+    }
+    let mut decoder = image::ImageReader::open(path)
+        .wrap_err_with(|| format!("{}", path.display()))?
+        .with_guessed_format()
+        .wrap_err_with(|| format!("{}", path.display()))?
+        .into_decoder()
+        .wrap_err_with(|| format!("decoding {}", path.display()))?;
+    let profile = decoder.icc_profile().ok().flatten();
+    let icon = image::DynamicImage::from_decoder(decoder)
+        .wrap_err_with(|| format!("decoding {}", path.display()))?;
+
+    match profile {
+        Some(profile) if is_display_p3(&profile) => {
+            debug!("Converting {} from Display P3", path.display());
+            let mut icon = icon.to_rgba8();
+            for pixel in icon.pixels_mut() {
+                let [r, g, b, a] = pixel.0;
+                let [r, g, b] = color::display_p3_to_srgb8([r, g, b]);
+                pixel.0 = [r, g, b, a];
+            }
+            Ok(icon.into())
+        }
+        // there is no color management, everything else is taken as sRGB
+        Some(_) => {
+            debug!("Treating the color profile of {} as sRGB", path.display());
+            Ok(icon)
+        }
+        None => Ok(icon),
+    }
+}
+
+/// Whether the ICC profile's description names Display P3, which is what wide-gamut icons
+/// practically always use. It's ASCII in version 2 profiles and UTF-16 in version 4 ones.
+fn is_display_p3(profile: &[u8]) -> bool {
+    let contains = |needle: &[u8]| profile.windows(needle.len()).any(|window| window == needle);
+    contains(b"Display P3") || contains(b"\0D\0i\0s\0p\0l\0a\0y\0 \0P\03")
+}
+
+/// Small enough to keep around for every app, big enough to draw at [`THUMBNAIL_SIZE`].
+/// Non-square icons are centered on a transparent square instead of being stretched.
+fn thumbnail(icon: &image::DynamicImage) -> image::RgbaImage {
+    let scaled = icon
+        .resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle)
+        .to_rgba8();
+    if scaled.width() == THUMBNAIL_SIZE && scaled.height() == THUMBNAIL_SIZE {
+        return scaled;
+    }
+    let mut square = image::RgbaImage::new(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    image::imageops::replace(
+        &mut square,
+        &scaled,
+        i64::from((THUMBNAIL_SIZE - scaled.width()) / 2),
+        i64::from((THUMBNAIL_SIZE - scaled.height()) / 2),
+    );
+    square
+}
+
+/// `None` if no pixel counts, like for fully transparent icons.
+fn average_color(image: &image::DynamicImage, config: &IconConfig) -> Option<Oklab> {
+    let image = image.to_rgba8();
+    match config.average_size {
+        Some(size) if image.width() > size || image.height() > size => {
+            average_downscaled(&image, size, config)
+        }
+        _ => {
+            let colors = image.enumerate_pixels().map(|(x, y, pixel)| {
+                let [r, g, b, a] = pixel.0;
+                let color = composite(Srgba::new(r, g, b, a).into_linear(), config.background);
+                (x, y, color.into_color())
+            });
+            weighted_average(colors, image.dimensions(), config)
+        }
+    }
+}
+
+/// Scales in linear light with premultiplied alpha, so edges don't get darker and transparent
+/// pixels don't bleed their (meaningless) color into their neighbors.
+fn average_downscaled(image: &image::RgbaImage, size: u32, config: &IconConfig) -> Option<Oklab> {
+    let linear = image::Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let color: LinSrgba = Srgba::new(r, g, b, a).into_linear();
+        image::Rgba([
+            color.red * color.alpha,
+            color.green * color.alpha,
+            color.blue * color.alpha,
+            color.alpha,
+        ])
+    });
+
+    // keeps the aspect ratio, so the center weighting stays round
+    let scale = size as f32 / image.width().max(image.height()) as f32;
+    let (width, height) = (
+        ((image.width() as f32 * scale).round() as u32).max(1),
+        ((image.height() as f32 * scale).round() as u32).max(1),
+    );
+    let small = image::imageops::resize(&linear, width, height, FilterType::Triangle);
+
+    let colors = small.enumerate_pixels().map(|(x, y, pixel)| {
+        let [r, g, b, a] = pixel.0;
+        let color = if a <= 0.0 {
+            LinSrgba::new(0.0, 0.0, 0.0, 0.0)
+        } else {
+            LinSrgba::new(r / a, g / a, b / a, a)
+        };
+        (x, y, composite(color, config.background).into_color())
+    });
+    weighted_average(colors, small.dimensions(), config)
+}
+
+/// The pixel blended over the background in linear light, which leaves it opaque.
+fn composite(color: LinSrgba, background: IconBackground) -> LinSrgba {
+    let background = match background {
+        IconBackground::None => return color,
+        IconBackground::White => 1.0,
+        IconBackground::Black => 0.0,
+    };
+    let blend = |component: f32| component * color.alpha + background * (1.0 - color.alpha);
+    LinSrgba::new(blend(color.red), blend(color.green), blend(color.blue), 1.0)
+}
+
+/// Averages the colors of the pixels weighted by their alpha and distance from the center,
+/// ignoring ones below the alpha cutoff. `None` if that leaves nothing to average.
+fn weighted_average(
+    pixels: impl Iterator<Item = (u32, u32, Oklaba)>,
+    (width, height): (u32, u32),
+    config: &IconConfig,
+) -> Option<Oklab> {
     let mut total_l = 0.0;
     let mut total_a = 0.0;
     let mut total_b = 0.0;
 
-    let image = image.to_rgba8();
-    let pixels = <&[palette::Srgba<u8>]>::from_components(&*image);
-
     let mut count = 0.0;
-    for pixel in pixels {
-        let color: Oklaba = pixel.into_linear().into_color();
+    for (x, y, color) in pixels {
+        if color.alpha < config.alpha_cutoff {
+            continue;
+        }
 
-        let weight = color.alpha;
+        let weight = color.alpha * center_weight(x, y, width, height, config.center_weight);
         total_l += color.l * weight;
         total_a += color.a * weight;
         total_b += color.b * weight;
@@ -131,11 +817,30 @@ fn average_color(image: &image::DynamicImage) -> palette::Oklab {
         count += weight;
     }
 
-    Oklab {
+    // the center weight of far out pixels can underflow to 0 too
+    if count <= 0.0 {
+        return None;
+    }
+    Some(Oklab {
         l: total_l / count,
         a: total_a / count,
         b: total_b / count,
+    })
+}
+
+/// A gaussian falloff from the center, so (often white or transparent) corners of
+/// rounded-square icons count less than the logo in the middle. 0 weighs everything the same.
+fn center_weight(x: u32, y: u32, width: u32, height: u32, strength: f32) -> f32 {
+    if strength == 0.0 {
+        return 1.0;
     }
+    // relative to the longer side, so the falloff is round for non-square icons too
+    let extent = width.max(height) as f32;
+    let dx = (x as f32 + 0.5 - width as f32 / 2.0) / extent;
+    let dy = (y as f32 + 0.5 - height as f32 / 2.0) / extent;
+    // 0 in the center, 1 in the corners
+    let distance_squared = (dx * dx + dy * dy) * 2.0;
+    (-strength * distance_squared).exp()
 }
 
 struct OrdFloat(f32);
@@ -156,3 +861,534 @@ impl Ord for OrdFloat {
         self.0.total_cmp(&other.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn icon_config(alpha_cutoff: f32) -> IconConfig {
+        IconConfig {
+            alpha_cutoff,
+            ..IconConfig::default()
+        }
+    }
+
+    #[test]
+    fn weighted_average_weighs_by_alpha() {
+        let pixels = [
+            (0, 0, Oklaba::new(0.2, 0.1, 0.0, 1.0)),
+            (1, 0, Oklaba::new(0.8, -0.1, 0.0, 0.5)),
+        ];
+        let average = weighted_average(pixels.into_iter(), (2, 1), &icon_config(0.0)).unwrap();
+        assert!((average.l - 0.4).abs() < 1e-6);
+        assert!((average.a - 0.1 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn weighted_average_of_nothing_is_none() {
+        let transparent = [(0, 0, Oklaba::new(0.5, 0.0, 0.0, 0.0))];
+        assert!(weighted_average(transparent.into_iter(), (1, 1), &icon_config(0.0)).is_none());
+        let opaque = [(0, 0, Oklaba::new(0.5, 0.0, 0.0, 1.0))];
+        // a cutoff above 1 leaves out even opaque pixels
+        assert!(weighted_average(opaque.into_iter(), (1, 1), &icon_config(1.5)).is_none());
+    }
+
+    #[test]
+    fn center_weight_falls_off_toward_the_corners() {
+        assert_eq!(center_weight(0, 0, 16, 16, 0.0), 1.0);
+        let center = center_weight(8, 8, 16, 16, 2.0);
+        let edge = center_weight(0, 8, 16, 16, 2.0);
+        let corner = center_weight(0, 0, 16, 16, 2.0);
+        assert!(center > edge && edge > corner);
+        assert!(center <= 1.0 && corner > 0.0);
+        // symmetric around the center
+        assert_eq!(
+            center_weight(2, 5, 16, 16, 2.0),
+            center_weight(13, 10, 16, 16, 2.0)
+        );
+    }
+
+    #[test]
+    fn stable_hash_is_fnv_1a() {
+        assert_eq!(stable_hash(""), 0xcbf29ce484222325);
+        assert_eq!(stable_hash("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(stable_hash("foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn icon_size_rounds_fractional_scales_up() {
+        assert_eq!(icon_size(120), ICON_SIZE);
+        assert_eq!(icon_size(150), ICON_SIZE * 2);
+        assert_eq!(icon_size(240), ICON_SIZE * 2);
+        assert_eq!(icon_size(0), ICON_SIZE);
+        assert_eq!(icon_size(120 * 20), ICON_SIZE * 8);
+    }
+
+    /// An empty directory for the test, left behind if it fails.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("colouncher-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The .desktop files `walkdir` finds, relative to `base`.
+    fn walk(base: &Path, max_depth: usize) -> Vec<String> {
+        let mut found = Vec::new();
+        walkdir(base, max_depth, &mut |entry| {
+            if entry.path().extension() == Some(OsStr::new("desktop")) {
+                let path = entry.path();
+                found.push(
+                    path.strip_prefix(base)
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .to_owned(),
+                );
+            }
+            Ok(())
+        })
+        .unwrap();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn walkdir_survives_symlink_cycles() {
+        let base = temp_dir("walkdir-cycle");
+        std::fs::create_dir(base.join("a")).unwrap();
+        std::fs::write(base.join("top.desktop"), "").unwrap();
+        std::fs::write(base.join("a/nested.desktop"), "").unwrap();
+        std::os::unix::fs::symlink("..", base.join("a/loop")).unwrap();
+        std::os::unix::fs::symlink("missing", base.join("dangling")).unwrap();
+
+        assert_eq!(walk(&base, 8), ["a/nested.desktop", "top.desktop"]);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn walkdir_stops_at_max_depth() {
+        let base = temp_dir("walkdir-depth");
+        std::fs::create_dir_all(base.join("a/b")).unwrap();
+        std::fs::write(base.join("top.desktop"), "").unwrap();
+        std::fs::write(base.join("a/one.desktop"), "").unwrap();
+        std::fs::write(base.join("a/b/two.desktop"), "").unwrap();
+
+        assert_eq!(walk(&base, 0), ["top.desktop"]);
+        assert_eq!(walk(&base, 1), ["a/one.desktop", "top.desktop"]);
+        assert_eq!(
+            walk(&base, 2),
+            ["a/b/two.desktop", "a/one.desktop", "top.desktop"]
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn load_icon_uses_the_largest_ico_entry() {
+        use image::{
+            ExtendedColorType, RgbaImage,
+            codecs::ico::{IcoEncoder, IcoFrame},
+        };
+
+        let small = RgbaImage::from_pixel(16, 16, image::Rgba([255, 0, 0, 255]));
+        let large = RgbaImage::from_pixel(32, 32, image::Rgba([0, 0, 255, 255]));
+        // the smaller one first, so the first entry isn't the right one by accident
+        let frames = [
+            IcoFrame::as_png(&small, 16, 16, ExtendedColorType::Rgba8).unwrap(),
+            IcoFrame::as_png(&large, 32, 32, ExtendedColorType::Rgba8).unwrap(),
+        ];
+        let mut ico = Vec::new();
+        IcoEncoder::new(&mut ico).encode_images(&frames).unwrap();
+        let dir = temp_dir("load-icon-ico");
+        let path = dir.join("icon.ico");
+        std::fs::write(&path, ico).unwrap();
+
+        let icon = load_icon(&path).unwrap();
+        assert_eq!((icon.width(), icon.height()), (32, 32));
+        assert_eq!(icon.to_rgba8().get_pixel(0, 0).0, [0, 0, 255, 255]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_icon_uses_the_first_frame_of_animations() {
+        use image::{Frame, RgbaImage, codecs::gif::GifEncoder};
+
+        let mut gif = Vec::new();
+        GifEncoder::new(&mut gif)
+            .encode_frames([
+                Frame::new(RgbaImage::from_pixel(8, 8, image::Rgba([255, 0, 0, 255]))),
+                Frame::new(RgbaImage::from_pixel(8, 8, image::Rgba([0, 0, 255, 255]))),
+            ])
+            .unwrap();
+        let dir = temp_dir("load-icon-gif");
+        // named like a png, the format is guessed from the contents
+        let path = dir.join("icon.png");
+        std::fs::write(&path, gif).unwrap();
+
+        let icon = load_icon(&path).unwrap();
+        assert_eq!(icon.to_rgba8().get_pixel(0, 0).0, [255, 0, 0, 255]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_icon_fails_on_corrupt_files() {
+        let dir = temp_dir("load-icon-corrupt");
+        let path = dir.join("icon.png");
+        std::fs::write(&path, b"\x89PNG\r\n\x1a\nnot really a png").unwrap();
+        assert!(load_icon(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_icon_fails_on_empty_files() {
+        let dir = temp_dir("load-icon-empty");
+        let path = dir.join("icon.png");
+        std::fs::write(&path, b"").unwrap();
+        let err = load_icon(&path).unwrap_err();
+        assert!(err.to_string().ends_with("is empty"), "{err}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dedup_wm_class_prefers_entries_with_an_icon() {
+        let color = Oklab::new(0.5, 0.0, 0.0);
+        let app = |id: &str, class: Option<&str>, icon: bool| {
+            let keys = match class {
+                Some(class) => format!("Name={id}\nStartupWMClass={class}"),
+                None => format!("Name={id}"),
+            };
+            let mut entry = DesktopEntry::for_test(id, &keys, color);
+            entry.icon_path = icon.then(|| PathBuf::from(format!("/icons/{id}.png")));
+            entry
+        };
+        let mut entries = vec![
+            app("a-pwa.desktop", Some("chat"), false),
+            app("b-chat.desktop", Some("chat"), true),
+            app("c-chat-beta.desktop", Some("chat"), true),
+            app("d-editor.desktop", Some("editor"), false),
+            app("e-no-class.desktop", None, false),
+            app("f-no-class.desktop", None, false),
+        ];
+        dedup_wm_class(&mut entries);
+
+        let mut ids = entries
+            .iter()
+            .map(|entry| entry.id.as_str())
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(
+            ids,
+            [
+                "b-chat.desktop",
+                "d-editor.desktop",
+                "e-no-class.desktop",
+                "f-no-class.desktop"
+            ]
+        );
+    }
+
+    #[test]
+    fn entry_actions_of_an_app_with_two_actions() {
+        let file = freedesktop_file_parser::parse(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=Firefox\n\
+             Exec=firefox %u\n\
+             Actions=new-window;new-private-window;\n\
+             \n\
+             [Desktop Action new-window]\n\
+             Name=New Window\n\
+             Exec=firefox --new-window %u\n\
+             \n\
+             [Desktop Action new-private-window]\n\
+             Name=New Private Window\n\
+             Exec=firefox --private-window %u\n",
+        )
+        .unwrap();
+        let color = Oklab::new(0.6, 0.1, 0.05);
+        let actions = entry_actions(
+            &file,
+            color,
+            &Config::default(),
+            ICON_SIZE,
+            &mut IconCache::default(),
+        );
+
+        let actions = actions
+            .iter()
+            .map(|action| {
+                assert_eq!(action.color.into_components(), color.into_components());
+                (
+                    action.id.as_str(),
+                    action.name.as_str(),
+                    action.exec.as_deref(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            [
+                (
+                    "new-private-window",
+                    "New Private Window",
+                    Some("firefox --private-window %u")
+                ),
+                ("new-window", "New Window", Some("firefox --new-window %u")),
+            ]
+        );
+    }
+
+    #[test]
+    fn average_downscaled_is_close_to_the_full_average() {
+        let gradient = image::RgbaImage::from_fn(256, 128, |x, y| {
+            let x = x as u8;
+            image::Rgba([x, 255 - x, (y * 2) as u8, 255])
+        });
+        let gradient = image::DynamicImage::from(gradient);
+        let full = average_color(&gradient, &icon_config(0.0)).unwrap();
+        let downscaled = average_color(
+            &gradient,
+            &IconConfig {
+                average_size: Some(16),
+                ..icon_config(0.0)
+            },
+        )
+        .unwrap();
+        assert!(
+            full.distance_squared(downscaled) < 0.01 * 0.01,
+            "{full:?} {downscaled:?}"
+        );
+    }
+
+    #[test]
+    fn center_weight_brings_out_the_logo() {
+        let icon = image::RgbaImage::from_fn(32, 32, |x, y| {
+            if (8..24).contains(&x) && (8..24).contains(&y) {
+                image::Rgba([220, 30, 30, 255])
+            } else {
+                image::Rgba([128, 128, 128, 255])
+            }
+        });
+        let icon = image::DynamicImage::from(icon);
+        let chroma = |center_weight| {
+            let config = IconConfig {
+                center_weight,
+                ..icon_config(0.0)
+            };
+            let color: Oklch = average_color(&icon, &config).unwrap().into_color();
+            color.chroma
+        };
+        assert!(chroma(4.0) > chroma(0.0) * 1.2);
+    }
+
+    #[test]
+    fn fallback_colors_are_stable_and_muted() {
+        let color = fallback_color("org.example.App.desktop");
+        assert_eq!(
+            color.into_components(),
+            fallback_color("org.example.App.desktop").into_components()
+        );
+        assert!(color.distance_squared(fallback_color("org.example.Other.desktop")) > 0.0);
+
+        let color: Oklch = color.into_color();
+        assert!(color.chroma < 0.06);
+    }
+
+    #[test]
+    fn icon_or_fallback_keeps_iconless_entries_only_if_asked() {
+        let icon = Oklab::new(0.5, 0.1, 0.1);
+        let (color, _) = icon_or_fallback("app.desktop", Some((icon, None)), true).unwrap();
+        assert_eq!(color.into_components(), icon.into_components());
+
+        assert!(icon_or_fallback("app.desktop", None, false).is_none());
+        let (color, thumbnail) = icon_or_fallback("app.desktop", None, true).unwrap();
+        assert_eq!(
+            color.into_components(),
+            fallback_color("app.desktop").into_components()
+        );
+        assert!(thumbnail.is_none());
+    }
+
+    #[test]
+    fn jitter_duplicates_moves_identical_colors_apart() {
+        let color = Oklab::new(0.6, 0.1, 0.05);
+        let far = Oklab::new(0.6, -0.1, -0.1);
+        let entries = || {
+            vec![
+                DesktopEntry::for_test("a.desktop", "Name=A", color),
+                DesktopEntry::for_test("b.desktop", "Name=B", color),
+                DesktopEntry::for_test("c.desktop", "Name=C", far),
+            ]
+        };
+        let colors = |entries: &[DesktopEntry]| {
+            entries
+                .iter()
+                .map(|entry| (entry.id.clone(), entry.avg_icon_color.into_components()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut jittered = entries();
+        jitter_duplicates(&mut jittered, 0.02);
+        let [a, b, c] = [0, 1, 2].map(|i| jittered[i].avg_icon_color);
+        assert!(a.distance(b) >= 0.02 - 1e-6, "{a:?} {b:?}");
+        // the first one stays, like colors that weren't close to anything
+        assert_eq!(a.into_components(), color.into_components());
+        assert_eq!(c.into_components(), far.into_components());
+
+        let mut reversed = entries();
+        reversed.reverse();
+        jitter_duplicates(&mut reversed, 0.02);
+        assert_eq!(colors(&reversed), colors(&jittered));
+    }
+
+    #[test]
+    fn thumbnail_pads_wide_icons_instead_of_stretching() {
+        let icon = image::DynamicImage::from(image::RgbaImage::from_pixel(
+            64,
+            32,
+            image::Rgba([200, 40, 40, 255]),
+        ));
+        let thumbnail = thumbnail(&icon);
+        assert_eq!(thumbnail.dimensions(), (THUMBNAIL_SIZE, THUMBNAIL_SIZE));
+        // centered, with transparent rows above and below
+        assert_eq!(thumbnail.get_pixel(32, 15).0[3], 0);
+        assert_eq!(thumbnail.get_pixel(32, 16).0, [200, 40, 40, 255]);
+        assert_eq!(thumbnail.get_pixel(32, 47).0, [200, 40, 40, 255]);
+        assert_eq!(thumbnail.get_pixel(32, 48).0[3], 0);
+
+        let config = IconConfig::default();
+        let padded = average_color(&thumbnail.into(), &config).unwrap();
+        let original = average_color(&icon, &config).unwrap();
+        assert!(padded.distance_squared(original) < 1e-6);
+    }
+
+    #[test]
+    fn find_entry_rejects_colors_beyond_the_max_distance() {
+        let matching = MatchingConfig {
+            max_distance: Some(0.1),
+            ..MatchingConfig::default()
+        };
+        let entries = DesktopEntries::from_entries(
+            vec![
+                DesktopEntry::for_test("red.desktop", "Name=Red", Oklab::new(0.6, 0.2, 0.05)),
+                DesktopEntry::for_test("blue.desktop", "Name=Blue", Oklab::new(0.5, -0.05, -0.2)),
+            ],
+            &matching,
+        );
+        let near = entries.find_entry(Oklab::new(0.6, 0.15, 0.05)).unwrap();
+        assert_eq!(near.id, "red.desktop");
+        let far = Oklab::new(0.9, -0.2, 0.2);
+        assert!(entries.find_entry(far).is_none());
+        assert!(entries.nearest(far, 2).is_empty());
+        assert_eq!(entries.nearest(Oklab::new(0.6, 0.15, 0.05), 2).len(), 1);
+    }
+
+    #[test]
+    fn data_dirs_treat_empty_like_unset() {
+        // whether flatpak is installed depends on the machine running the test
+        let data_dirs = |data_home: Option<&str>, data_dirs: Option<&str>, home: Option<&str>| {
+            let mut dirs = data_dirs_from(
+                data_home.map(OsStr::new),
+                data_dirs.map(OsStr::new),
+                home.map(Path::new),
+            );
+            dirs.retain(|dir| !dir.ends_with("flatpak/exports/share"));
+            dirs
+        };
+        let home = Some("/home/user");
+        let defaults = [
+            PathBuf::from("/home/user/.local/share"),
+            PathBuf::from("/usr/local/share/"),
+            PathBuf::from("/usr/share/"),
+        ];
+
+        assert_eq!(data_dirs(None, None, home), defaults);
+        assert_eq!(data_dirs(Some(""), Some(""), home), defaults);
+        assert_eq!(data_dirs(None, Some(" \n"), home), defaults);
+        assert_eq!(
+            data_dirs(Some("/data"), Some("/opt/share:/usr/share"), home),
+            [
+                PathBuf::from("/data"),
+                PathBuf::from("/opt/share"),
+                PathBuf::from("/usr/share"),
+            ]
+        );
+        // the data home isn't repeated
+        assert_eq!(
+            data_dirs(Some("/data"), Some("/data/:/usr/share"), home),
+            [PathBuf::from("/data"), PathBuf::from("/usr/share")]
+        );
+        assert_eq!(
+            data_dirs(None, Some("/usr/share"), None),
+            [PathBuf::from("/usr/share")]
+        );
+    }
+
+    #[test]
+    fn composite_over_white_and_black() {
+        let blue = LinSrgba::new(0.0, 0.0, 1.0, 0.5);
+        let white = composite(blue, IconBackground::White);
+        assert_eq!(white.into_components(), (0.5, 0.5, 1.0, 1.0));
+        let black = composite(blue, IconBackground::Black);
+        assert_eq!(black.into_components(), (0.0, 0.0, 0.5, 1.0));
+        // alpha-weighted averaging takes the color as it is
+        assert_eq!(
+            composite(blue, IconBackground::None).into_components(),
+            blue.into_components()
+        );
+
+        let icon = image::DynamicImage::from(image::RgbaImage::from_pixel(
+            4,
+            4,
+            image::Rgba([0, 0, 255, 128]),
+        ));
+        let average = |background| {
+            let config = IconConfig {
+                background,
+                ..icon_config(0.0)
+            };
+            average_color(&icon, &config).unwrap()
+        };
+        let (over_white, over_black) = (
+            average(IconBackground::White),
+            average(IconBackground::Black),
+        );
+        assert!(
+            over_white.l > over_black.l + 0.2,
+            "{over_white:?} {over_black:?}"
+        );
+        // both are still blue
+        assert!(over_white.b < 0.0 && over_black.b < 0.0);
+    }
+
+    #[test]
+    fn bias_toward_categories_turns_the_hue() {
+        let color: Oklab = Oklch::new(0.7, 0.1, 350.0).into_color();
+        let mut entries = vec![
+            DesktopEntry::for_test(
+                "code.desktop",
+                "Name=Code\nCategories=Utility;Development;",
+                color,
+            ),
+            DesktopEntry::for_test("other.desktop", "Name=Other\nCategories=Game;", color),
+        ];
+        let config = CategoryConfig {
+            bias: 0.5,
+            hues: HashMap::from([("Development".into(), 30.0)]),
+        };
+        bias_toward_categories(&mut entries, &config);
+
+        // halfway the shorter way around, through 0
+        let biased: Oklch = entries[0].avg_icon_color.into_color();
+        assert!(
+            (biased.hue.into_positive_degrees() - 10.0).abs() < 1e-2,
+            "{biased:?}"
+        );
+        assert!((biased.l - 0.7).abs() < 1e-4 && (biased.chroma - 0.1).abs() < 1e-4);
+        assert_eq!(
+            entries[1].avg_icon_color.into_components(),
+            color.into_components()
+        );
+    }
+}
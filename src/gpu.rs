@@ -1,28 +1,78 @@
-use std::{mem::offset_of, ptr::NonNull};
+use std::{cell::RefCell, mem::offset_of, ptr::NonNull};
 
-use eyre::{Context, Result};
+use bytemuck::Zeroable;
+use eyre::{Context, Result, eyre};
+use log::{error, warn};
 use palette::Oklab;
 use raw_window_handle::{
     RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle,
 };
-use wayland_client::{Proxy, protocol::wl_surface::WlSurface};
+use wayland_client::{
+    Proxy,
+    protocol::{wl_output::Transform, wl_surface::WlSurface},
+};
 use wgpu::util::DeviceExt;
 
+use crate::{
+    color,
+    config::{ColorMetric, Config, GradientConfig, MatchingConfig, PresentMode, RenderMode},
+    desktop::DesktopEntries,
+    sprites::{self, Sprite},
+    wallpaper::{Wallpaper, place_image},
+};
+
+/// [`SurfaceGpuState::render_to_image`] expects BGRA.
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
+
 pub struct AppGpuState {
     instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    render_pipeline: wgpu::RenderPipeline,
+    shader: wgpu::ShaderModule,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    /// One for every surface texture format, they're created when a surface first needs them.
+    render_pipelines: RefCell<Vec<(wgpu::TextureFormat, wgpu::RenderPipeline)>>,
+    pick_pipeline: wgpu::ComputePipeline,
+    pick_bind_group_layout: wgpu::BindGroupLayout,
     screen_size_bind_group_layout: wgpu::BindGroupLayout,
+    desktop_colors_bind_group_layout: wgpu::BindGroupLayout,
     desktop_colors_bind_group: wgpu::BindGroup,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    wallpaper_bind_group: wgpu::BindGroup,
+    wallpaper_size: Option<(u32, u32)>,
+    overlay_sampler: wgpu::Sampler,
+    /// Empty if there are no icon thumbnails.
+    icon_uvs: Vec<Option<([f32; 2], [f32; 2])>>,
+    /// How many colors the voronoi diagram has.
+    seed_count: u32,
+    /// What the seed match coordinates were computed with.
+    matching: MatchingConfig,
 }
 
 pub struct SurfaceGpuState {
-    surface: wgpu::Surface<'static>,
+    /// `None` when only rendering offscreen.
+    surface: Option<wgpu::Surface<'static>>,
+    /// The format of the surface textures, they're always rendered to as sRGB.
+    format: wgpu::TextureFormat,
+    render_pipeline: wgpu::RenderPipeline,
     width: u32,
     height: u32,
     input_buffer: wgpu::Buffer,
     screen_size_bind_group: wgpu::BindGroup,
+    overlay_bind_group: wgpu::BindGroup,
+    icon_sprites_buffer: wgpu::Buffer,
+    /// Buffer pixels per surface-local (logical) pixel.
+    scale: f32,
+    /// The origin and size of the overlay in logical pixels.
+    overlay_placement: Option<([f32; 2], [f32; 2])>,
+    /// How the buffer is rotated relative to the surface, see `wl_surface.set_buffer_transform`.
+    transform: Transform,
+    /// The present modes the surface supports.
+    present_modes: Vec<wgpu::PresentMode>,
+    /// The configured present mode, which might not be supported.
+    present_mode: PresentMode,
+    frame_latency: u32,
 }
 
 #[repr(C)]
@@ -30,7 +80,39 @@ pub struct SurfaceGpuState {
 struct InputUniform {
     size: [f32; 2], // width, height
     voronoi_progress: f32,
+    dither: u32,
+    lightness: f32,
+    use_image: u32,
+    image_origin: [f32; 2],
+    image_size: [f32; 2],
+    use_overlay: u32,
+    _pad: u32,
+    overlay_origin: [f32; 2],
+    overlay_size: [f32; 2],
+    gradient_offset: [f32; 3],
+    chroma: f32,
+    chroma_offset: [f32; 2],
+    transform: u32,
+    seed_count: u32,
+    grain: f32,
+    metric: u32,
+    lightness_weight: f32,
+    ripple_radius: f32,
+    ripple_center: [f32; 2],
+    ripple_alpha: f32,
+    /// 0 if there is no limit.
+    max_distance: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct IconSpriteStorage {
+    center: [f32; 2],
+    // zero if the sprite isn't drawn
+    size: f32,
     _pad: f32,
+    uv_origin: [f32; 2],
+    uv_size: [f32; 2],
 }
 
 #[repr(C)]
@@ -39,12 +121,22 @@ struct DesktopColorsStorage {
     l: f32,
     a: f32,
     b: f32,
+    /// Taken off the squared distance, so the cell is larger.
+    weight: f32,
+    /// The color in the space of the matching metric, see [`color::match_coordinates`].
+    matched: [f32; 3],
     _pad: f32,
 }
 
 impl AppGpuState {
+    /// `seed_colors` are the colors of the voronoi diagram, usually those of `desktop_files`,
+    /// and `seed_weights` how much larger their cells are.
     pub fn new(
-        desktop_colors: impl IntoIterator<Item = Oklab> + ExactSizeIterator,
+        desktop_files: &DesktopEntries,
+        seed_colors: &[Oklab],
+        seed_weights: &[f32],
+        matching: &MatchingConfig,
+        wallpaper: Option<&Wallpaper>,
     ) -> Result<Self> {
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
 
@@ -59,30 +151,83 @@ impl AppGpuState {
         let screen_size_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: None,
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                ],
             });
         let desktop_colors_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("desktop_colors_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("texture_bind_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
             });
 
         let render_pipeline_layout =
@@ -91,89 +236,399 @@ impl AppGpuState {
                 bind_group_layouts: &[
                     &screen_size_bind_group_layout,
                     &desktop_colors_bind_group_layout,
+                    &texture_bind_group_layout,
+                    &texture_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::TextureFormat::Bgra8UnormSrgb.into())],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
+        let pick_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("pick_bind_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let pick_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pick Pipeline Layout"),
+            bind_group_layouts: &[
+                &screen_size_bind_group_layout,
+                &desktop_colors_bind_group_layout,
+                &texture_bind_group_layout,
+                &pick_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let pick_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Pick Pipeline"),
+            layout: Some(&pick_pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_pick"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
             cache: None,
-            multiview: None,
         });
 
-        let desktop_colors = desktop_colors
-            .into_iter()
-            .map(|color| DesktopColorsStorage {
-                l: color.l,
-                a: color.a,
-                b: color.b,
-                _pad: 0.0,
-            })
-            .collect::<Vec<_>>();
+        let (desktop_colors_bind_group, icon_uvs, seed_count) = create_desktop_colors_bind_group(
+            &device,
+            &queue,
+            &desktop_colors_bind_group_layout,
+            desktop_files,
+            seed_colors,
+            seed_weights,
+            matching,
+        );
 
-        let desktop_colors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("desktop_colors_buffer"),
-            contents: bytemuck::cast_slice::<DesktopColorsStorage, u8>(&desktop_colors),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        // without a wallpaper, the shader still needs something to sample from
+        let (wallpaper_size, wallpaper_pixels) = match wallpaper {
+            Some(wallpaper) => (wallpaper.size(), wallpaper.image.as_raw().as_slice()),
+            None => ((1, 1), &[0, 0, 0, 255][..]),
+        };
+        let wallpaper_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("wallpaper_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
         });
+        let wallpaper_bind_group = create_texture_bind_group(
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            &wallpaper_sampler,
+            wallpaper_size,
+            wallpaper_pixels,
+            "wallpaper",
+        );
 
-        let desktop_colors_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &desktop_colors_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &desktop_colors_buffer,
-                    offset: 0,
-                    size: None,
-                }),
-            }],
-            label: Some("desktop_colors_bind_group"),
+        // overlays are drawn pixel for pixel, so there's no point in filtering
+        let overlay_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("overlay_sampler"),
+            ..Default::default()
         });
 
         Ok(Self {
             instance,
+            adapter,
             device,
             queue,
-            render_pipeline,
+            shader,
+            render_pipeline_layout,
+            render_pipelines: RefCell::new(Vec::new()),
+            pick_pipeline,
+            pick_bind_group_layout,
             screen_size_bind_group_layout,
+            desktop_colors_bind_group_layout,
             desktop_colors_bind_group,
+            texture_bind_group_layout,
+            wallpaper_bind_group,
+            wallpaper_size: wallpaper.map(Wallpaper::size),
+            overlay_sampler,
+            icon_uvs,
+            seed_count,
+            matching: matching.clone(),
         })
     }
 }
 
+impl AppGpuState {
+    /// Runs the callbacks of finished readbacks, never blocks.
+    pub fn poll(&self) {
+        if let Err(err) = self.device.poll(wgpu::PollType::Poll) {
+            error!("Failed to poll the device: {err}");
+        }
+    }
+
+    /// Replaces the apps the state was created with, for apps loaded after the surfaces
+    /// were up. The surfaces need a [`SurfaceGpuState::set_apps`] afterwards.
+    pub fn set_apps(
+        &mut self,
+        desktop_files: &DesktopEntries,
+        seed_colors: &[Oklab],
+        seed_weights: &[f32],
+    ) {
+        (
+            self.desktop_colors_bind_group,
+            self.icon_uvs,
+            self.seed_count,
+        ) = create_desktop_colors_bind_group(
+            &self.device,
+            &self.queue,
+            &self.desktop_colors_bind_group_layout,
+            desktop_files,
+            seed_colors,
+            seed_weights,
+            &self.matching,
+        );
+    }
+
+    /// The render pipeline for textures with this format, created the first time it's needed.
+    fn render_pipeline(&self, format: wgpu::TextureFormat) -> wgpu::RenderPipeline {
+        let mut pipelines = self.render_pipelines.borrow_mut();
+        if let Some((_, pipeline)) = pipelines.iter().find(|(f, _)| *f == format) {
+            return pipeline.clone();
+        }
+
+        let pipeline = self
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Render Pipeline"),
+                layout: Some(&self.render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(format.into())],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                cache: None,
+                multiview: None,
+            });
+        pipelines.push((format, pipeline.clone()));
+        pipeline
+    }
+
+    fn empty_overlay_bind_group(&self) -> wgpu::BindGroup {
+        create_texture_bind_group(
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            &self.overlay_sampler,
+            (1, 1),
+            &[0, 0, 0, 0],
+            "empty_overlay",
+        )
+    }
+}
+
+/// The bind group with everything about the apps: the seed colors of the voronoi diagram and
+/// the icon atlas. Also returns the uvs of the icons in the atlas and the number of seeds.
+fn create_desktop_colors_bind_group(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    desktop_files: &DesktopEntries,
+    seed_colors: &[Oklab],
+    seed_weights: &[f32],
+    matching: &MatchingConfig,
+) -> (wgpu::BindGroup, Vec<Option<([f32; 2], [f32; 2])>>, u32) {
+    let mut desktop_colors = seed_colors
+        .iter()
+        .enumerate()
+        .map(|(i, color)| DesktopColorsStorage {
+            l: color.l,
+            a: color.a,
+            b: color.b,
+            weight: seed_weights.get(i).copied().unwrap_or(0.0),
+            matched: color::match_coordinates(*color, matching),
+            _pad: 0.0,
+        })
+        .collect::<Vec<_>>();
+    // storage buffers can't be empty, the shader only looks at the first `seed_count`
+    let seed_count = desktop_colors.len() as u32;
+    if desktop_colors.is_empty() {
+        desktop_colors.push(DesktopColorsStorage::zeroed());
+    }
+
+    let desktop_colors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("desktop_colors_buffer"),
+        contents: bytemuck::cast_slice::<DesktopColorsStorage, u8>(&desktop_colors),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    // without icons, the shader still needs something to bind
+    let icon_atlas = sprites::build_atlas(desktop_files);
+    let (atlas_size, atlas_pixels) = match &icon_atlas {
+        Some(atlas) => (atlas.image.dimensions(), atlas.image.as_raw().as_slice()),
+        None => ((1, 1), &[0, 0, 0, 0][..]),
+    };
+    let icon_atlas_texture = create_texture(device, queue, atlas_size, atlas_pixels, "icon_atlas");
+    let icon_atlas_view = icon_atlas_texture.create_view(&Default::default());
+    let icon_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("icon_sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+
+    let desktop_colors_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &desktop_colors_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&icon_atlas_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&icon_sampler),
+            },
+        ],
+        label: Some("desktop_colors_bind_group"),
+    });
+
+    (
+        desktop_colors_bind_group,
+        icon_atlas.map(|atlas| atlas.uvs).unwrap_or_default(),
+        seed_count,
+    )
+}
+
+/// The bind group with the input uniform and the icon sprites, which has a sprite for every
+/// icon in the atlas.
+fn create_icon_sprites_bind_group(
+    gpu_state: &AppGpuState,
+    input_buffer: &wgpu::Buffer,
+) -> (wgpu::Buffer, wgpu::BindGroup) {
+    // storage buffers can't be empty, a single sprite with size zero isn't drawn either
+    let icon_sprites_buffer =
+        gpu_state
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("icon_sprites_buffer"),
+                contents: bytemuck::cast_slice(&vec![
+                    IconSpriteStorage::zeroed();
+                    gpu_state.icon_uvs.len().max(1)
+                ]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            });
+
+    let screen_size_bind_group = gpu_state
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &gpu_state.screen_size_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: input_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: icon_sprites_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("screen_size_bind_group"),
+        });
+
+    (icon_sprites_buffer, screen_size_bind_group)
+}
+
+/// Uploads sRGB RGBA pixels into a texture that can be bound with the texture bind group layout.
+fn create_texture_bind_group(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    (width, height): (u32, u32),
+    pixels: &[u8],
+    label: &str,
+) -> wgpu::BindGroup {
+    let texture = create_texture(device, queue, (width, height), pixels, label);
+    let view = texture.create_view(&Default::default());
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+        label: Some(format!("{label}_bind_group").as_str()),
+    })
+}
+
+/// Uploads sRGB RGBA pixels into a texture.
+fn create_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    (width, height): (u32, u32),
+    pixels: &[u8],
+    label: &str,
+) -> wgpu::Texture {
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some(format!("{label}_texture").as_str()),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        pixels,
+    )
+}
+
 impl SurfaceGpuState {
     pub fn new(
         gpu_state: &AppGpuState,
         wayland_backend: &wayland_backend::client::Backend,
         wl_surface: &WlSurface,
+        config: &Config,
     ) -> Result<Self> {
         let surface = unsafe {
             gpu_state
@@ -189,6 +644,31 @@ impl SurfaceGpuState {
         }
         .wrap_err("failed to create wgpu surface")?;
 
+        let capabilities = surface.get_capabilities(&gpu_state.adapter);
+        let format = select_format(&capabilities.formats)
+            .ok_or_else(|| eyre!("the GPU adapter can't render to the surface"))?;
+
+        Ok(Self::with_surface(
+            gpu_state,
+            Some(surface),
+            format,
+            capabilities.present_modes,
+            config,
+        ))
+    }
+
+    /// State for rendering into images with [`Self::render_to_image`], not shown anywhere.
+    pub fn offscreen(gpu_state: &AppGpuState, config: &Config) -> Self {
+        Self::with_surface(gpu_state, None, OFFSCREEN_FORMAT, Vec::new(), config)
+    }
+
+    fn with_surface(
+        gpu_state: &AppGpuState,
+        surface: Option<wgpu::Surface<'static>>,
+        format: wgpu::TextureFormat,
+        present_modes: Vec<wgpu::PresentMode>,
+        config: &Config,
+    ) -> Self {
         let screen_size_buffer =
             gpu_state
                 .device
@@ -197,66 +677,342 @@ impl SurfaceGpuState {
                     contents: bytemuck::bytes_of(&InputUniform {
                         size: [0.0, 0.0],
                         voronoi_progress: 0.0,
-                        _pad: 0.0,
+                        dither: config.render.dither.into(),
+                        lightness: config.gradient.lightness,
+                        use_image: 0,
+                        image_origin: [0.0, 0.0],
+                        image_size: [0.0, 0.0],
+                        use_overlay: 0,
+                        _pad: 0,
+                        overlay_origin: [0.0, 0.0],
+                        overlay_size: [0.0, 0.0],
+                        gradient_offset: [0.0, 0.0, 0.0],
+                        chroma: config.gradient.chroma,
+                        chroma_offset: config.gradient.chroma_offset,
+                        transform: Transform::Normal.into(),
+                        seed_count: gpu_state.seed_count,
+                        grain: config.render.grain,
+                        // the seeds are matched with what the app started with
+                        metric: match gpu_state.matching.metric {
+                            ColorMetric::Oklab => 0,
+                            ColorMetric::Cielab => 1,
+                            ColorMetric::WeightedOklab => 2,
+                        },
+                        lightness_weight: gpu_state.matching.lightness_weight,
+                        ripple_radius: 0.0,
+                        ripple_center: [0.0, 0.0],
+                        ripple_alpha: 0.0,
+                        max_distance: gpu_state.matching.max_distance.unwrap_or(0.0),
                     }),
                     usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 });
 
-        let screen_size_bind_group =
-            gpu_state
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &gpu_state.screen_size_bind_group_layout,
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                            buffer: &screen_size_buffer,
-                            offset: 0,
-                            size: None,
-                        }),
-                    }],
-                    label: Some("screen_size_bind_group"),
-                });
+        let (icon_sprites_buffer, screen_size_bind_group) =
+            create_icon_sprites_bind_group(gpu_state, &screen_size_buffer);
 
-        Ok(Self {
+        Self {
             surface,
+            format,
+            render_pipeline: gpu_state.render_pipeline(format.add_srgb_suffix()),
             input_buffer: screen_size_buffer,
             screen_size_bind_group,
+            overlay_bind_group: gpu_state.empty_overlay_bind_group(),
+            icon_sprites_buffer,
             width: 0,
             height: 0,
-        })
+            scale: 1.0,
+            overlay_placement: None,
+            transform: Transform::Normal,
+            present_modes,
+            present_mode: PresentMode::Auto,
+            frame_latency: config.render.frame_latency,
+        }
     }
 
-    pub fn resize(&mut self, gpu_state: &AppGpuState, width: u32, height: u32) {
+    /// `width` and `height` are in buffer pixels, `scale` is how many of them there are per
+    /// logical pixel.
+    ///
+    /// Fails if the swapchain couldn't be allocated, the surface can't be drawn until the next
+    /// successful resize then.
+    pub fn resize(
+        &mut self,
+        gpu_state: &AppGpuState,
+        width: u32,
+        height: u32,
+        scale: f32,
+        transform: Transform,
+        config: &Config,
+    ) -> Result<()> {
+        // compositors like to send the same configure many times in a row,
+        // reconfiguring the swapchain every time reallocates all its textures.
+        if self.width == width
+            && self.height == height
+            && self.scale == scale
+            && self.transform == transform
+            && self.present_mode == config.render.present_mode
+            && self.frame_latency == config.render.frame_latency
+        {
+            return Ok(());
+        }
+
         self.width = width;
         self.height = height;
+        self.scale = scale;
+        self.transform = transform;
+        if self.present_mode != config.render.present_mode {
+            if self.surface.is_some()
+                && let Some(requested) = wgpu_present_mode(config.render.present_mode)
+                && !self.present_modes.contains(&requested)
+            {
+                warn!(
+                    "The surface doesn't support the {requested:?} present mode, using {:?}",
+                    select_present_mode(config.render.present_mode, &self.present_modes)
+                );
+            }
+            self.present_mode = config.render.present_mode;
+        }
+        self.frame_latency = config.render.frame_latency;
+        self.write_overlay_placement(gpu_state);
 
         gpu_state.queue.write_buffer(
             &self.input_buffer,
-            0,
-            bytemuck::bytes_of(&InputUniform {
-                size: [width as f32, height as f32],
-                voronoi_progress: 0.0,
-                _pad: 0.0,
-            }),
+            offset_of!(InputUniform, transform) as u64,
+            bytemuck::bytes_of(&u32::from(transform)),
         );
 
-        self.configure(gpu_state);
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, size) as u64,
+            bytemuck::bytes_of(&[width as f32, height as f32]),
+        );
+        self.set_image_placement(gpu_state, config);
+
+        self.configure(gpu_state).inspect_err(|_| {
+            // so the next resize to the same size tries again
+            self.width = 0;
+            self.height = 0;
+        })
     }
 
-    fn configure(&self, gpu_state: &AppGpuState) {
+    fn buffer_size(&self) -> (u32, u32) {
+        buffer_size(self.transform, (self.width, self.height))
+    }
+
+    fn configure(&self, gpu_state: &AppGpuState) -> Result<()> {
+        let Some(surface) = &self.surface else {
+            return Ok(());
+        };
+        let (width, height) = self.buffer_size();
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            view_formats: vec![wgpu::TextureFormat::Bgra8UnormSrgb],
+            format: self.format,
+            view_formats: vec![self.format.add_srgb_suffix()],
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
-            width: self.width,
-            height: self.height,
-            desired_maximum_frame_latency: 2,
-            // Wayland is inherently a mailbox system.
-            present_mode: wgpu::PresentMode::Mailbox,
+            width,
+            height,
+            desired_maximum_frame_latency: self.frame_latency,
+            present_mode: select_present_mode(self.present_mode, &self.present_modes),
         };
-        self.surface.configure(&gpu_state.device, &surface_config);
+
+        // running out of (video) memory for large outputs shouldn't take down the whole process
+        gpu_state
+            .device
+            .push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        gpu_state
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        surface.configure(&gpu_state.device, &surface_config);
+        let validation_error = pollster::block_on(gpu_state.device.pop_error_scope());
+        let oom_error = pollster::block_on(gpu_state.device.pop_error_scope());
+
+        match validation_error.or(oom_error) {
+            Some(err) => Err(eyre!("failed to configure the swapchain: {err}")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn set_config(&self, gpu_state: &AppGpuState, config: &Config) {
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, dither) as u64,
+            bytemuck::bytes_of(&u32::from(config.render.dither)),
+        );
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, grain) as u64,
+            bytemuck::bytes_of(&config.render.grain),
+        );
+        self.set_gradient(gpu_state, &config.gradient);
+        self.set_image_placement(gpu_state, config);
+    }
+
+    pub fn set_gradient(&self, gpu_state: &AppGpuState, gradient: &GradientConfig) {
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, lightness) as u64,
+            bytemuck::bytes_of(&gradient.lightness),
+        );
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, chroma) as u64,
+            bytemuck::bytes_of(&gradient.chroma),
+        );
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, chroma_offset) as u64,
+            bytemuck::bytes_of(&gradient.chroma_offset),
+        );
+    }
+
+    fn set_image_placement(&self, gpu_state: &AppGpuState, config: &Config) {
+        let placement = match gpu_state.wallpaper_size {
+            Some(image_size) if config.render.mode == RenderMode::Image => Some(place_image(
+                (self.width, self.height),
+                image_size,
+                config.image.fit,
+            )),
+            _ => None,
+        };
+
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, use_image) as u64,
+            bytemuck::bytes_of(&u32::from(placement.is_some())),
+        );
+        if let Some(placement) = placement {
+            gpu_state.queue.write_buffer(
+                &self.input_buffer,
+                offset_of!(InputUniform, image_origin) as u64,
+                bytemuck::bytes_of(&placement.origin),
+            );
+            gpu_state.queue.write_buffer(
+                &self.input_buffer,
+                offset_of!(InputUniform, image_size) as u64,
+                bytemuck::bytes_of(&placement.size),
+            );
+        }
+    }
+
+    /// Shows `image` on top of everything else with its top left corner at `origin`.
+    /// `origin` is in logical pixels, the overlay is scaled up to match the buffer.
+    pub fn set_overlay(
+        &mut self,
+        gpu_state: &AppGpuState,
+        overlay: Option<(&image::RgbaImage, (u32, u32))>,
+    ) {
+        let Some((image, origin)) = overlay else {
+            self.overlay_bind_group = gpu_state.empty_overlay_bind_group();
+            self.overlay_placement = None;
+            gpu_state.queue.write_buffer(
+                &self.input_buffer,
+                offset_of!(InputUniform, use_overlay) as u64,
+                bytemuck::bytes_of(&0_u32),
+            );
+            return;
+        };
+
+        self.overlay_bind_group = create_texture_bind_group(
+            &gpu_state.device,
+            &gpu_state.queue,
+            &gpu_state.texture_bind_group_layout,
+            &gpu_state.overlay_sampler,
+            image.dimensions(),
+            image.as_raw(),
+            "overlay",
+        );
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, use_overlay) as u64,
+            bytemuck::bytes_of(&1_u32),
+        );
+        self.overlay_placement = Some((
+            [origin.0 as f32, origin.1 as f32],
+            [image.width() as f32, image.height() as f32],
+        ));
+        self.write_overlay_placement(gpu_state);
+    }
+
+    fn write_overlay_placement(&self, gpu_state: &AppGpuState) {
+        let Some((origin, size)) = self.overlay_placement else {
+            return;
+        };
+        let scaled = |[x, y]: [f32; 2]| [x * self.scale, y * self.scale];
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, overlay_origin) as u64,
+            bytemuck::bytes_of(&scaled(origin)),
+        );
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, overlay_size) as u64,
+            bytemuck::bytes_of(&scaled(size)),
+        );
+    }
+
+    /// Picks up the apps after [`AppGpuState::set_apps`], the icon sprites have to be placed
+    /// again.
+    pub fn set_apps(&mut self, gpu_state: &AppGpuState) {
+        (self.icon_sprites_buffer, self.screen_size_bind_group) =
+            create_icon_sprites_bind_group(gpu_state, &self.input_buffer);
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, seed_count) as u64,
+            bytemuck::bytes_of(&gpu_state.seed_count),
+        );
+    }
+
+    /// Places the app icons, sprites past the end and of apps without an icon aren't drawn.
+    pub fn set_icon_sprites(&self, gpu_state: &AppGpuState, sprites: &[Sprite]) {
+        if gpu_state.icon_uvs.is_empty() {
+            return;
+        }
+        let storage = gpu_state
+            .icon_uvs
+            .iter()
+            .enumerate()
+            .map(|(i, uv)| match (sprites.get(i), uv) {
+                (Some(sprite), Some((uv_origin, uv_size))) => IconSpriteStorage {
+                    center: sprite.center,
+                    size: sprite.size,
+                    _pad: 0.0,
+                    uv_origin: *uv_origin,
+                    uv_size: *uv_size,
+                },
+                _ => IconSpriteStorage::zeroed(),
+            })
+            .collect::<Vec<_>>();
+        gpu_state
+            .queue
+            .write_buffer(&self.icon_sprites_buffer, 0, bytemuck::cast_slice(&storage));
+    }
+
+    /// Shifts the generated gradient, used for the time of day.
+    pub fn set_gradient_offset(&self, gpu_state: &AppGpuState, offset: Oklab) {
+        let (l, a, b) = offset.into_components();
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, gradient_offset) as u64,
+            bytemuck::bytes_of(&[l, a, b]),
+        );
+    }
+
+    /// Draws a ring around the surface-local (logical) position, an alpha of 0 hides it.
+    pub fn set_ripple(&self, gpu_state: &AppGpuState, (x, y): (f64, f64), radius: f32, alpha: f32) {
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, ripple_center) as u64,
+            bytemuck::bytes_of(&[x as f32 * self.scale, y as f32 * self.scale]),
+        );
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, ripple_radius) as u64,
+            bytemuck::bytes_of(&(radius * self.scale)),
+        );
+        gpu_state.queue.write_buffer(
+            &self.input_buffer,
+            offset_of!(InputUniform, ripple_alpha) as u64,
+            bytemuck::bytes_of(&alpha),
+        );
     }
 
     pub fn set_voronoi_progress(&self, gpu_state: &AppGpuState, voronoi_progress: f32) {
@@ -267,52 +1023,389 @@ impl SurfaceGpuState {
         );
     }
 
-    pub fn draw(&self, gpu_state: &AppGpuState) {
-        let surface_texture = match self.surface.get_current_texture() {
+    /// Finds the index of the desktop color the shader uses at the logical surface pixel.
+    ///
+    /// The result is read back asynchronously, `on_done` is called from [`AppGpuState::poll`]
+    /// once it's available.
+    pub fn pick(
+        &self,
+        gpu_state: &AppGpuState,
+        position: (f64, f64),
+        on_done: impl FnOnce(Result<Option<usize>, wgpu::BufferAsyncError>) + Send + 'static,
+    ) {
+        let device = &gpu_state.device;
+
+        let position = buffer_pixel_center(position, self.scale, (self.width, self.height));
+        let position = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("pick_position_buffer"),
+            contents: bytemuck::bytes_of(&position),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let result = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pick_result_buffer"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pick_readback_buffer"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pick_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &gpu_state.pick_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: position.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: result.as_entire_binding(),
+                },
+            ],
+            label: Some("pick_bind_group"),
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Pick Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&gpu_state.pick_pipeline);
+            compute_pass.set_bind_group(0, &self.screen_size_bind_group, &[]);
+            compute_pass.set_bind_group(1, &gpu_state.desktop_colors_bind_group, &[]);
+            compute_pass.set_bind_group(2, &gpu_state.wallpaper_bind_group, &[]);
+            compute_pass.set_bind_group(3, &pick_bind_group, &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&result, 0, &readback, 0, size_of::<u32>() as u64);
+        gpu_state.queue.submit(Some(encoder.finish()));
+
+        let mapped = readback.clone();
+        readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |status| {
+                let index = status.map(|()| {
+                    let index = decode_pick(&mapped.slice(..).get_mapped_range());
+                    mapped.unmap();
+                    index
+                });
+                on_done(index);
+            });
+    }
+
+    /// Returns whether a frame was presented, which commits the surface.
+    pub fn draw(&self, gpu_state: &AppGpuState) -> bool {
+        let Some(surface) = &self.surface else {
+            return false;
+        };
+        let surface_texture = match surface.get_current_texture() {
             Ok(texture) => texture,
             Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
-                self.configure(gpu_state);
-                self.surface.get_current_texture().unwrap()
+                if let Err(err) = self.configure(gpu_state) {
+                    error!("{err:?}");
+                    return false;
+                }
+                match surface.get_current_texture() {
+                    Ok(texture) => texture,
+                    Err(err) => {
+                        error!("Failed to acquire swapchain texture after reconfiguring: {err}");
+                        return false;
+                    }
+                }
+            }
+            Err(err) => {
+                error!("Failed to acquire next swapchain texture: {err}");
+                return false;
             }
-            Err(e) => panic!("failed to acquire next swapchain texture: {e}"),
         };
 
-        let texture_view: wgpu::TextureView = surface_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let texture_view: wgpu::TextureView =
+            surface_texture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor {
+                    format: Some(self.format.add_srgb_suffix()),
+                    ..Default::default()
+                });
 
         let mut encoder = gpu_state.device.create_command_encoder(&Default::default());
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[
-                    // This is what @location(0) in the fragment shader targets
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &texture_view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.1,
-                                g: 0.5,
-                                b: 0.3,
-                                a: 1.0,
-                            }),
-                            store: wgpu::StoreOp::Store,
-                        },
-                        depth_slice: None,
-                    }),
-                ],
-                depth_stencil_attachment: None,
-                ..Default::default()
+        self.encode_render(gpu_state, &mut encoder, &texture_view);
+        gpu_state.queue.submit(Some(encoder.finish()));
+        surface_texture.present();
+        true
+    }
+
+    /// Renders a frame into an image, with the same pipeline as on screen. Blocks until the
+    /// GPU is done.
+    pub fn render_to_image(&self, gpu_state: &AppGpuState) -> Result<image::RgbaImage> {
+        let device = &gpu_state.device;
+        let (width, height) = self.buffer_size();
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // has to match the render pipeline
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+
+        // rows of a buffer copy have to be aligned, the padding is skipped when reading
+        let row_size = width * 4;
+        let padded_row_size = row_size.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen_readback_buffer"),
+            size: u64::from(padded_row_size) * u64::from(height),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        self.encode_render(gpu_state, &mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_size),
+                    rows_per_image: Some(height),
+                },
+            },
+            size,
+        );
+        gpu_state.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |status| {
+                let _ = sender.send(status);
             });
+        let status = loop {
+            device
+                .poll(wgpu::PollType::Poll)
+                .wrap_err("failed to poll the device")?;
+            match receiver.try_recv() {
+                Ok(status) => break status,
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(1)),
+            }
+        };
+        status.wrap_err("failed to read back the rendered frame")?;
 
-            render_pass.set_pipeline(&gpu_state.render_pipeline);
-            render_pass.set_bind_group(0, &self.screen_size_bind_group, &[]);
-            render_pass.set_bind_group(1, &gpu_state.desktop_colors_bind_group, &[]);
-            render_pass.draw(0..6, 0..1);
+        let mapped = readback.slice(..).get_mapped_range();
+        let mut pixels = Vec::with_capacity((row_size * height) as usize);
+        for row in mapped.chunks_exact(padded_row_size as usize) {
+            // BGRA to RGBA
+            for pixel in row[..row_size as usize].chunks_exact(4) {
+                pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+            }
         }
+        drop(mapped);
+        readback.unmap();
 
-        gpu_state.queue.submit(Some(encoder.finish()));
-        surface_texture.present();
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| eyre!("readback has the wrong size"))
+    }
+
+    fn encode_render(
+        &self,
+        gpu_state: &AppGpuState,
+        encoder: &mut wgpu::CommandEncoder,
+        texture_view: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[
+                // This is what @location(0) in the fragment shader targets
+                Some(wgpu::RenderPassColorAttachment {
+                    view: texture_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: 0.1,
+                            g: 0.5,
+                            b: 0.3,
+                            a: 1.0,
+                        }),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                }),
+            ],
+            depth_stencil_attachment: None,
+            ..Default::default()
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.screen_size_bind_group, &[]);
+        render_pass.set_bind_group(1, &gpu_state.desktop_colors_bind_group, &[]);
+        render_pass.set_bind_group(2, &gpu_state.wallpaper_bind_group, &[]);
+        render_pass.set_bind_group(3, &self.overlay_bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
+
+/// The app index written by the pick shader, `u32::MAX` means there are no apps.
+fn decode_pick(bytes: &[u8]) -> Option<usize> {
+    let index = u32::from_ne_bytes(bytes.try_into().ok()?);
+    (index != u32::MAX).then_some(index as usize)
+}
+
+/// The center of the buffer pixel under a surface-local (logical) position, which is where the
+/// fragment shader evaluates that pixel. Rounding to a logical pixel first would make every
+/// other pixel unreachable at scale 2. The shader undoes the buffer transform itself.
+fn buffer_pixel_center((x, y): (f64, f64), scale: f32, (width, height): (u32, u32)) -> [f32; 2] {
+    // clicks on the very edge can be reported slightly outside of the surface,
+    // they belong to the nearest pixel on it
+    let center = |logical: f64, size: u32| {
+        let pixel = (logical * f64::from(scale)).floor();
+        (pixel.clamp(0.0, f64::from(size.max(1) - 1)) + 0.5) as f32
+    };
+    [center(x, width), center(y, height)]
+}
+
+/// The size of the buffer for a surface of the size, which is rotated for 90 and 270 degree
+/// transforms.
+fn buffer_size(transform: Transform, (width, height): (u32, u32)) -> (u32, u32) {
+    if is_rotated(transform) {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// Whether the transform swaps width and height.
+fn is_rotated(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+/// The configured present mode if the surface supports it, or the best one it does support.
+fn select_present_mode(
+    configured: PresentMode,
+    supported: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    match wgpu_present_mode(configured) {
+        Some(mode) if supported.contains(&mode) => mode,
+        // Wayland is inherently a mailbox system, and every surface supports fifo.
+        _ if supported.contains(&wgpu::PresentMode::Mailbox) => wgpu::PresentMode::Mailbox,
+        _ => wgpu::PresentMode::Fifo,
+    }
+}
+
+/// `None` for [`PresentMode::Auto`].
+fn wgpu_present_mode(mode: PresentMode) -> Option<wgpu::PresentMode> {
+    match mode {
+        PresentMode::Auto => None,
+        PresentMode::Mailbox => Some(wgpu::PresentMode::Mailbox),
+        PresentMode::Fifo => Some(wgpu::PresentMode::Fifo),
+        PresentMode::FifoRelaxed => Some(wgpu::PresentMode::FifoRelaxed),
+        PresentMode::Immediate => Some(wgpu::PresentMode::Immediate),
+    }
+}
+
+/// The first sRGB format, so the shader output is encoded the same everywhere. Surfaces without
+/// one get their first format, which is then rendered to through an sRGB view.
+fn select_format(formats: &[wgpu::TextureFormat]) -> Option<wgpu::TextureFormat> {
+    formats
+        .iter()
+        .copied()
+        .find(|format| format.is_srgb())
+        .or_else(|| formats.first().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_pick_reads_the_index() {
+        assert_eq!(decode_pick(&7u32.to_ne_bytes()), Some(7));
+        assert_eq!(decode_pick(&u32::MAX.to_ne_bytes()), None);
+        assert_eq!(decode_pick(&[0, 0]), None);
+    }
+
+    #[test]
+    fn select_present_mode_falls_back() {
+        use wgpu::PresentMode::{Fifo, Immediate, Mailbox};
+        assert_eq!(
+            select_present_mode(PresentMode::Immediate, &[Fifo, Immediate]),
+            Immediate
+        );
+        assert_eq!(
+            select_present_mode(PresentMode::Immediate, &[Fifo, Mailbox]),
+            Mailbox
+        );
+        assert_eq!(
+            select_present_mode(PresentMode::Auto, &[Fifo, Mailbox]),
+            Mailbox
+        );
+        assert_eq!(select_present_mode(PresentMode::Auto, &[Fifo]), Fifo);
+    }
+
+    #[test]
+    fn buffer_pixel_center_at_fractional_scales() {
+        assert_eq!(
+            buffer_pixel_center((10.0, 20.0), 1.0, (100, 100)),
+            [10.5, 20.5]
+        );
+        // both buffer pixels of a logical pixel are reachable at scale 2
+        assert_eq!(
+            buffer_pixel_center((10.0, 10.25), 2.0, (200, 200)),
+            [20.5, 20.5]
+        );
+        assert_eq!(
+            buffer_pixel_center((10.5, 10.75), 2.0, (200, 200)),
+            [21.5, 21.5]
+        );
+        assert_eq!(buffer_pixel_center((3.0, 0.0), 1.5, (150, 150)), [4.5, 0.5]);
+    }
+
+    #[test]
+    fn buffer_pixel_center_clamps_to_the_buffer() {
+        assert_eq!(
+            buffer_pixel_center((-0.5, 100.0), 1.0, (100, 100)),
+            [0.5, 99.5]
+        );
+        assert_eq!(buffer_pixel_center((5.0, 5.0), 1.0, (0, 0)), [0.5, 0.5]);
+    }
+
+    #[test]
+    fn buffer_size_of_every_transform() {
+        // the numbers are what surface_position in the shader switches on
+        let table = [
+            (Transform::Normal, 0, (200, 100)),
+            (Transform::_90, 1, (100, 200)),
+            (Transform::_180, 2, (200, 100)),
+            (Transform::_270, 3, (100, 200)),
+            (Transform::Flipped, 4, (200, 100)),
+            (Transform::Flipped90, 5, (100, 200)),
+            (Transform::Flipped180, 6, (200, 100)),
+            (Transform::Flipped270, 7, (100, 200)),
+        ];
+        for (transform, number, size) in table {
+            assert_eq!(u32::from(transform), number, "{transform:?}");
+            assert_eq!(buffer_size(transform, (200, 100)), size, "{transform:?}");
+        }
     }
 }
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use image::RgbaImage;
+
+use crate::text;
+
+/// How long the hint takes to fade out at the end of `hint.duration_secs`.
+pub const FADE: Duration = Duration::from_millis(500);
+/// Distance from the corner in logical pixels.
+const MARGIN: u32 = 16;
+
+/// The opacity of the hint after it was shown for `elapsed`, fully opaque until it fades out
+/// over the last [`FADE`] of `duration`.
+pub fn alpha(elapsed: Duration, duration: Duration) -> f32 {
+    let fade = FADE.min(duration);
+    let Some(remaining) = duration.checked_sub(elapsed) else {
+        return 0.0;
+    };
+    if remaining >= fade {
+        1.0
+    } else {
+        remaining.as_secs_f32() / fade.as_secs_f32()
+    }
+}
+
+/// The hint text with `{count}` replaced, as a label at the given opacity.
+pub fn render(text: &str, app_count: usize, alpha: f32) -> RgbaImage {
+    let mut image = text::label(&text.replace("{count}", &app_count.to_string()));
+    for pixel in image.pixels_mut() {
+        pixel.0[3] = (f32::from(pixel.0[3]) * alpha).round() as u8;
+    }
+    image
+}
+
+/// Top left corner of the hint in the bottom left corner of the surface.
+pub fn origin(
+    (surface_width, surface_height): (u32, u32),
+    (width, height): (u32, u32),
+) -> (u32, u32) {
+    (
+        MARGIN.min(surface_width.saturating_sub(width)),
+        surface_height.saturating_sub(height + MARGIN),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alpha_fades_out_at_the_end() {
+        let duration = Duration::from_secs(5);
+        assert_eq!(alpha(Duration::ZERO, duration), 1.0);
+        assert_eq!(alpha(duration - FADE, duration), 1.0);
+        assert!((alpha(duration - FADE / 2, duration) - 0.5).abs() < 1e-6);
+        assert_eq!(alpha(duration, duration), 0.0);
+        assert_eq!(alpha(duration * 2, duration), 0.0);
+        // shorter than the fade, it fades the whole time
+        let short = FADE / 2;
+        assert!((alpha(short / 2, short) - 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use eyre::{Context, Result};
+use log::{debug, warn};
+use palette::Oklab;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, IconConfig};
+
+/// Average icon colors and what was found out about the .desktop files in earlier runs, so
+/// icons and files that didn't change since then don't have to be decoded, parsed or looked up
+/// again. Stored in `$XDG_CACHE_HOME/colouncher/icon-colors.toml`.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IconCache {
+    /// The icon settings the colors were averaged with, changing them invalidates the colors.
+    settings: String,
+    icons: HashMap<String, CachedIcon>,
+    /// The discovery settings and icon size the entries were processed with, changing them
+    /// invalidates the entries.
+    entry_settings: String,
+    /// By the path of the .desktop file.
+    entries: HashMap<String, CachedEntry>,
+    #[serde(skip)]
+    used: HashSet<String>,
+    #[serde(skip)]
+    used_entries: HashSet<String>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedIcon {
+    /// Modification time of the icon file in nanoseconds since the epoch.
+    modified: u64,
+    color: [f32; 3],
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedEntry {
+    /// Modification time of the .desktop file in nanoseconds since the epoch.
+    modified: u64,
+    #[serde(flatten)]
+    entry: ProcessedEntry,
+}
+
+/// What processing a .desktop file found out that doesn't need parsing it again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessedEntry {
+    /// Whether it's an application that's shown, the others aren't parsed at all next time.
+    pub shown: bool,
+    /// The icon it was found to have, `None` if there was none yet and it's looked up again.
+    pub icon_path: Option<PathBuf>,
+}
+
+impl IconCache {
+    /// An empty cache if there is none yet or it can't be read. `icon_size` is the size themed
+    /// icons are looked up for.
+    pub fn load(config: &Config, icon_size: u16) -> Self {
+        let settings = cache_settings(&config.icons);
+        let entry_settings = entry_settings(config, icon_size);
+        let cache = cache_path()
+            .filter(|path| path.exists())
+            .map(|path| -> Result<Self> {
+                let contents = std::fs::read_to_string(&path)
+                    .wrap_err_with(|| format!("reading {}", path.display()))?;
+                toml::from_str(&contents).wrap_err_with(|| format!("parsing {}", path.display()))
+            })
+            .transpose()
+            .unwrap_or_else(|err| {
+                warn!("Ignoring the icon color cache: {err:?}");
+                None
+            });
+
+        let mut cache = cache.unwrap_or_default();
+        if cache.settings != settings {
+            cache.settings = settings;
+            cache.icons.clear();
+        }
+        if cache.entry_settings != entry_settings {
+            cache.entry_settings = entry_settings;
+            cache.entries.clear();
+        }
+        cache
+    }
+
+    /// The cached color of the icon, if the file hasn't been modified since.
+    pub fn get(&mut self, path: &Path) -> Option<Oklab> {
+        let key = path.to_str()?;
+        let icon = self.icons.get(key)?;
+        if modified(path)? != icon.modified {
+            debug!("Icon {key} changed since it was cached");
+            return None;
+        }
+        let [l, a, b] = icon.color;
+        // written by versions that averaged transparent icons to NaN
+        if !icon.color.iter().all(|component| component.is_finite()) {
+            return None;
+        }
+        self.used.insert(key.to_owned());
+        Some(Oklab::new(l, a, b))
+    }
+
+    pub fn insert(&mut self, path: &Path, color: Oklab) {
+        let (Some(key), Some(modified)) = (path.to_str(), modified(path)) else {
+            return;
+        };
+        self.icons.insert(
+            key.to_owned(),
+            CachedIcon {
+                modified,
+                color: [color.l, color.a, color.b],
+            },
+        );
+        self.used.insert(key.to_owned());
+        self.changed = true;
+    }
+
+    /// What was found out about the .desktop file, if it hasn't been modified since.
+    pub fn get_entry(&mut self, path: &Path) -> Option<ProcessedEntry> {
+        let key = path.to_str()?;
+        let entry = self.entries.get(key)?;
+        if modified(path)? != entry.modified {
+            debug!("{key} changed since it was cached");
+            return None;
+        }
+        self.used_entries.insert(key.to_owned());
+        Some(entry.entry.clone())
+    }
+
+    pub fn insert_entry(&mut self, path: &Path, entry: ProcessedEntry) {
+        let (Some(key), Some(modified)) = (path.to_str(), modified(path)) else {
+            return;
+        };
+        let entry = CachedEntry { modified, entry };
+        if self
+            .entries
+            .get(key)
+            .is_none_or(|cached| cached.modified != entry.modified || cached.entry != entry.entry)
+        {
+            self.entries.insert(key.to_owned(), entry);
+            self.changed = true;
+        }
+        self.used_entries.insert(key.to_owned());
+    }
+
+    /// Writes the cache back if anything changed, forgetting icons and entries that weren't
+    /// used this time.
+    pub fn save(mut self) {
+        let unused =
+            self.icons.len() - self.used.len() + self.entries.len() - self.used_entries.len();
+        if !self.changed && unused == 0 {
+            return;
+        }
+        self.icons.retain(|key, _| self.used.contains(key));
+        self.entries
+            .retain(|key, _| self.used_entries.contains(key));
+        if let Err(err) = self.write() {
+            warn!("Failed to write the icon color cache: {err:?}");
+        }
+    }
+
+    fn write(&self) -> Result<()> {
+        let Some(path) = cache_path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).wrap_err_with(|| format!("creating {}", dir.display()))?;
+        }
+        let contents = toml::to_string(self).wrap_err("serializing the cache")?;
+        std::fs::write(&path, contents).wrap_err_with(|| format!("writing {}", path.display()))
+    }
+}
+
+/// Everything that changes the average color of an icon.
+/// Bumped whenever the averaging itself changes, so colors from older versions aren't reused.
+const AVERAGING_VERSION: u32 = 2;
+
+fn cache_settings(config: &IconConfig) -> String {
+    format!(
+        "version={AVERAGING_VERSION} alpha_cutoff={} average_size={:?} center_weight={} \
+         background={:?}",
+        config.alpha_cutoff, config.average_size, config.center_weight, config.background
+    )
+}
+
+/// Everything that changes which .desktop files are shown and which icons they get.
+fn entry_settings(config: &Config, icon_size: u16) -> String {
+    format!("icon_size={icon_size} discovery={:?}", config.discovery)
+}
+
+fn modified(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
+fn cache_path() -> Option<PathBuf> {
+    let cache_home = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_home.join("colouncher").join("icon-colors.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+
+    #[test]
+    fn entries_are_invalidated_by_modification() {
+        let path = std::env::temp_dir().join(format!(
+            "colouncher-test-{}-entry.desktop",
+            std::process::id()
+        ));
+        let file = File::create(&path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+
+        let entry = ProcessedEntry {
+            shown: true,
+            icon_path: Some("/usr/share/icons/hicolor/48x48/apps/test.png".into()),
+        };
+        let mut cache = IconCache::default();
+        cache.insert_entry(&path, entry.clone());
+        assert_eq!(cache.get_entry(&path), Some(entry));
+
+        file.set_modified(SystemTime::now()).unwrap();
+        assert_eq!(cache.get_entry(&path), None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,576 @@
+use eyre::{Context, Result, bail};
+use log::{debug, error, info, warn};
+use std::{
+    collections::HashMap, fmt, io, io::ErrorKind, path::Path, process::Command, sync::Mutex,
+};
+
+use crate::config::LauncherBackend;
+
+/// Why a program couldn't be launched, so callers can tell the user what to do about it.
+#[derive(Debug)]
+pub enum LaunchError {
+    /// The `Exec` key can't be turned into a command.
+    InvalidExec(String),
+    /// The program that does the launching (like `sh`, `niri` or `systemd-run`) isn't
+    /// installed.
+    NotFound { program: &'static str },
+    /// The program that does the launching couldn't be started.
+    Spawn {
+        program: &'static str,
+        source: io::Error,
+    },
+    /// The program that does the launching reported an error, like niri's IPC failing.
+    Failed {
+        program: &'static str,
+        stderr: String,
+    },
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::InvalidExec(err) => write!(f, "invalid Exec: {err}"),
+            LaunchError::NotFound { program } => write!(f, "{program} is not installed"),
+            LaunchError::Spawn { program, source } => write!(f, "executing {program}: {source}"),
+            LaunchError::Failed { program, stderr } => {
+                write!(f, "{program} returned error: {}", stderr.trim())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LaunchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LaunchError::Spawn { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl LaunchError {
+    fn spawn(program: &'static str, err: io::Error) -> Self {
+        if err.kind() == ErrorKind::NotFound {
+            LaunchError::NotFound { program }
+        } else {
+            LaunchError::Spawn {
+                program,
+                source: err,
+            }
+        }
+    }
+}
+
+/// How to launch an app, worked out before it's clicked so only the launching itself is left.
+#[derive(Debug, Clone)]
+pub enum PreparedLaunch {
+    /// Activated over DBus, falling back to the `Exec` if that fails.
+    Dbus {
+        name: String,
+        path: String,
+        exec: Option<String>,
+    },
+    /// An argv for niri to spawn.
+    Argv(Vec<String>),
+    /// A command line for the backends that run it with a shell.
+    Command(String),
+}
+
+/// The session bus connection, kept around since connecting takes longer than activating.
+static SESSION_BUS: Mutex<Option<zbus::blocking::Connection>> = Mutex::new(None);
+
+pub fn launch(exec: &str, backend: LauncherBackend) -> Result<(), LaunchError> {
+    launch_prepared(prepare(exec, backend)?, backend, || {})
+}
+
+/// Turns an `Exec` key into what the backend runs.
+pub fn prepare(exec: &str, backend: LauncherBackend) -> Result<PreparedLaunch, LaunchError> {
+    let invalid = |err: eyre::Report| LaunchError::InvalidExec(err.to_string());
+    if backend == LauncherBackend::Niri {
+        // niri can spawn an argv directly, no need to go through a shell
+        return Ok(PreparedLaunch::Argv(tokenize_exec(exec).map_err(invalid)?));
+    }
+    Ok(PreparedLaunch::Command(expand_exec(exec).map_err(invalid)?))
+}
+
+/// Prepares activating an app with `DBusActivatable=true` over DBus, with its `Exec` to fall
+/// back to.
+// https://specifications.freedesktop.org/desktop-entry/latest/dbus.html
+pub fn prepare_dbus(desktop_id: &str, exec: Option<String>) -> Result<PreparedLaunch> {
+    let (name, path) = dbus_names(desktop_id)?;
+    Ok(PreparedLaunch::Dbus { name, path, exec })
+}
+
+/// DBus activation runs in the background since it waits for the app's service to start, so
+/// its errors are only logged. `on_launched` is called once the app was actually launched,
+/// from that background thread for DBus activation.
+pub fn launch_prepared(
+    prepared: PreparedLaunch,
+    backend: LauncherBackend,
+    on_launched: impl FnOnce() + Send + 'static,
+) -> Result<(), LaunchError> {
+    match prepared {
+        PreparedLaunch::Dbus { name, path, exec } => {
+            std::thread::spawn(move || {
+                let Err(err) = call_activate(&name, &path) else {
+                    on_launched();
+                    return;
+                };
+                warn!("Failed to activate {name} over DBus, using its Exec instead: {err:?}");
+                let Some(exec) = exec else {
+                    return;
+                };
+                match launch(&exec, backend) {
+                    Ok(()) => on_launched(),
+                    Err(err) => error!("Failed to launch program: {exec}: {err}"),
+                }
+            });
+            Ok(())
+        }
+        PreparedLaunch::Argv(argv) => {
+            spawn_niri(&argv)?;
+            on_launched();
+            Ok(())
+        }
+        PreparedLaunch::Command(cmd) => {
+            spawn(&cmd, backend)?;
+            on_launched();
+            Ok(())
+        }
+    }
+}
+
+/// Connects to the session bus in the background if it isn't yet, so activating an app over
+/// DBus doesn't have to wait for it.
+pub fn connect_session_bus() {
+    std::thread::spawn(|| {
+        if let Err(err) = session_bus() {
+            debug!("Not connecting to the session bus ahead of time: {err:?}");
+        }
+    });
+}
+
+fn session_bus() -> Result<zbus::blocking::Connection> {
+    let mut bus = SESSION_BUS.lock().unwrap();
+    if let Some(connection) = &*bus {
+        return Ok(connection.clone());
+    }
+    let connection =
+        zbus::blocking::Connection::session().wrap_err("connecting to the session bus")?;
+    *bus = Some(connection.clone());
+    Ok(connection)
+}
+
+fn call_activate(name: &str, path: &str) -> Result<()> {
+    info!("Activating {name} over DBus");
+    let connection = session_bus()?;
+    let platform_data = HashMap::<&str, zbus::zvariant::Value>::new();
+    let result = connection.call_method(
+        Some(name),
+        path,
+        Some("org.freedesktop.Application"),
+        "Activate",
+        &(platform_data,),
+    );
+    if let Err(zbus::Error::InputOutput(_)) = &result {
+        // the connection broke, the next activation connects again
+        *SESSION_BUS.lock().unwrap() = None;
+    }
+    result.wrap_err_with(|| format!("calling Activate on {name}"))?;
+    Ok(())
+}
+
+/// The bus name and object path of the app with the desktop file id,
+/// `org.example.App.desktop` is `org.example.App` at `/org/example/App`.
+fn dbus_names(desktop_id: &str) -> Result<(String, String)> {
+    let Some(name) = desktop_id.strip_suffix(".desktop") else {
+        bail!("desktop file id {desktop_id} doesn't end in .desktop");
+    };
+    // dashes are allowed in bus names but not in object paths
+    let path = format!("/{}", name.replace('.', "/").replace('-', "_"));
+    Ok((name.to_owned(), path))
+}
+
+/// Substitutes the field codes of an `Exec` key.
+// https://specifications.freedesktop.org/desktop-entry/latest/exec-variables.html
+pub fn expand_exec(exec: &str) -> Result<String> {
+    // lol terrible implementation that works well enough
+    let cmd = exec.replace("%U", "").replace("%F", "");
+    if cmd.contains("%") {
+        bail!("trying to execute insufficiently substituted command-line, refusing: {cmd}");
+    }
+    Ok(cmd)
+}
+
+/// Splits an `Exec` key into its arguments, dropping the field codes since we never pass
+/// files or URLs.
+// https://specifications.freedesktop.org/desktop-entry/latest/exec-variables.html
+pub fn tokenize_exec(exec: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    // `None` between arguments, so `""` is still an (empty) argument
+    let mut current: Option<String> = None;
+    let mut in_quotes = false;
+
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.get_or_insert_default();
+            }
+            '\\' if in_quotes => match chars.next() {
+                Some(escaped @ ('"' | '`' | '$' | '\\')) => {
+                    current.get_or_insert_default().push(escaped);
+                }
+                Some(other) => bail!("invalid escape sequence \\{other} in {exec}"),
+                None => bail!("unterminated escape sequence in {exec}"),
+            },
+            c if c.is_ascii_whitespace() && !in_quotes => {
+                args.extend(current.take());
+            }
+            '%' => match chars.next() {
+                Some('%') => current.get_or_insert_default().push('%'),
+                // a field code on its own doesn't leave an empty argument behind
+                Some(
+                    'f' | 'F' | 'u' | 'U' | 'i' | 'c' | 'k' | 'd' | 'D' | 'n' | 'N' | 'v' | 'm',
+                ) => {}
+                Some(other) => bail!("unknown field code %{other} in {exec}"),
+                None => bail!("unterminated field code in {exec}"),
+            },
+            c => current.get_or_insert_default().push(c),
+        }
+    }
+    if in_quotes {
+        bail!("unterminated quote in {exec}");
+    }
+    args.extend(current);
+
+    if args.is_empty() {
+        bail!("empty Exec");
+    }
+    Ok(args)
+}
+
+fn spawn(cmd: &str, backend: LauncherBackend) -> Result<(), LaunchError> {
+    info!("Spawning program: {cmd}");
+    match backend {
+        // the command still has the quoting of the `Exec` key, so it splits the same way
+        LauncherBackend::Niri => spawn_niri(
+            &tokenize_exec(cmd).map_err(|err| LaunchError::InvalidExec(err.to_string()))?,
+        ),
+        LauncherBackend::Direct => spawn_direct(cmd),
+        LauncherBackend::SystemdRun => spawn_systemd_run(cmd),
+        LauncherBackend::Sway => spawn_sway(cmd),
+        LauncherBackend::Hyprland => spawn_hyprland(cmd),
+        LauncherBackend::Auto if std::env::var_os("SWAYSOCK").is_some() => spawn_sway(cmd),
+        LauncherBackend::Auto if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() => {
+            spawn_hyprland(cmd)
+        }
+        LauncherBackend::Auto if has_user_systemd() => spawn_systemd_run(cmd),
+        LauncherBackend::Auto => spawn_direct(cmd),
+    }
+}
+
+/// Whether the user's systemd instance can be reached, which systemd-run needs.
+fn has_user_systemd() -> bool {
+    std::env::var_os("XDG_RUNTIME_DIR").is_some_and(|runtime_dir| {
+        let runtime_dir = Path::new(&runtime_dir);
+        runtime_dir.join("bus").exists() || runtime_dir.join("systemd/private").exists()
+    })
+}
+
+fn systemd_run_command(cmd: &str) -> Command {
+    let mut command = Command::new("systemd-run");
+    command
+        .args(["--user", "--scope", "--collect", "--quiet", "--"])
+        .args(["sh", "-c", cmd]);
+    command
+}
+
+fn spawn_systemd_run(cmd: &str) -> Result<(), LaunchError> {
+    let mut child = match systemd_run_command(cmd).spawn() {
+        Ok(child) => child,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            warn!("systemd-run not found, spawning the program directly");
+            return spawn_direct(cmd);
+        }
+        Err(err) => return Err(LaunchError::spawn("systemd-run", err)),
+    };
+    // with --scope, systemd-run becomes the program, so it's our child just like with direct
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+fn spawn_direct(cmd: &str) -> Result<(), LaunchError> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .spawn()
+        .map_err(|err| LaunchError::spawn("sh", err))?;
+    // reap it in the background so it doesn't stay around as a zombie
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// swaymsg running the argv. Sway runs the rest of an exec command with `sh -c`, after
+/// splitting commands at `;` and `,` outside of quotes, so every argument is quoted for both.
+fn sway_command(argv: &[String]) -> Command {
+    let cmd = argv
+        .iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut command = Command::new("swaymsg");
+    command.args(["--", "exec", &cmd]);
+    command
+}
+
+/// The argument quoted for `sh`, unless it only has characters that need no quoting.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./=:@+%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_owned();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+fn spawn_sway(cmd: &str) -> Result<(), LaunchError> {
+    // the command still has the quoting of the `Exec` key, which isn't quite the shell's
+    let argv = tokenize_exec(cmd).map_err(|err| LaunchError::InvalidExec(err.to_string()))?;
+    let output = match sway_command(&argv).output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            warn!("swaymsg not found, spawning the program directly");
+            return spawn_direct(cmd);
+        }
+        Err(err) => return Err(LaunchError::spawn("swaymsg", err)),
+    };
+    if let Some(error) = sway_reply_error(&output.stdout) {
+        return Err(LaunchError::Failed {
+            program: "swaymsg",
+            stderr: error,
+        });
+    }
+    if !output.status.success() {
+        return Err(LaunchError::Failed {
+            program: "swaymsg",
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// The error in swaymsg's reply to a command, which is a JSON array with a result for every
+/// command like `[{"success": false, "error": "..."}]`.
+fn sway_reply_error(stdout: &[u8]) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct CommandResult {
+        success: bool,
+        error: Option<String>,
+    }
+    let results = serde_json::from_slice::<Vec<CommandResult>>(stdout).ok()?;
+    results
+        .into_iter()
+        .find(|result| !result.success)
+        .map(|result| result.error.unwrap_or_else(|| "command failed".into()))
+}
+
+fn hyprctl_command(cmd: &str) -> Command {
+    // like sway, Hyprland runs the rest of an exec dispatch with `sh -c`
+    let mut command = Command::new("hyprctl");
+    command.args(["dispatch", "exec", "--", cmd]);
+    command
+}
+
+fn spawn_hyprland(cmd: &str) -> Result<(), LaunchError> {
+    let output = match hyprctl_command(cmd).output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            warn!("hyprctl not found, spawning the program directly");
+            return spawn_direct(cmd);
+        }
+        Err(err) => return Err(LaunchError::spawn("hyprctl", err)),
+    };
+    let error = hyprctl_reply_error(&output.stdout);
+    if error.is_none() && output.status.success() {
+        return Ok(());
+    }
+    let message = match error {
+        Some(error) if !error.is_empty() => error,
+        // no reply that says what went wrong
+        _ => String::from_utf8_lossy(&output.stderr).into_owned(),
+    };
+    Err(LaunchError::Failed {
+        program: "hyprctl",
+        stderr: message,
+    })
+}
+
+/// The error in hyprctl's reply to a dispatch, which is `ok` if it worked. hyprctl exits
+/// successfully even when the dispatch fails, only the reply tells.
+fn hyprctl_reply_error(stdout: &[u8]) -> Option<String> {
+    let reply = String::from_utf8_lossy(stdout);
+    let reply = reply.trim();
+    (reply != "ok").then(|| reply.to_owned())
+}
+
+fn spawn_niri(argv: &[String]) -> Result<(), LaunchError> {
+    info!("Spawning program through niri: {argv:?}");
+    let output = Command::new("niri")
+        .arg("msg")
+        .arg("action")
+        .arg("spawn")
+        .arg("--")
+        .args(argv)
+        .output()
+        .map_err(|err| LaunchError::spawn("niri", err))?;
+    if !output.status.success() {
+        return Err(LaunchError::Failed {
+            program: "niri",
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_exec_drops_file_and_url_codes() {
+        assert_eq!(expand_exec("firefox %U").unwrap(), "firefox ");
+        assert_eq!(expand_exec("gimp %F").unwrap(), "gimp ");
+        assert!(expand_exec("app --icon %i").is_err());
+    }
+
+    #[test]
+    fn dbus_names_of_desktop_ids() {
+        assert_eq!(
+            dbus_names("org.gnome.Nautilus.desktop").unwrap(),
+            ("org.gnome.Nautilus".into(), "/org/gnome/Nautilus".into())
+        );
+        assert_eq!(
+            dbus_names("org.example.my-app.desktop").unwrap(),
+            ("org.example.my-app".into(), "/org/example/my_app".into())
+        );
+        assert!(dbus_names("org.example.App").is_err());
+    }
+
+    #[test]
+    fn tokenize_exec_splits_like_the_spec() {
+        assert_eq!(
+            tokenize_exec("firefox --new-window %u").unwrap(),
+            ["firefox", "--new-window"]
+        );
+        assert_eq!(
+            tokenize_exec(r#""/opt/My App/app" --name "a \"b\" \\c" """#).unwrap(),
+            ["/opt/My App/app", "--name", r#"a "b" \c"#, ""]
+        );
+        assert_eq!(tokenize_exec("app 100%%").unwrap(), ["app", "100%"]);
+        assert!(tokenize_exec("app %x").is_err());
+        assert!(tokenize_exec(r#"app "unterminated"#).is_err());
+        assert!(tokenize_exec("  %U ").is_err());
+    }
+
+    #[test]
+    fn systemd_run_command_runs_a_scope() {
+        let command = systemd_run_command("firefox --new-window");
+        assert_eq!(command.get_program(), "systemd-run");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "--user",
+                "--scope",
+                "--collect",
+                "--quiet",
+                "--",
+                "sh",
+                "-c",
+                "firefox --new-window"
+            ]
+        );
+    }
+
+    #[test]
+    fn launch_errors_say_what_went_wrong() {
+        for backend in [LauncherBackend::Direct, LauncherBackend::Niri] {
+            assert!(matches!(
+                prepare("app --icon %x", backend),
+                Err(LaunchError::InvalidExec(_))
+            ));
+        }
+        assert!(matches!(
+            prepare("app %i", LauncherBackend::Direct),
+            Err(LaunchError::InvalidExec(_))
+        ));
+        assert!(matches!(
+            prepare("firefox %U", LauncherBackend::Direct),
+            Ok(PreparedLaunch::Command(_))
+        ));
+
+        assert!(matches!(
+            LaunchError::spawn("niri", io::Error::from(ErrorKind::NotFound)),
+            LaunchError::NotFound { program: "niri" }
+        ));
+        assert!(matches!(
+            LaunchError::spawn("niri", io::Error::from(ErrorKind::PermissionDenied)),
+            LaunchError::Spawn {
+                program: "niri",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn sway_command_quotes_every_argument() {
+        let argv =
+            tokenize_exec(r#""/opt/My App/app" --name "it's; done" a,b plain-arg """#).unwrap();
+        let command = sway_command(&argv);
+        assert_eq!(command.get_program(), "swaymsg");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "--",
+                "exec",
+                r#"'/opt/My App/app' --name 'it'\''s; done' 'a,b' plain-arg ''"#
+            ]
+        );
+    }
+
+    #[test]
+    fn sway_reply_errors() {
+        assert_eq!(sway_reply_error(br#"[{"success": true}]"#), None);
+        assert_eq!(
+            sway_reply_error(br#"[{"success": false, "error": "x"}]"#).as_deref(),
+            Some("x")
+        );
+        assert_eq!(
+            sway_reply_error(br#"[{"success": true}, {"success": false}]"#).as_deref(),
+            Some("command failed")
+        );
+        // not a reply at all, the exit status has to tell
+        assert_eq!(sway_reply_error(b"garbage"), None);
+    }
+
+    #[test]
+    fn hyprctl_command_dispatches_exec() {
+        let command = hyprctl_command("firefox --new-window");
+        assert_eq!(command.get_program(), "hyprctl");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["dispatch", "exec", "--", "firefox --new-window"]
+        );
+    }
+
+    #[test]
+    fn hyprctl_reply_errors() {
+        assert_eq!(hyprctl_reply_error(b"ok\n"), None);
+        assert_eq!(
+            hyprctl_reply_error(b"Invalid dispatcher\n").as_deref(),
+            Some("Invalid dispatcher")
+        );
+        // empty, so stderr has to tell
+        assert_eq!(hyprctl_reply_error(b"").as_deref(), Some(""));
+    }
+}
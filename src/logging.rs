@@ -0,0 +1,69 @@
+use std::io::Write;
+
+use log::LevelFilter;
+
+use crate::cli::{Args, LogFormat};
+
+pub fn init(args: &Args) {
+    let mut logger =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+
+    if let Some(level) = level_override(args) {
+        logger.filter_level(level);
+    }
+
+    if args.log_format == LogFormat::Json {
+        logger.format(|buf, record| {
+            writeln!(
+                buf,
+                r#"{{"timestamp":"{}","level":"{}","target":"{}","message":"{}"}}"#,
+                buf.timestamp(),
+                record.level(),
+                json_escape(record.target()),
+                json_escape(&record.args().to_string()),
+            )
+        });
+    }
+
+    logger.init();
+}
+
+/// Flags win over `$RUST_LOG`, which wins over the default of `info`.
+fn level_override(args: &Args) -> Option<LevelFilter> {
+    if args.quiet {
+        Some(LevelFilter::Warn)
+    } else if args.verbose {
+        Some(LevelFilter::Debug)
+    } else {
+        None
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_escape_escapes_quotes_and_control_characters() {
+        assert_eq!(json_escape("plain ünïcode"), "plain ünïcode");
+        assert_eq!(json_escape(r#"say "hi" \o/"#), r#"say \"hi\" \\o/"#);
+        assert_eq!(json_escape("a\nb\r\tc"), "a\\nb\\r\\tc");
+        assert_eq!(json_escape("\u{1b}[0m"), "\\u001b[0m");
+    }
+}
@@ -1,24 +1,70 @@
+mod accent;
+mod cli;
+mod cluster;
+mod color;
+mod config;
+mod control;
+mod cycle;
 mod desktop;
 mod gpu;
+mod hint;
+mod icon_cache;
+mod launch;
+mod logging;
+mod menu;
+mod recent;
+mod search;
+mod sprites;
+mod text;
+mod time_of_day;
+mod touch;
+mod usage;
+mod wallpaper;
 
 use std::{
+    cell::RefCell,
     collections::HashMap,
+    fmt::Write,
+    io::Write as _,
+    os::unix::net::UnixListener,
+    path::Path,
     time::{Duration, Instant},
 };
 
+use clap::Parser;
 use eyre::{Context, Result, bail, eyre};
 use freedesktop_file_parser::EntryType;
-use log::{error, info, warn};
+use image::RgbaImage;
+use log::{debug, error, info, warn};
 use palette::Oklab;
+use serde::Serialize;
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState},
     output::{OutputHandler, OutputState},
-    reexports::{calloop::EventLoop, calloop_wayland_source::WaylandSource},
+    reexports::protocols::wp::{
+        fractional_scale::v1::client::{
+            wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+            wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+        },
+        viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+    },
+    reexports::{
+        calloop::{
+            EventLoop, Interest, Mode, PostAction,
+            channel::{self, Sender},
+            generic::Generic,
+            signals::{Signal, Signals},
+            timer::{TimeoutAction, Timer},
+        },
+        calloop_wayland_source::WaylandSource,
+    },
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
     seat::{
         SeatHandler, SeatState,
+        keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers, RawModifiers},
         pointer::{BTN_LEFT, BTN_RIGHT, PointerEventKind, PointerHandler},
+        touch::TouchHandler,
     },
     shell::{
         WaylandSurface,
@@ -29,92 +75,1661 @@ use smithay_client_toolkit::{
     shm::{Shm, ShmHandler},
 };
 use wayland_client::{
-    Connection, QueueHandle,
+    Connection, Dispatch, QueueHandle,
     globals::registry_queue_init,
-    protocol::{wl_buffer, wl_output::WlOutput, wl_pointer::WlPointer, wl_seat::WlSeat},
+    protocol::{
+        wl_buffer,
+        wl_keyboard::WlKeyboard,
+        wl_output::{Transform, WlOutput},
+        wl_pointer::WlPointer,
+        wl_seat::WlSeat,
+        wl_surface::WlSurface,
+        wl_touch::WlTouch,
+    },
 };
 
 use crate::{
-    desktop::DesktopEntries,
+    cli::Args,
+    cluster::Clusters,
+    config::{
+        Config, DuplicateStrategy, Edge, GradientConfig, RenderMode, SurfaceConfig, SurfaceLayer,
+    },
+    control::Command,
+    cycle::ScrollCycle,
+    desktop::{DesktopEntries, DesktopEntry},
     gpu::{AppGpuState, SurfaceGpuState},
+    launch::{LaunchError, PreparedLaunch},
+    menu::ActionMenu,
+    recent::RecentApps,
+    search::AppSearch,
+    sprites::Sprite,
+    touch::TapTracker,
+    usage::Usage,
+    wallpaper::Wallpaper,
 };
 
+/// Lets wrappers tell an unsupported compositor (or X11) apart from a crash, retrying won't help.
+const EXIT_NO_LAYER_SHELL: i32 = 3;
+
+/// Gives up after reconnecting this many times in a row.
+const MAX_RECONNECTS: u32 = 5;
+/// A connection that held up for this long starts the count of reconnects over.
+const RECONNECT_RESET: Duration = Duration::from_secs(60);
+
 fn main() -> Result<()> {
-    env_logger::builder()
-        .filter(None, log::LevelFilter::Info)
-        .init();
+    let args = Args::parse();
 
-    let now = Instant::now();
-    let desktop_files = desktop::find_desktop_files().wrap_err("loading .desktop files")?;
-    info!(
-        "Loaded {} desktop icons in {:?}",
-        desktop_files.count(),
-        now.elapsed()
-    );
+    logging::init(&args);
+
+    if let Some(command) = args.send {
+        print!("{}", control::send(command)?);
+        return Ok(());
+    }
+
+    let mut reconnects = 0;
+    loop {
+        let started = Instant::now();
+        match run(&args, reconnects > 0)? {
+            Run::Exited => return Ok(()),
+            Run::Disconnected(err) => {
+                if started.elapsed() >= RECONNECT_RESET {
+                    reconnects = 0;
+                }
+                let Some(delay) = reconnect_delay(reconnects) else {
+                    return Err(err.wrap_err(format!("giving up after {reconnects} reconnects")));
+                };
+                error!("Lost the Wayland connection, reconnecting in {delay:?}: {err:?}");
+                std::thread::sleep(delay);
+                reconnects += 1;
+            }
+        }
+    }
+}
+
+/// How [`run`] ended, it returns errors that reconnecting won't fix.
+enum Run {
+    Exited,
+    /// The Wayland connection broke, the compositor might have crashed or restarted.
+    Disconnected(eyre::Report),
+}
+
+/// How long to wait before reconnecting for the `attempt`th time in a row (counting from 0),
+/// doubling every time. `None` once it's time to give up.
+fn reconnect_delay(attempt: u32) -> Option<Duration> {
+    (attempt < MAX_RECONNECTS).then(|| Duration::from_millis(500) * 2_u32.pow(attempt))
+}
+
+/// Loads everything and runs until exiting or losing the connection, starting from scratch
+/// every time so a reconnect gets new globals, outputs and surfaces.
+fn run(args: &Args, reconnecting: bool) -> Result<Run> {
+    let mut config = Config::load(args.config.as_deref()).wrap_err("loading config")?;
+    args.apply_to(&mut config);
+    config.validate()?;
+
+    // the other modes need the apps right away
+    let load_in_background = config.icons.load_in_background
+        && !args.list_apps
+        && args.dump_json.is_none()
+        && !args.once;
+    let desktop_files = if load_in_background {
+        DesktopEntries::empty(&config.matching)
+    } else {
+        // the outputs aren't known yet, the apps are loaded again if one has a larger scale
+        load_apps(&config, 120)?
+    };
+
+    if args.list_apps {
+        print!("{}", format_app_list(&desktop_files));
+        return Ok(Run::Exited);
+    }
+    if let Some(path) = &args.dump_json {
+        let json = app_list_json(&desktop_files)?;
+        match path {
+            Some(path) => std::fs::write(path, json)
+                .wrap_err_with(|| format!("writing {}", path.display()))?,
+            None => println!("{json}"),
+        }
+        return Ok(Run::Exited);
+    }
+
+    let usage = Usage::load();
+    let (clusters, seed_colors, seed_weights) = voronoi_seeds(&desktop_files, &config, &usage);
+
+    let wallpaper = match &config.image.path {
+        Some(path) => Some(Wallpaper::load(path).wrap_err("loading wallpaper image")?),
+        None if config.render.mode == RenderMode::Image => {
+            bail!("the image render mode requires an image path")
+        }
+        None => None,
+    };
+
+    if args.once {
+        let (width, height) = args.size;
+        let output = args.output.as_deref().expect("clap requires --output");
+        let accent = if config.gradient.accent {
+            accent::read().unwrap_or_else(|err| {
+                warn!("Not using the accent color: {err:?}");
+                None
+            })
+        } else {
+            None
+        };
+        let offset = gradient_offset(&config, accent);
+        let image = if args.gpu {
+            let gpu = AppGpuState::new(
+                &desktop_files,
+                &seed_colors,
+                &seed_weights,
+                &config.matching,
+                wallpaper.as_ref(),
+            )?;
+            let mut target = SurfaceGpuState::offscreen(&gpu, &config);
+            target.resize(&gpu, width, height, 1.0, Transform::Normal, &config)?;
+            target.set_gradient_offset(&gpu, offset);
+            target.set_voronoi_progress(&gpu, idle_voronoi_progress(&config));
+            target.set_icon_sprites(
+                &gpu,
+                &icon_sprites(&desktop_files, &config, offset, (width, height)),
+            );
+            target.render_to_image(&gpu)?
+        } else {
+            render_cpu(
+                (width, height),
+                &config,
+                offset,
+                &seed_colors,
+                &seed_weights,
+                wallpaper.as_ref(),
+            )
+        };
+        image
+            .save_with_format(output, image::ImageFormat::Png)
+            .wrap_err_with(|| format!("writing {}", output.display()))?;
+        info!("Rendered a {width}x{height} frame to {}", output.display());
+        return Ok(Run::Exited);
+    }
+
+    let conn = match Connection::connect_to_env() {
+        Ok(conn) => conn,
+        // a restarting compositor might not have its socket up yet
+        Err(err) if reconnecting => {
+            return Ok(Run::Disconnected(
+                eyre::Report::new(err).wrap_err("can't connect to Wayland socket"),
+            ));
+        }
+        // there's no X11 backend, but `--once` works anywhere
+        Err(err) if std::env::var_os("DISPLAY").is_some() => {
+            error!(
+                "Can't connect to a Wayland compositor ({err}), and X11 isn't supported. Use \
+                 --once to render the wallpaper into an image for an X11 wallpaper setter instead."
+            );
+            std::process::exit(EXIT_NO_LAYER_SHELL);
+        }
+        Err(err) => return Err(err).wrap_err("can't connect to Wayland socket"),
+    };
+
+    let (globals, event_queue) = registry_queue_init(&conn).wrap_err("initializing connection")?;
+
+    let mut event_loop: EventLoop<App> = EventLoop::try_new().wrap_err("creating event loop")?;
+    let qh: &QueueHandle<App> = &event_queue.handle();
+
+    let layer_shell = match LayerShell::bind(&globals, qh) {
+        Ok(layer_shell) => layer_shell,
+        Err(err) => {
+            error!(
+                "The compositor doesn't support zwlr_layer_shell_v1 ({err}), which is needed to \
+                 put a surface behind all windows. It is supported by wlroots-based compositors \
+                 (Sway, Hyprland, river, ...), niri, KDE Plasma and COSMIC, but not by GNOME."
+            );
+            std::process::exit(EXIT_NO_LAYER_SHELL);
+        }
+    };
+
+    let gradient_offset = gradient_offset(&config, None);
+    let (pick_sender, pick_channel) = channel::channel();
+    let (accent_sender, accent_channel) = channel::channel();
+    let (apps_sender, apps_channel) = channel::channel();
+    let (launch_sender, launch_channel) = channel::channel();
+    let mut app = App {
+        conn: conn.clone(),
+        qh: qh.clone(),
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, qh),
+        compositor_state: CompositorState::bind(&globals, qh)
+            .wrap_err("failed to bind wl_compositor global")?,
+        layer_shell,
+        shm: Shm::bind(&globals, qh).wrap_err("failed to bind shm")?,
+        // both are needed for fractional scaling, without them we fall back to integer scales
+        fractional_scale_manager: globals.bind(qh, 1..=1, ()).ok(),
+        viewporter: globals.bind(qh, 1..=1, ()).ok(),
+        seat_state: SeatState::new(&globals, qh),
+
+        gpu: AppGpuState::new(
+            &desktop_files,
+            &seed_colors,
+            &seed_weights,
+            &config.matching,
+            wallpaper.as_ref(),
+        )?,
+
+        args: args.clone(),
+        config,
+        desktop_files,
+        clusters,
+        usage: RefCell::new(usage),
+        prepared_launches: RefCell::new(HashMap::new()),
+        last_prefetched: RefCell::new(None),
+        wallpaper,
+        last_reload: None,
+        started: Instant::now(),
+        loading: load_in_background,
+        notified_ready: false,
+        icon_scale_120: 120,
+        apps: apps_sender,
+        gradient_offset,
+        accent: None,
+        accent_sender: Some(accent_sender),
+        picks: pick_sender,
+        pending_picks: 0,
+        launched: launch_sender,
+        action_menu: None,
+        app_search: None,
+        scroll_cycle: None,
+        overlay_seat: None,
+        last_launch: RefCell::new(None),
+        pointers: HashMap::new(),
+        keyboards: HashMap::new(),
+        touches: HashMap::new(),
+        keyboard_focus: None,
+        layer_surfaces: Vec::new(),
+        toggled_on: false,
+        exit: false,
+    };
+
+    WaylandSource::new(conn.clone(), event_queue)
+        .insert(event_loop.handle())
+        .map_err(|err| eyre!("{:?}", err))
+        .wrap_err("failed to register wayland event source")?;
+
+    event_loop
+        .handle()
+        .insert_source(pick_channel, |event, _, app| {
+            if let channel::Event::Msg((pick, result)) = event {
+                app.finish_pick(pick, result);
+            }
+        })
+        .map_err(|err| eyre!("{:?}", err))
+        .wrap_err("failed to register pick channel")?;
+
+    event_loop
+        .handle()
+        .insert_source(launch_channel, |event, _, app| {
+            if let channel::Event::Msg(id) = event {
+                app.finish_launch(&id);
+            }
+        })
+        .map_err(|err| eyre!("{:?}", err))
+        .wrap_err("failed to register launch channel")?;
+
+    let signals = Signals::new(&[
+        Signal::SIGHUP,
+        Signal::SIGUSR1,
+        Signal::SIGUSR2,
+        Signal::SIGTERM,
+        Signal::SIGINT,
+    ])
+    .wrap_err("failed to create signal source")?;
+    event_loop
+        .handle()
+        .insert_source(signals, |event, _, app| match event.signal() {
+            Signal::SIGHUP => app.reload_config(),
+            Signal::SIGUSR1 => app.log_status(),
+            Signal::SIGUSR2 => app.launch_at_center(),
+            signal => {
+                info!("Received {signal}, exiting");
+                app.exit = true;
+            }
+        })
+        .map_err(|err| eyre!("{:?}", err))
+        .wrap_err("failed to register signal source")?;
+
+    // without it everything but `--send` still works
+    match control::listen() {
+        Ok(listener) => {
+            event_loop
+                .handle()
+                .insert_source(
+                    Generic::new(listener, Interest::READ, Mode::Level),
+                    |_, listener, app| {
+                        app.accept_commands(listener.as_ref());
+                        Ok(PostAction::Continue)
+                    },
+                )
+                .map_err(|err| eyre!("{:?}", err))
+                .wrap_err("failed to register control socket")?;
+        }
+        Err(err) => warn!("Not listening for commands: {err:?}"),
+    }
+
+    event_loop
+        .handle()
+        .insert_source(apps_channel, |event, _, app| {
+            if let channel::Event::Msg(result) = event {
+                app.finish_loading(result);
+            }
+        })
+        .map_err(|err| eyre!("{:?}", err))
+        .wrap_err("failed to register app loading channel")?;
+    if load_in_background {
+        app.load_apps_in_background();
+    }
+
+    app.watch_accent();
+    event_loop
+        .handle()
+        .insert_source(accent_channel, |event, _, app| {
+            if let channel::Event::Msg(accent) = event {
+                app.accent = accent;
+                app.update_gradient_offset();
+            }
+        })
+        .map_err(|err| eyre!("{:?}", err))
+        .wrap_err("failed to register accent color channel")?;
+
+    // steps through the fade out of the hint, the overlay is redrawn on every step
+    let hint_duration = Duration::from_secs_f32(app.config.hint.duration_secs);
+    if !hint_duration.is_zero() {
+        event_loop
+            .handle()
+            .insert_source(
+                Timer::from_duration(hint_duration.saturating_sub(hint::FADE)),
+                move |_, _, app| {
+                    app.redraw_idle_overlays();
+                    if app.started.elapsed() >= hint_duration {
+                        TimeoutAction::Drop
+                    } else {
+                        TimeoutAction::ToDuration(Duration::from_millis(50))
+                    }
+                },
+            )
+            .map_err(|err| eyre!("{:?}", err))
+            .wrap_err("failed to register hint timer")?;
+    }
+
+    // always running so enabling it in a reloaded config works
+    let time_of_day_interval = Duration::from_secs(app.config.time_of_day.update_interval);
+    event_loop
+        .handle()
+        .insert_source(Timer::from_duration(time_of_day_interval), |_, _, app| {
+            app.update_gradient_offset();
+            TimeoutAction::ToDuration(Duration::from_secs(app.config.time_of_day.update_interval))
+        })
+        .map_err(|err| eyre!("{:?}", err))
+        .wrap_err("failed to register time of day timer")?;
+
+    if app.config.render.buffer_scale.is_some() && app.viewporter.is_none() {
+        warn!("render.buffer_scale needs wp_viewporter, using the output's scale instead");
+    }
+
+    // there's no surface to wait for, it's up as soon as it can be toggled
+    if app.config.surface.toggle {
+        app.notify_ready();
+    }
+
+    while !app.exit {
+        // the wayland source is the only one that fails, when the connection breaks
+        if let Err(err) = event_loop.dispatch(app.dispatch_timeout(), &mut app) {
+            return Ok(Run::Disconnected(
+                eyre::Report::new(err).wrap_err("error during event loop"),
+            ));
+        }
+        app.gpu.poll();
+    }
+
+    notify_systemd(sd_notify::NotifyState::Stopping);
+    app.shutdown();
+    conn.flush().wrap_err("flushing connection")?;
+
+    Ok(Run::Exited)
+}
+
+struct App {
+    conn: Connection,
+    qh: QueueHandle<App>,
+    registry_state: RegistryState,
+    output_state: OutputState,
+    compositor_state: CompositorState,
+    layer_shell: LayerShell,
+    shm: Shm,
+    fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    viewporter: Option<WpViewporter>,
+    seat_state: SeatState,
+
+    gpu: AppGpuState,
+
+    args: Args,
+    config: Config,
+    desktop_files: DesktopEntries,
+    /// Set if the voronoi diagram shows clusters instead of single apps.
+    clusters: Option<Clusters>,
+    /// Updated by every launch, which only borrows the app since the entry borrows it too.
+    usage: RefCell<Usage>,
+    /// How to launch the apps that were hovered or launched, by desktop file id. Cleared when
+    /// the config or the apps change.
+    prepared_launches: RefCell<HashMap<String, PreparedLaunch>>,
+    /// The app the pointer hovered last, so moving over the same app doesn't prepare it again.
+    last_prefetched: RefCell<Option<String>>,
+    wallpaper: Option<Wallpaper>,
+    last_reload: Option<Instant>,
+    /// When the hint started showing, see `hint.duration_secs`.
+    started: Instant,
+    /// The apps are still loading in the background, see `icons.load_in_background`.
+    loading: bool,
+    /// systemd was told that the wallpaper is up.
+    notified_ready: bool,
+    /// The output scale in 120ths the icons were last looked up for.
+    icon_scale_120: u32,
+    /// Apps loaded in the background are sent here.
+    apps: Sender<Result<DesktopEntries>>,
+    /// Added to the generated gradient, see [`time_of_day`] and [`accent`].
+    gradient_offset: Oklab,
+    /// The accent color of the desktop, if it has one.
+    accent: Option<Oklab>,
+    /// Taken once the accent color is followed, see [`Self::watch_accent`].
+    accent_sender: Option<Sender<Option<Oklab>>>,
+    /// Picks are read back from the GPU asynchronously and come back through here.
+    picks: Sender<(Pick, PickResult)>,
+    pending_picks: usize,
+    /// The ids of apps that were actually launched come back through here, which for DBus
+    /// activation is only known once the app's service started.
+    launched: Sender<String>,
+    action_menu: Option<ActionMenu>,
+    app_search: Option<AppSearch>,
+    scroll_cycle: Option<ScrollCycle>,
+    /// The seat whose pointer opened the action menu or scroll cycle. The pointers of other
+    /// seats don't hover, scroll or close them, so they don't get in each other's way.
+    overlay_seat: Option<WlSeat>,
+    /// The id of the app last launched and when, see `launcher.debounce_ms`.
+    last_launch: RefCell<Option<(String, Instant)>>,
+    pointers: HashMap<WlSeat, WlPointer>,
+    keyboards: HashMap<WlSeat, WlKeyboard>,
+    touches: HashMap<WlSeat, (WlTouch, TapTracker)>,
+    keyboard_focus: Option<WlSurface>,
+    layer_surfaces: Vec<OutputSurface>,
+    /// Whether the surfaces are shown, with `surface.toggle`.
+    toggled_on: bool,
+    exit: bool,
+}
+
+impl App {
+    /// Everything that changes what's shown arrives as an event (Wayland, signals, timers),
+    /// so the loop can sleep until then. Only GPU readbacks need polling to make progress.
+    fn dispatch_timeout(&self) -> Option<Duration> {
+        (self.pending_picks > 0).then_some(Duration::from_millis(2))
+    }
+
+    /// Tells systemd that the wallpaper is up once the first surface is configured, for
+    /// `Type=notify` units.
+    fn notify_ready(&mut self) {
+        if first_time(&mut self.notified_ready) {
+            notify_systemd(sd_notify::NotifyState::Ready);
+        }
+    }
+
+    fn shutdown(&mut self) {
+        info!("Destroying {} layer surfaces", self.layer_surfaces.len());
+        // dropping the last reference to a layer surface destroys it
+        self.layer_surfaces.clear();
+    }
+
+    /// Asks the GPU which app is shown at the pixel, [`Self::finish_pick`] is called with the
+    /// answer. The CPU can't know it exactly, e.g. the GPU filters the wallpaper image.
+    /// `position` is in surface-local (logical) coordinates, as the pointer reports them.
+    /// `seat` is the seat of the pointer that picked, if it was one.
+    fn pick(
+        &mut self,
+        kind: PickKind,
+        surface: &WlSurface,
+        position: (f64, f64),
+        seat: Option<WlSeat>,
+    ) {
+        if self.loading {
+            info!("The apps are still loading, not launching anything");
+            return;
+        }
+        let Some(output_surface) = self
+            .layer_surfaces
+            .iter_mut()
+            .find(|output_surface| output_surface.layer_surface.wl_surface() == surface)
+        else {
+            return;
+        };
+        let position = clamp_to_surface(position, (output_surface.width, output_surface.height));
+        let pick = Pick {
+            kind,
+            surface: surface.clone(),
+            position: (position.0 as u32, position.1 as u32),
+            size: (output_surface.width, output_surface.height),
+            seat,
+        };
+        let picks = self.picks.clone();
+        self.pending_picks += 1;
+        output_surface.gpu.pick(&self.gpu, position, move |result| {
+            // the receiver only goes away when exiting
+            let _ = picks.send((pick, result));
+        });
+    }
+
+    fn finish_pick(&mut self, pick: Pick, result: PickResult) {
+        self.pending_picks -= 1;
+        // the output went away while the GPU was working on it
+        let Some(surface) = self
+            .layer_surfaces
+            .iter()
+            .find(|surface| surface.layer_surface.wl_surface() == &pick.surface)
+        else {
+            return;
+        };
+        let color = surface.color_at(pick.position, self.gradient_offset, self.wallpaper.as_ref());
+        let entry = match (result, &self.clusters) {
+            (Ok(Some(index)), Some(clusters)) => {
+                let members = clusters.members.get(index).map_or(&[][..], Vec::as_slice);
+                if let &[member] = members {
+                    self.desktop_files.nth(member)
+                } else {
+                    // the cluster has to be narrowed down to an app first
+                    let mut entries = members
+                        .iter()
+                        .filter_map(|&member| self.desktop_files.nth(member))
+                        .collect::<Vec<_>>();
+                    entries.sort_by(|a, b| {
+                        let distance = |entry: &DesktopEntry| {
+                            self.desktop_files.diff_color(entry.avg_icon_color, color)
+                        };
+                        distance(a).total_cmp(&distance(b))
+                    });
+                    self.scroll_cycle = Some(ScrollCycle::new(
+                        pick.surface,
+                        entries,
+                        color,
+                        pick.position,
+                        pick.size,
+                    ));
+                    self.draw_scroll_cycle();
+                    self.overlay_seat = pick.seat;
+                    return;
+                }
+            }
+            (Ok(index), _) => index.and_then(|index| self.desktop_files.nth(index)),
+            (Err(err), _) => {
+                warn!("Failed to read back the picked app, matching on the CPU instead: {err}");
+                self.desktop_files.find_entry(color)
+            }
+        };
+        let Some(entry) = entry else {
+            debug!("No app matches the color at {:?}", pick.position);
+            return;
+        };
+
+        match pick.kind {
+            PickKind::Launch => {
+                // no feedback for clicks that don't launch anything
+                if !self.launch_entry(entry) {
+                    return;
+                }
+                self.start_ripple(&pick.surface, pick.position);
+                self.redraw_idle_overlays();
+                self.hide();
+            }
+            PickKind::ActionMenu => {
+                if !entry.actions.is_empty() {
+                    self.action_menu = Some(ActionMenu::new(
+                        pick.surface,
+                        entry,
+                        pick.position,
+                        pick.size,
+                    ));
+                    self.draw_action_menu();
+                    self.overlay_seat = pick.seat;
+                }
+            }
+        }
+    }
+
+    /// Whether the app is being launched. Launching the same app again right after is
+    /// ignored, however it was launched.
+    fn launch_entry(&self, entry: &DesktopEntry) -> bool {
+        let EntryType::Application(app) = &entry.file.entry.entry_type else {
+            return false;
+        };
+        let now = Instant::now();
+        let debounce = Duration::from_millis(self.config.launcher.debounce_ms);
+        if is_bounce(self.last_launch.borrow().as_ref(), &entry.id, now, debounce) {
+            debug!("Ignoring a repeated launch of {}", entry.id);
+            return false;
+        }
+        self.last_launch.replace(Some((entry.id.clone(), now)));
+        if self.config.launcher.dry_run {
+            let command = match &app.exec {
+                Some(exec) => launch::tokenize_exec(exec).map_or_else(
+                    |err| format!("invalid Exec {exec}: {err}"),
+                    |argv| format!("{argv:?}"),
+                ),
+                None => "no Exec".into(),
+            };
+            info!(
+                "Dry run, not launching {} ({}, DBus activatable: {}): {command}",
+                entry.id,
+                entry.file.entry.name.default,
+                entry.file.entry.dbus_activatable == Some(true),
+            );
+            return false;
+        }
+        let Some(prepared) = self.prepare_launch(entry) else {
+            return false;
+        };
+        let launched = self.launched.clone();
+        let id = entry.id.clone();
+        let result = prepared.and_then(|prepared| {
+            launch::launch_prepared(prepared, self.config.launcher.backend, move || {
+                // the event loop is gone if we are exiting, then there's nothing to record
+                let _ = launched.send(id);
+            })
+        });
+        self.report_launch(&entry.id, result)
+    }
+
+    /// The ripple showing where an app was launched from. `position` is in surface-local
+    /// (logical) coordinates.
+    fn start_ripple(&mut self, surface: &WlSurface, (x, y): (u32, u32)) {
+        let Some(output_surface) = self
+            .layer_surfaces
+            .iter_mut()
+            .find(|output_surface| output_surface.layer_surface.wl_surface() == surface)
+        else {
+            return;
+        };
+        output_surface.ripple = Some(Ripple {
+            position: (f64::from(x), f64::from(y)),
+            start: Instant::now(),
+        });
+        output_surface.request_redraw(&self.gpu, &self.qh);
+    }
+
+    /// Records the launch of the app, only once it actually launched so apps failing to
+    /// launch don't get more popular.
+    fn finish_launch(&mut self, id: &str) {
+        self.usage
+            .borrow_mut()
+            .record(id, self.config.render.popularity_half_life_days);
+        self.redraw_idle_overlays();
+    }
+
+    /// How to launch the app, from the cache if it was hovered before. `None` if it has no
+    /// way to be launched.
+    fn prepare_launch(&self, entry: &DesktopEntry) -> Option<Result<PreparedLaunch, LaunchError>> {
+        if let Some(prepared) = self.prepared_launches.borrow().get(&entry.id) {
+            return Some(Ok(prepared.clone()));
+        }
+        let EntryType::Application(app) = &entry.file.entry.entry_type else {
+            return None;
+        };
+        let dbus = (entry.file.entry.dbus_activatable == Some(true))
+            .then(|| launch::prepare_dbus(&entry.id, app.exec.clone()))
+            .and_then(|prepared| {
+                prepared
+                    .inspect_err(|err| {
+                        warn!(
+                            "Can't activate {} over DBus, using its Exec instead: {err:?}",
+                            entry.id
+                        )
+                    })
+                    .ok()
+            });
+        let prepared = match dbus {
+            Some(prepared) => Ok(prepared),
+            None => launch::prepare(app.exec.as_deref()?, self.config.launcher.backend),
+        };
+        if let Ok(prepared) = &prepared {
+            self.prepared_launches
+                .borrow_mut()
+                .insert(entry.id.clone(), prepared.clone());
+        }
+        Some(prepared)
+    }
+
+    /// Prepares launching the app at the color before it's clicked, so the click only has to
+    /// launch it. Mostly helps DBus activation, which needs a session bus connection.
+    fn prefetch_launch(&self, color: Oklab) {
+        if self.config.launcher.dry_run {
+            return;
+        }
+        let Some(entry) = self.desktop_files.find_entry(color) else {
+            return;
+        };
+        if self.last_prefetched.borrow().as_ref() == Some(&entry.id) {
+            return;
+        }
+        self.last_prefetched.replace(Some(entry.id.clone()));
+        if self.prepared_launches.borrow().contains_key(&entry.id) {
+            return;
+        }
+        debug!("Preparing to launch {}", entry.id);
+        if let Some(Ok(PreparedLaunch::Dbus { .. })) = self.prepare_launch(entry) {
+            launch::connect_session_bus();
+        }
+    }
+
+    /// Whether the program was launched.
+    fn launch(&self, exec: &str) -> bool {
+        if self.config.launcher.dry_run {
+            info!("Dry run, not launching {exec}");
+            return false;
+        }
+        self.report_launch(exec, launch::launch(exec, self.config.launcher.backend))
+    }
+
+    /// Logs why `what` wasn't launched, if it wasn't. Whether it was.
+    fn report_launch(&self, what: &str, result: Result<(), LaunchError>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(err @ LaunchError::NotFound { .. }) => {
+                error!(
+                    "Failed to launch program: {what}: {err}, pick another launcher.backend \
+                     in the config"
+                );
+                false
+            }
+            Err(err) => {
+                error!("Failed to launch program: {what}: {err}");
+                false
+            }
+        }
+    }
+
+    /// Replaces the overlay of the surface and redraws it. Without an overlay, the surface
+    /// shows what [`Self::idle_overlay`] says instead.
+    fn set_overlay(&mut self, wl_surface: &WlSurface, overlay: Option<(&RgbaImage, (u32, u32))>) {
+        let Some(size) = self
+            .layer_surfaces
+            .iter()
+            .find(|surface| surface.layer_surface.wl_surface() == wl_surface)
+            .map(|surface| (surface.width, surface.height))
+        else {
+            return;
+        };
+        let idle = match overlay {
+            Some(_) => None,
+            None => self.idle_overlay(size),
+        };
+        let overlay = overlay.or(idle.as_ref().map(|(image, origin)| (image, *origin)));
+        if let Some(surface) = self
+            .layer_surfaces
+            .iter_mut()
+            .find(|surface| surface.layer_surface.wl_surface() == wl_surface)
+        {
+            surface.gpu.set_overlay(&self.gpu, overlay);
+            surface.request_redraw(&self.gpu, &self.qh);
+        }
+    }
+
+    /// What's shown when nothing else is: nothing while the apps are loading, a message if
+    /// there are none, the hint while it's still showing, otherwise the recent apps if they
+    /// are shown.
+    fn idle_overlay(&self, surface_size: (u32, u32)) -> Option<(RgbaImage, (u32, u32))> {
+        if self.loading {
+            return None;
+        }
+        if self.desktop_files.count() == 0 {
+            let no_apps = text::label("No apps found");
+            let origin = (
+                surface_size.0.saturating_sub(no_apps.width()) / 2,
+                surface_size.1.saturating_sub(no_apps.height()) / 2,
+            );
+            return Some((no_apps, origin));
+        }
+        let hint_alpha = self.hint_alpha();
+        if hint_alpha > 0.0 {
+            let image = hint::render(
+                &self.config.hint.text,
+                self.desktop_files.count(),
+                hint_alpha,
+            );
+            let origin = hint::origin(surface_size, image.dimensions());
+            return Some((image, origin));
+        }
+        let recent_apps = self.recent_apps()?;
+        Some((recent_apps.render(), recent_apps.origin(surface_size)))
+    }
+
+    /// The opacity of the hint, 0 once it's gone.
+    fn hint_alpha(&self) -> f32 {
+        hint::alpha(
+            self.started.elapsed(),
+            Duration::from_secs_f32(self.config.hint.duration_secs),
+        )
+    }
+
+    /// The most recently launched apps that still exist, `None` if there are none or they
+    /// aren't shown.
+    fn recent_apps(&self) -> Option<RecentApps> {
+        if self.config.recent.count == 0 {
+            return None;
+        }
+        let usage = self.usage.borrow();
+        let entries = usage
+            .recent(self.config.recent.count)
+            .into_iter()
+            .filter_map(|id| self.desktop_files.get(id))
+            .collect::<Vec<_>>();
+        (!entries.is_empty()).then(|| RecentApps::new(&entries, self.config.recent.edge))
+    }
+
+    /// Redraws the surfaces that don't show anything else, after a launch changed the recent
+    /// apps or while the hint fades out.
+    fn redraw_idle_overlays(&mut self) {
+        let idle = self
+            .layer_surfaces
+            .iter()
+            .map(|surface| surface.layer_surface.wl_surface().clone())
+            .filter(|wl_surface| !self.has_overlay(wl_surface))
+            .collect::<Vec<_>>();
+        for wl_surface in idle {
+            self.set_overlay(&wl_surface, None);
+        }
+    }
+
+    /// Whether the action menu, search or scroll cycle is open on the surface.
+    fn has_overlay(&self, wl_surface: &WlSurface) -> bool {
+        self.action_menu
+            .as_ref()
+            .is_some_and(|menu| menu.surface == *wl_surface)
+            || self
+                .app_search
+                .as_ref()
+                .is_some_and(|search| search.surface == *wl_surface)
+            || self
+                .scroll_cycle
+                .as_ref()
+                .is_some_and(|cycle| cycle.surface == *wl_surface)
+    }
+
+    /// Renders the action menu into its surface's overlay.
+    fn draw_action_menu(&mut self) {
+        let Some(menu) = &self.action_menu else {
+            return;
+        };
+        let (wl_surface, image, origin) = (menu.surface.clone(), menu.render(), menu.origin);
+        self.close_app_search();
+        self.close_scroll_cycle();
+        self.set_overlay(&wl_surface, Some((&image, origin)));
+    }
+
+    fn close_action_menu(&mut self) -> Option<ActionMenu> {
+        let menu = self.action_menu.take()?;
+        self.set_overlay(&menu.surface, None);
+        if self.scroll_cycle.is_none() {
+            self.overlay_seat = None;
+        }
+        Some(menu)
+    }
+
+    /// Renders the search query and its best match into its surface's overlay.
+    fn draw_app_search(&mut self) {
+        let Some(search) = &self.app_search else {
+            return;
+        };
+        let Some(surface) = self
+            .layer_surfaces
+            .iter()
+            .find(|surface| *surface.layer_surface.wl_surface() == search.surface)
+        else {
+            return;
+        };
+        let best_match = search::filter_apps(&self.desktop_files, &search.query)
+            .first()
+            .copied();
+        let image = search.render(best_match);
+        let origin = AppSearch::origin((surface.width, surface.height), image.dimensions());
+        let wl_surface = search.surface.clone();
+
+        self.close_action_menu();
+        self.close_scroll_cycle();
+        self.set_overlay(&wl_surface, Some((&image, origin)));
+    }
+
+    fn close_app_search(&mut self) -> Option<AppSearch> {
+        let search = self.app_search.take()?;
+        self.set_overlay(&search.surface, None);
+        Some(search)
+    }
+
+    /// Moves the scroll selection, starting over if the pointer moved to a different color.
+    fn scroll_apps(&mut self, wl_surface: &WlSurface, position: (u32, u32), amount_120: i32) {
+        let Some(surface) = self
+            .layer_surfaces
+            .iter()
+            .find(|surface| surface.layer_surface.wl_surface() == wl_surface)
+        else {
+            return;
+        };
+        let color = surface.color_at(position, self.gradient_offset, self.wallpaper.as_ref());
+        let surface_size = (surface.width, surface.height);
+        let mut cycle = match self.scroll_cycle.take() {
+            Some(cycle) if cycle.surface == *wl_surface && cycle.is_near(color) => cycle,
+            previous => {
+                let candidates = self.desktop_files.nearest(color, cycle::CANDIDATES);
+                if candidates.is_empty() {
+                    debug!("No app matches the color at {position:?}");
+                    self.scroll_cycle = previous;
+                    self.close_scroll_cycle();
+                    return;
+                }
+                ScrollCycle::new(
+                    wl_surface.clone(),
+                    candidates,
+                    color,
+                    position,
+                    surface_size,
+                )
+            }
+        };
+        cycle.scroll(amount_120);
+        self.scroll_cycle = Some(cycle);
+        self.draw_scroll_cycle();
+    }
+
+    /// Renders the apps that can be scrolled through into their surface's overlay.
+    fn draw_scroll_cycle(&mut self) {
+        let Some(cycle) = &self.scroll_cycle else {
+            return;
+        };
+        let (wl_surface, image, origin) = (cycle.surface.clone(), cycle.render(), cycle.origin);
+        self.close_action_menu();
+        self.close_app_search();
+        self.set_overlay(&wl_surface, Some((&image, origin)));
+    }
+
+    /// Closes the action menu and scroll cycle if the seat's pointer opened them.
+    fn close_seat_overlay(&mut self, seat: &WlSeat) {
+        if self.overlay_seat.as_ref() == Some(seat) {
+            self.close_action_menu();
+            self.close_scroll_cycle();
+        }
+    }
+
+    fn close_scroll_cycle(&mut self) -> Option<ScrollCycle> {
+        let cycle = self.scroll_cycle.take()?;
+        self.set_overlay(&cycle.surface, None);
+        if self.action_menu.is_none() {
+            self.overlay_seat = None;
+        }
+        Some(cycle)
+    }
+
+    /// Drops the surfaces along with everything that refers to them. The others keep their
+    /// order and state, so an animation on another output isn't interrupted.
+    fn remove_surfaces(&mut self, remove: impl Fn(&OutputSurface) -> bool) {
+        let removed = self
+            .layer_surfaces
+            .iter()
+            .filter(|surface| remove(surface))
+            .map(|surface| surface.layer_surface.wl_surface().clone())
+            .collect::<Vec<_>>();
+        self.layer_surfaces.retain(|surface| !remove(surface));
+
+        // the overlays went away with the surfaces
+        if self
+            .action_menu
+            .as_ref()
+            .is_some_and(|menu| removed.contains(&menu.surface))
+        {
+            self.action_menu = None;
+        }
+        if self
+            .app_search
+            .as_ref()
+            .is_some_and(|search| removed.contains(&search.surface))
+        {
+            self.app_search = None;
+        }
+        if self
+            .scroll_cycle
+            .as_ref()
+            .is_some_and(|cycle| removed.contains(&cycle.surface))
+        {
+            self.scroll_cycle = None;
+        }
+        if self.action_menu.is_none() && self.scroll_cycle.is_none() {
+            self.overlay_seat = None;
+        }
+        if self
+            .keyboard_focus
+            .as_ref()
+            .is_some_and(|focus| removed.contains(focus))
+        {
+            self.keyboard_focus = None;
+        }
+    }
+
+    fn reload_config(&mut self) {
+        info!("Reloading config");
+        let mut config = match Config::load(self.args.config.as_deref()) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("Failed to reload config, keeping the old one: {err:?}");
+                return;
+            }
+        };
+        self.args.apply_to(&mut config);
+        if let Err(err) = config.validate() {
+            error!("Invalid config, keeping the old one: {err:?}");
+            return;
+        }
+        self.apply_config(config);
+        self.last_reload = Some(Instant::now());
+    }
+
+    /// Starts following the accent color once `gradient.accent` is on, so without it there is
+    /// no session bus connection. It's followed from then on, even if turned off again.
+    fn watch_accent(&mut self) {
+        if self.config.gradient.accent
+            && let Some(sender) = self.accent_sender.take()
+        {
+            accent::watch(sender);
+        }
+    }
+
+    /// Loads the apps on a thread, [`Self::finish_loading`] is called with them.
+    fn load_apps_in_background(&self) {
+        let config = self.config.clone();
+        let scale_120 = self.icon_scale_120;
+        let apps = self.apps.clone();
+        std::thread::spawn(move || {
+            // the app is gone if this fails
+            let _ = apps.send(load_apps(&config, scale_120));
+        });
+    }
+
+    /// Loads the apps again once an output has a larger scale than the icons were looked up
+    /// for, so the icon sprites and thumbnails stay sharp on it.
+    fn update_icon_scale(&mut self) {
+        let scale_120 = self
+            .layer_surfaces
+            .iter()
+            .map(OutputSurface::buffer_scale_120)
+            .max()
+            .unwrap_or(120);
+        if desktop::icon_size(scale_120) <= desktop::icon_size(self.icon_scale_120) {
+            return;
+        }
+        info!(
+            "Loading larger icons for an output with scale {}",
+            scale_120 as f32 / 120.0
+        );
+        self.icon_scale_120 = scale_120;
+        self.load_apps_in_background();
+    }
+
+    /// Swaps in the apps loaded in the background, fading in their colors if they are the
+    /// first ones.
+    fn finish_loading(&mut self, result: Result<DesktopEntries>) {
+        let first = std::mem::replace(&mut self.loading, false);
+        let desktop_files = result.unwrap_or_else(|err| {
+            error!("Failed to load the apps: {err:?}");
+            DesktopEntries::empty(&self.config.matching)
+        });
+        let (clusters, seed_colors, seed_weights) =
+            voronoi_seeds(&desktop_files, &self.config, &self.usage.borrow());
+        self.gpu
+            .set_apps(&desktop_files, &seed_colors, &seed_weights);
+        self.desktop_files = desktop_files;
+        self.clusters = clusters;
+        self.prepared_launches.borrow_mut().clear();
+        self.last_prefetched.replace(None);
+
+        for surface in &mut self.layer_surfaces {
+            surface.gpu.set_apps(&self.gpu);
+            surface.gpu.set_icon_sprites(
+                &self.gpu,
+                &icon_sprites(
+                    &self.desktop_files,
+                    &surface.config,
+                    self.gradient_offset,
+                    surface.physical_size(),
+                ),
+            );
+            if first {
+                surface.reveal =
+                    Reveal::loaded(surface.width != 0, surface.config.render.crossfade_ms);
+                surface
+                    .gpu
+                    .set_voronoi_progress(&self.gpu, surface.shown_voronoi_progress());
+            }
+            surface.request_redraw(&self.gpu, &self.qh);
+        }
+        self.redraw_idle_overlays();
+    }
+
+    /// Launches the app shown at the center of the focused output, or of the first one if
+    /// none has keyboard focus. Meant for a compositor keybind like
+    /// `Mod+Return { spawn "pkill" "-USR2" "colouncher"; }` in niri.
+    fn launch_at_center(&mut self) {
+        let surface = self
+            .layer_surfaces
+            .iter()
+            .find(|surface| {
+                self.keyboard_focus.as_ref() == Some(surface.layer_surface.wl_surface())
+            })
+            .or_else(|| self.layer_surfaces.first());
+        // not configured yet, so there is no center
+        let Some(surface) = surface.filter(|surface| surface.width != 0) else {
+            warn!("No output to launch the app at the center of");
+            return;
+        };
+        let wl_surface = surface.layer_surface.wl_surface().clone();
+        let center = (surface.width as f64 / 2.0, surface.height as f64 / 2.0);
+        info!("Launching the app at the center of the output");
+        self.pick(PickKind::Launch, &wl_surface, center, None);
+    }
+
+    /// Runs the commands of everyone connecting to the control socket, until no one is left.
+    fn accept_commands(&mut self, listener: &UnixListener) {
+        loop {
+            let mut stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => return,
+                Err(err) => {
+                    warn!("Failed to accept a command: {err}");
+                    return;
+                }
+            };
+            let reply = match control::read_command(&stream) {
+                Ok(command) => self.run_command(command),
+                Err(err) => format!("error: {err}\n"),
+            };
+            // the sender gave up waiting, nothing to do about it
+            if let Err(err) = stream.write_all(reply.as_bytes()) {
+                debug!("Failed to reply to a command: {err}");
+            }
+        }
+    }
+
+    /// The reply to a command, starting with `error: ` if it failed.
+    fn run_command(&mut self, command: Command) -> String {
+        match command {
+            Command::Toggle if !self.config.surface.toggle => {
+                "error: surface.toggle is disabled\n".into()
+            }
+            Command::Toggle => {
+                self.toggle();
+                "ok\n".into()
+            }
+            Command::Status => match self.status_json() {
+                Ok(json) => format!("{json}\n"),
+                Err(err) => format!("error: {err}\n"),
+            },
+        }
+    }
+
+    /// What [`Self::log_status`] logs, for `--send status`.
+    fn status_json(&self) -> Result<String> {
+        let outputs = self
+            .layer_surfaces
+            .iter()
+            .map(|surface| OutputStatusJson {
+                name: self
+                    .output_state
+                    .info(&surface.output)
+                    .and_then(|info| info.name),
+                logical_size: [surface.width, surface.height],
+                physical_size: surface.physical_size().into(),
+                scale: surface.buffer_scale_120() as f32 / 120.0,
+                render_mode: surface.config.render.mode,
+            })
+            .collect();
+        let usage = self.usage.borrow();
+        let recent = usage
+            .recent(5)
+            .into_iter()
+            .map(|id| RecentJson {
+                id: id.to_owned(),
+                count: usage.count(id),
+            })
+            .collect();
+        let status = StatusJson {
+            apps: self.desktop_files.count(),
+            loading: self.loading,
+            render_mode: self.config.render.mode,
+            shown: !self.config.surface.toggle || self.toggled_on,
+            seconds_since_reload: self.last_reload.map(|time| time.elapsed().as_secs_f64()),
+            outputs,
+            recent,
+        };
+        serde_json::to_string(&status).wrap_err("serializing the status")
+    }
+
+    /// Shows the surfaces on all outputs if they are hidden, hides them otherwise.
+    fn toggle(&mut self) {
+        if self.toggled_on {
+            self.hide();
+            return;
+        }
+        info!("Showing the launcher");
+        self.toggled_on = true;
+        let qh = self.qh.clone();
+        for output in self.output_state.outputs().collect::<Vec<_>>() {
+            self.add_surface(&qh, output);
+        }
+    }
+
+    /// Hides the surfaces again with `surface.toggle`.
+    fn hide(&mut self) {
+        if !self.toggled_on {
+            return;
+        }
+        info!("Hiding the launcher");
+        self.toggled_on = false;
+        self.remove_surfaces(|_| true);
+    }
+
+    /// Logs what's currently going on, to debug why an app isn't showing up. `--send status`
+    /// gets the same as JSON.
+    fn log_status(&self) {
+        info!(
+            "Status: {} apps, render mode {:?}, last config reload {}",
+            self.desktop_files.count(),
+            self.config.render.mode,
+            match self.last_reload {
+                Some(time) => format!("{:?} ago", time.elapsed()),
+                None => "never".into(),
+            }
+        );
+        for surface in &self.layer_surfaces {
+            let name = self
+                .output_state
+                .info(&surface.output)
+                .and_then(|info| info.name)
+                .unwrap_or_else(|| "<unknown>".into());
+            let (width, height) = surface.physical_size();
+            info!(
+                "Output {name}: {}x{} logical, {width}x{height} pixels at scale {}, render mode {:?}",
+                surface.width,
+                surface.height,
+                surface.buffer_scale_120() as f32 / 120.0,
+                surface.config.render.mode,
+            );
+        }
+        let usage = self.usage.borrow();
+        let recent = usage
+            .recent(5)
+            .into_iter()
+            .map(|id| format!("{id} ({}x)", usage.count(id)))
+            .collect::<Vec<_>>();
+        if !recent.is_empty() {
+            info!("Recently launched: {}", recent.join(", "));
+        }
+    }
+
+    fn apply_config(&mut self, config: Config) {
+        if config.icons != self.config.icons
+            || config.discovery != self.config.discovery
+            || config.render.clusters != self.config.render.clusters
+            || config.matching != self.config.matching
+            || config.categories != self.config.categories
+            || config.render.popularity != self.config.render.popularity
+            || config.render.popularity_half_life_days
+                != self.config.render.popularity_half_life_days
+        {
+            warn!(
+                "Icon, discovery, cluster, popularity, matching and category settings only take \
+                 effect after a restart"
+            );
+        }
+        if config.surface != self.config.surface {
+            warn!("Surface settings only take effect after a restart");
+        }
+        if config.render.only_outputs != self.config.render.only_outputs {
+            warn!("render.only_outputs only applies to outputs connected after the change");
+        }
+        if config.image.path != self.config.image.path {
+            warn!("The wallpaper image only changes after a restart");
+        }
+        if config.keyboard != self.config.keyboard {
+            warn!("Keyboard settings only take effect after a restart");
+        }
+        if config.hint.duration_secs != self.config.hint.duration_secs {
+            warn!("hint.duration_secs only takes effect after a restart");
+        }
+        let needs_resize = config.render.buffer_scale != self.config.render.buffer_scale
+            || config.render.match_transform != self.config.render.match_transform
+            || config.render.present_mode != self.config.render.present_mode
+            || config.render.frame_latency != self.config.render.frame_latency;
+        let recent_changed = config.recent != self.config.recent;
+        self.config = config;
+        // the backend might have changed
+        self.prepared_launches.borrow_mut().clear();
+        self.last_prefetched.replace(None);
+        self.reconfigure_surfaces();
+        self.watch_accent();
+        self.update_gradient_offset();
+
+        if needs_resize {
+            let wl_surfaces = self
+                .layer_surfaces
+                .iter()
+                .map(|surface| surface.layer_surface.wl_surface().clone())
+                .collect::<Vec<_>>();
+            for wl_surface in wl_surfaces {
+                self.resize_surface(&wl_surface);
+            }
+            // render.buffer_scale might have gone up
+            self.update_icon_scale();
+        }
+        if recent_changed {
+            self.redraw_idle_overlays();
+        }
+    }
+
+    /// Applies the output rules again, after the config or an output's name changed.
+    fn reconfigure_surfaces(&mut self) {
+        for surface in &mut self.layer_surfaces {
+            let config = output_config(&self.output_state, &self.config, &surface.output);
+            let needs_redraw = config.render != surface.config.render
+                || config.gradient != surface.config.gradient
+                || config.image != surface.config.image;
+            // fade from whatever is on screen right now, which might be the middle of a fade
+            let shown_gradient = surface.shown_gradient();
+            let fade = config.render.mode == surface.config.render.mode
+                && config.gradient != shown_gradient
+                && config.render.crossfade_ms > 0
+                // nothing to fade from if it hasn't been drawn yet
+                && surface.width != 0;
+            surface.config = config;
+            if !needs_redraw {
+                continue;
+            }
+
+            surface.gpu.set_config(&self.gpu, &surface.config);
+            surface.fade = fade.then(|| Fade {
+                from: shown_gradient,
+                start: Instant::now(),
+            });
+            surface.gpu.set_icon_sprites(
+                &self.gpu,
+                &icon_sprites(
+                    &self.desktop_files,
+                    &surface.config,
+                    self.gradient_offset,
+                    surface.physical_size(),
+                ),
+            );
+            surface.voronoi_progress = idle_voronoi_progress(&surface.config);
+            surface
+                .gpu
+                .set_voronoi_progress(&self.gpu, surface.shown_voronoi_progress());
+            surface.request_redraw(&self.gpu, &self.qh);
+        }
+    }
+
+    fn update_gradient_offset(&mut self) {
+        let offset = gradient_offset(&self.config, self.accent);
+        if offset == self.gradient_offset {
+            return;
+        }
+        self.gradient_offset = offset;
+        for surface in &mut self.layer_surfaces {
+            surface.gpu.set_gradient_offset(&self.gpu, offset);
+            surface.gpu.set_icon_sprites(
+                &self.gpu,
+                &icon_sprites(
+                    &self.desktop_files,
+                    &surface.config,
+                    offset,
+                    surface.physical_size(),
+                ),
+            );
+            surface.request_redraw(&self.gpu, &self.qh);
+        }
+    }
+}
+
+type PickResult = Result<Option<usize>, wgpu::BufferAsyncError>;
+
+/// A click or tap waiting for the GPU to tell which app was hit.
+struct Pick {
+    kind: PickKind,
+    surface: WlSurface,
+    position: (u32, u32),
+    size: (u32, u32),
+    seat: Option<WlSeat>,
+}
+
+enum PickKind {
+    Launch,
+    ActionMenu,
+}
+
+struct OutputSurface {
+    // must be first to be dropped before the Wayland surface
+    gpu: SurfaceGpuState,
+    output: WlOutput,
+    layer_surface: LayerSurface,
+    width: u32,
+    height: u32,
+    voronoi_progress: f32,
+    /// Something changed since the last frame was drawn.
+    dirty: bool,
+    /// We asked for a frame callback and haven't gotten it yet.
+    frame_pending: bool,
+    /// The scale the compositor wants, in 120ths.
+    scale_120: u32,
+    /// Maps the buffer to the logical size, without it the buffer scale has to be an integer.
+    viewport: Option<WpViewport>,
+    fractional_scale: Option<WpFractionalScaleV1>,
+    /// The config with the rules for this output applied.
+    config: Config,
+    /// The transform of the output the surface is on.
+    transform: Transform,
+    /// A gradient change that's still fading in.
+    fade: Option<Fade>,
+    /// How much of the apps' colors is shown.
+    reveal: Reveal,
+    /// Shows where the last click launched something.
+    ripple: Option<Ripple>,
+}
+
+/// Blends from the previous gradient to the configured one, one frame at a time.
+struct Fade {
+    from: GradientConfig,
+    start: Instant,
+}
+
+impl Fade {
+    /// How far along the fade is, from 0 to 1.
+    fn progress(&self, duration_ms: u64) -> f32 {
+        fade_progress(self.start.elapsed(), Duration::from_millis(duration_ms))
+    }
+}
+
+/// The colors of the apps are hidden until they're loaded with `icons.load_in_background`,
+/// then they fade in.
+enum Reveal {
+    Hidden,
+    Fading(Instant),
+    Shown,
+}
+
+impl Reveal {
+    /// Once the apps are loaded. There's nothing to fade from if it hasn't been drawn yet.
+    fn loaded(drawn: bool, crossfade_ms: u64) -> Self {
+        if drawn && crossfade_ms > 0 {
+            Reveal::Fading(Instant::now())
+        } else {
+            Reveal::Shown
+        }
+    }
+
+    /// How much of the colors is shown, from 0 to 1.
+    fn progress(&self, duration_ms: u64) -> f32 {
+        match self {
+            Reveal::Hidden => 0.0,
+            Reveal::Fading(start) => {
+                fade_progress(start.elapsed(), Duration::from_millis(duration_ms))
+            }
+            Reveal::Shown => 1.0,
+        }
+    }
+}
+
+const RIPPLE_DURATION: Duration = Duration::from_millis(300);
+/// In logical pixels.
+const RIPPLE_RADIUS: f32 = 40.0;
+const RIPPLE_ALPHA: f32 = 0.6;
+
+/// A ring expanding from a click, so there's feedback before the app shows up.
+struct Ripple {
+    /// Surface-local (logical) position.
+    position: (f64, f64),
+    start: Instant,
+}
+
+/// The radius in logical pixels and the opacity of a ripple, `progress` from 0 to 1.
+fn ripple_shape(progress: f32) -> (f32, f32) {
+    // fast at first, slowing down as it fades
+    let eased = 1.0 - (1.0 - progress).powi(3);
+    (eased * RIPPLE_RADIUS, (1.0 - progress) * RIPPLE_ALPHA)
+}
+
+impl Drop for OutputSurface {
+    fn drop(&mut self) {
+        if let Some(fractional_scale) = &self.fractional_scale {
+            fractional_scale.destroy();
+        }
+        if let Some(viewport) = &self.viewport {
+            viewport.destroy();
+        }
+    }
+}
 
-    let conn = Connection::connect_to_env().wrap_err("can't connect to Wayland socket")?;
+impl OutputSurface {
+    /// The scale the buffer is rendered at, in 120ths.
+    fn buffer_scale_120(&self) -> u32 {
+        match (self.config.render.buffer_scale, &self.viewport) {
+            // the viewport maps any buffer size to the logical size
+            (Some(scale), Some(_)) => ((scale * 120.0).round() as u32).max(1),
+            _ => self.scale_120,
+        }
+    }
 
-    let (globals, event_queue) = registry_queue_init(&conn).wrap_err("initializing connection")?;
+    fn physical_size(&self) -> (u32, u32) {
+        physical_size((self.width, self.height), self.buffer_scale_120())
+    }
 
-    let mut event_loop: EventLoop<App> = EventLoop::try_new().wrap_err("creating event loop")?;
-    let qh: &QueueHandle<App> = &event_queue.handle();
+    /// The color displayed at the pixel, ignoring the voronoi diagram.
+    fn color_at(
+        &self,
+        (x, y): (u32, u32),
+        gradient_offset: Oklab,
+        wallpaper: Option<&Wallpaper>,
+    ) -> Oklab {
+        displayed_color(
+            x,
+            y,
+            self.width,
+            self.height,
+            &self.config,
+            gradient_offset,
+            wallpaper,
+        )
+    }
 
-    let mut app = App {
-        conn: conn.clone(),
-        registry_state: RegistryState::new(&globals),
-        output_state: OutputState::new(&globals, qh),
-        compositor_state: CompositorState::bind(&globals, qh)
-            .wrap_err("failed to bind wl_compositor global")?,
-        layer_shell: LayerShell::bind(&globals, qh)
-            .wrap_err("failed to bind zwlr_layer_shell_v1 global, does the compositor not support layer shell?")?,
-        shm: Shm::bind(&globals, qh).wrap_err("failed to bind shm")?,
-        seat_state: SeatState::new(&globals, qh),
+    /// The gradient on screen, which lags behind the config while fading.
+    fn shown_gradient(&self) -> GradientConfig {
+        match &self.fade {
+            Some(fade) => fade.from.lerp(
+                &self.config.gradient,
+                fade.progress(self.config.render.crossfade_ms),
+            ),
+            None => self.config.gradient.clone(),
+        }
+    }
 
-        gpu: AppGpuState::new(desktop_files.colors())?,
+    /// The voronoi progress on screen, which is lower while the apps' colors fade in.
+    fn shown_voronoi_progress(&self) -> f32 {
+        self.voronoi_progress * self.reveal.progress(self.config.render.crossfade_ms)
+    }
 
-        desktop_files,
-        pointers: HashMap::new(),
-        layer_surfaces: Vec::new(),
-    };
+    /// Only redraws if the progress actually changed, the surface is committed on every draw.
+    fn set_voronoi_progress(&mut self, gpu: &AppGpuState, qh: &QueueHandle<App>, progress: f32) {
+        if !changes_frame(self.voronoi_progress, progress) {
+            return;
+        }
+        self.voronoi_progress = progress;
+        self.gpu
+            .set_voronoi_progress(gpu, self.shown_voronoi_progress());
+        self.request_redraw(gpu, qh);
+    }
 
-    WaylandSource::new(conn.clone(), event_queue)
-        .insert(event_loop.handle())
-        .map_err(|err| eyre!("{:?}", err))
-        .wrap_err("failed to register wayland event source")?;
+    /// Draws right away if the compositor is ready for a new frame, or once it is.
+    fn request_redraw(&mut self, gpu: &AppGpuState, qh: &QueueHandle<App>) {
+        self.dirty = true;
+        if !self.frame_pending {
+            self.draw(gpu, qh);
+        }
+    }
 
-    let _ = sd_notify::notify(true, &[sd_notify::NotifyState::Ready]);
+    fn draw(&mut self, gpu: &AppGpuState, qh: &QueueHandle<App>) {
+        // not configured yet, it will be drawn once it is
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
 
-    loop {
-        event_loop
-            .dispatch(Duration::from_millis(16), &mut app)
-            .wrap_err("error during event loop")?;
-    }
-}
+        let wl_surface = self.layer_surface.wl_surface();
+        wl_surface.frame(qh, wl_surface.clone());
+        self.frame_pending = true;
+        self.dirty = false;
 
-struct App {
-    conn: Connection,
-    registry_state: RegistryState,
-    output_state: OutputState,
-    compositor_state: CompositorState,
-    layer_shell: LayerShell,
-    shm: Shm,
-    seat_state: SeatState,
+        if let Some(fade) = &self.fade {
+            let progress = fade.progress(self.config.render.crossfade_ms);
+            if progress < 1.0 {
+                let gradient = fade.from.lerp(&self.config.gradient, progress);
+                self.gpu.set_gradient(gpu, &gradient);
+                // keep drawing on every frame callback until it's done
+                self.dirty = true;
+            } else {
+                self.gpu.set_gradient(gpu, &self.config.gradient);
+                self.fade = None;
+            }
+        }
 
-    gpu: AppGpuState,
+        if let Reveal::Fading(_) = self.reveal {
+            if self.reveal.progress(self.config.render.crossfade_ms) < 1.0 {
+                self.dirty = true;
+            } else {
+                self.reveal = Reveal::Shown;
+            }
+            self.gpu
+                .set_voronoi_progress(gpu, self.shown_voronoi_progress());
+        }
 
-    desktop_files: DesktopEntries,
-    pointers: HashMap<WlSeat, WlPointer>,
-    layer_surfaces: Vec<OutputSurface>,
-}
+        if let Some(ripple) = &self.ripple {
+            let progress = ripple.start.elapsed().as_secs_f32() / RIPPLE_DURATION.as_secs_f32();
+            if progress < 1.0 {
+                let (radius, alpha) = ripple_shape(progress);
+                self.gpu.set_ripple(gpu, ripple.position, radius, alpha);
+                self.dirty = true;
+            } else {
+                self.gpu.set_ripple(gpu, ripple.position, 0.0, 0.0);
+                self.ripple = None;
+            }
+        }
 
-struct OutputSurface {
-    // must be first to be dropped before the Wayland surface
-    gpu: SurfaceGpuState,
-    output: WlOutput,
-    layer_surface: LayerSurface,
-    width: u32,
-    height: u32,
-    voronoi_progress: f32,
+        if !self.gpu.draw(gpu) {
+            // the frame request still needs to be committed to ever get a callback
+            wl_surface.commit();
+        }
+    }
 }
 
 impl ProvidesRegistryState for App {
@@ -144,29 +1759,148 @@ impl OutputHandler for App {
                 );
             }
         }
+        // it gets a surface once it's shown
+        if self.config.surface.toggle && !self.toggled_on {
+            return;
+        }
+        self.add_surface(qh, output);
+    }
+
+    fn update_output(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _output: wayland_client::protocol::wl_output::WlOutput,
+    ) {
+        // the name and description might have arrived only now
+        self.reconfigure_surfaces();
+    }
+
+    fn output_destroyed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        output: wayland_client::protocol::wl_output::WlOutput,
+    ) {
+        match self.output_state.info(&output) {
+            None => warn!("Output disconnected, unknown information"),
+            Some(info) => {
+                info!(
+                    "Output disconnected ({})",
+                    info.description.unwrap_or_else(|| "<unknown>".into()),
+                );
+            }
+        }
+        self.remove_surfaces(|surface| surface.output == output);
+    }
+}
+
+impl App {
+    /// Creates the layer surface on the output.
+    fn add_surface(&mut self, qh: &QueueHandle<Self>, output: WlOutput) {
+        let name = self.output_state.info(&output).and_then(|info| info.name);
+        if !self.config.shows_output(name.as_deref()) {
+            info!(
+                "Not showing the wallpaper on output {}, it isn't in render.only_outputs",
+                name.as_deref().unwrap_or("<unknown>")
+            );
+            if self.layer_surfaces.is_empty() {
+                let names = self
+                    .output_state
+                    .outputs()
+                    .map(|output| {
+                        self.output_state
+                            .info(&output)
+                            .and_then(|info| info.name)
+                            .unwrap_or_else(|| "<unknown>".into())
+                    })
+                    .collect::<Vec<_>>();
+                warn!(
+                    "No output matches render.only_outputs {:?} yet, the outputs are: {}",
+                    self.config.render.only_outputs,
+                    names.join(", ")
+                );
+            }
+            return;
+        }
         let surface: wayland_client::protocol::wl_surface::WlSurface =
             self.compositor_state.create_surface(qh);
         let layer_surface = self.layer_shell.create_layer_surface(
             qh,
             surface.clone(),
-            Layer::Background,
+            match self.config.surface.layer {
+                // it's a launcher that's shown on demand then
+                _ if self.config.surface.toggle => Layer::Overlay,
+                SurfaceLayer::Background => Layer::Background,
+                SurfaceLayer::Bottom => Layer::Bottom,
+                SurfaceLayer::Top => Layer::Top,
+                SurfaceLayer::Overlay => Layer::Overlay,
+            },
             Some("wallpaper"),
             Some(&output),
         );
         layer_surface.set_exclusive_zone(-1);
-        layer_surface.set_anchor(Anchor::all());
-        layer_surface.set_keyboard_interactivity(KeyboardInteractivity::None);
+        let surface_config = &self.config.surface;
+        layer_surface.set_anchor(layer_anchor(surface_config));
+        let [top, right, bottom, left] = surface_config.margin;
+        layer_surface.set_margin(top, right, bottom, left);
+        if let Some([width, height]) = surface_config.size {
+            layer_surface.set_size(width, height);
+        }
+        layer_surface.set_keyboard_interactivity(if self.config.surface.toggle {
+            // so it can be searched and dismissed without clicking first
+            KeyboardInteractivity::Exclusive
+        } else if self.config.keyboard.enabled {
+            // only take focus when clicked, so we don't steal it from other windows
+            KeyboardInteractivity::OnDemand
+        } else {
+            KeyboardInteractivity::None
+        });
+
+        let viewport = self
+            .viewporter
+            .as_ref()
+            .map(|viewporter| viewporter.get_viewport(&surface, qh, ()));
+        // fractional scales can only be applied through a viewport
+        let fractional_scale = self
+            .fractional_scale_manager
+            .as_ref()
+            .filter(|_| viewport.is_some())
+            .map(|manager| manager.get_fractional_scale(&surface, qh, surface.clone()));
+
         layer_surface.wl_surface().commit();
 
-        match SurfaceGpuState::new(&self.gpu, &self.conn.backend(), &surface) {
+        let config = output_config(&self.output_state, &self.config, &output);
+        match SurfaceGpuState::new(&self.gpu, &self.conn.backend(), &surface, &config) {
             Ok(gpu_state) => {
+                let voronoi_progress = idle_voronoi_progress(&config);
+                let reveal = if self.loading {
+                    Reveal::Hidden
+                } else {
+                    Reveal::Shown
+                };
+                gpu_state.set_voronoi_progress(
+                    &self.gpu,
+                    voronoi_progress * reveal.progress(config.render.crossfade_ms),
+                );
+                gpu_state.set_gradient_offset(&self.gpu, self.gradient_offset);
                 self.layer_surfaces.push(OutputSurface {
                     gpu: gpu_state,
                     output,
                     layer_surface,
                     width: 0,
                     height: 0,
-                    voronoi_progress: 0.0,
+                    voronoi_progress,
+                    dirty: false,
+                    frame_pending: false,
+                    scale_120: 120,
+                    viewport,
+                    fractional_scale,
+                    config,
+                    transform: Transform::Normal,
+                    fade: None,
+                    reveal,
+                    ripple: None,
                 });
             }
             Err(err) => error!(
@@ -175,141 +1909,504 @@ impl OutputHandler for App {
             ),
         }
     }
+}
 
-    fn update_output(
+impl CompositorHandler for App {
+    fn scale_factor_changed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _output: wayland_client::protocol::wl_output::WlOutput,
+        surface: &wayland_client::protocol::wl_surface::WlSurface,
+        new_factor: i32,
     ) {
+        let Some(output_surface) = self
+            .layer_surfaces
+            .iter_mut()
+            .find(|output_surface| output_surface.layer_surface.wl_surface() == surface)
+        else {
+            return;
+        };
+        // the fractional scale is more precise and wins if we have it
+        if output_surface.fractional_scale.is_some() {
+            return;
+        }
+        output_surface.scale_120 = new_factor.max(1) as u32 * 120;
+        self.resize_surface(surface);
+        self.update_icon_scale();
     }
 
-    fn output_destroyed(
+    fn transform_changed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        output: wayland_client::protocol::wl_output::WlOutput,
+        surface: &wayland_client::protocol::wl_surface::WlSurface,
+        new_transform: wayland_client::protocol::wl_output::Transform,
     ) {
-        match self.output_state.info(&output) {
-            None => warn!("Output disconnected, unknown information"),
-            Some(info) => {
-                info!(
-                    "Output disconnected ({})",
-                    info.description.unwrap_or_else(|| "<unknown>".into()),
-                );
-            }
-        }
-        if let Some(suface_idx) = self
+        let Some(output_surface) = self
             .layer_surfaces
-            .iter()
-            .position(|surface| surface.output == output)
+            .iter_mut()
+            .find(|output_surface| output_surface.layer_surface.wl_surface() == surface)
+        else {
+            return;
+        };
+        output_surface.transform = new_transform;
+        self.resize_surface(surface);
+    }
+
+    fn frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        surface: &wayland_client::protocol::wl_surface::WlSurface,
+        _time: u32,
+    ) {
+        if let Some(surface) = self
+            .layer_surfaces
+            .iter_mut()
+            .find(|output_surface| output_surface.layer_surface.wl_surface() == surface)
         {
-            self.layer_surfaces.swap_remove(suface_idx);
+            surface.frame_pending = false;
+            if surface.dirty {
+                surface.draw(&self.gpu, &self.qh);
+            }
         }
     }
-}
 
-impl CompositorHandler for App {
-    fn scale_factor_changed(
+    fn surface_enter(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _new_factor: i32,
+        _output: &wayland_client::protocol::wl_output::WlOutput,
     ) {
     }
 
-    fn transform_changed(
+    fn surface_leave(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _new_transform: wayland_client::protocol::wl_output::Transform,
+        _output: &wayland_client::protocol::wl_output::WlOutput,
     ) {
     }
+}
 
-    fn frame(
+impl LayerShellHandler for App {
+    fn closed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _time: u32,
+        layer: &smithay_client_toolkit::shell::wlr_layer::LayerSurface,
+    ) {
+        self.remove_surfaces(|surface| surface.layer_surface == *layer);
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        layer: &smithay_client_toolkit::shell::wlr_layer::LayerSurface,
+        configure: smithay_client_toolkit::shell::wlr_layer::LayerSurfaceConfigure,
+        _serial: u32,
     ) {
-        dbg!("yeet");
+        let Some(surface) = self
+            .layer_surfaces
+            .iter_mut()
+            .find(|surface| surface.layer_surface == *layer)
+        else {
+            return;
+        };
+
+        let output_size = self
+            .output_state
+            .info(&surface.output)
+            .and_then(|info| info.logical_size);
+        let Some((width, height)) = resolve_surface_size(configure.new_size, output_size) else {
+            warn!("Got zero-size configure and the output size is unknown, not drawing yet");
+            return;
+        };
+        info!("Reconfiguring surface to {}x{}", width, height);
+
+        surface.width = width;
+        surface.height = height;
+        self.resize_surface(layer.wl_surface());
+        self.notify_ready();
+    }
+}
+
+impl App {
+    /// Sizes the buffer for the surface's logical size and scale, then redraws.
+    fn resize_surface(&mut self, wl_surface: &WlSurface) {
+        let Some(surface) = self
+            .layer_surfaces
+            .iter_mut()
+            .find(|surface| surface.layer_surface.wl_surface() == wl_surface)
+        else {
+            return;
+        };
+        // not configured yet, this happens again once it is
+        if surface.width == 0 || surface.height == 0 {
+            return;
+        }
+
+        let (width, height) = surface.physical_size();
+        match &surface.viewport {
+            Some(viewport) => viewport.set_destination(surface.width as i32, surface.height as i32),
+            None => wl_surface.set_buffer_scale((surface.scale_120 / 120) as i32),
+        }
+
+        // everything else stays in surface coordinates, only the shader knows about this
+        let transform = if surface.config.render.match_transform {
+            surface.transform
+        } else {
+            Transform::Normal
+        };
+        wl_surface.set_buffer_transform(transform);
+
+        let scale = surface.buffer_scale_120() as f32 / 120.0;
+        if let Err(err) =
+            surface
+                .gpu
+                .resize(&self.gpu, width, height, scale, transform, &surface.config)
+        {
+            error!("Failed to resize surface to {width}x{height}, not drawing it: {err:?}");
+            // the last successfully drawn frame stays up
+            surface.width = 0;
+            surface.height = 0;
+            return;
+        }
+
+        surface.gpu.set_icon_sprites(
+            &self.gpu,
+            &icon_sprites(
+                &self.desktop_files,
+                &surface.config,
+                self.gradient_offset,
+                (width, height),
+            ),
+        );
+        surface.request_redraw(&self.gpu, &self.qh);
+
+        // the message, hint and recent apps are placed by the size, so they have to move with it
+        if !self.has_overlay(wl_surface) {
+            self.set_overlay(wl_surface, None);
+        }
+    }
+}
+
+/// The config for the output, with the rules matching its name and description applied.
+fn output_config(output_state: &OutputState, config: &Config, output: &WlOutput) -> Config {
+    let info = output_state.info(output);
+    config.for_output(
+        info.as_ref().and_then(|info| info.name.as_deref()),
+        info.as_ref().and_then(|info| info.description.as_deref()),
+    )
+}
+
+/// The edges to anchor the layer surface to, see [`SurfaceConfig::anchor`].
+fn layer_anchor(config: &SurfaceConfig) -> Anchor {
+    match &config.anchor {
+        Some(edges) => edges.iter().fold(Anchor::empty(), |anchor, edge| {
+            anchor
+                | match edge {
+                    Edge::Top => Anchor::TOP,
+                    Edge::Bottom => Anchor::BOTTOM,
+                    Edge::Left => Anchor::LEFT,
+                    Edge::Right => Anchor::RIGHT,
+                }
+        }),
+        None if config.size.is_some() => Anchor::empty(),
+        None => Anchor::all(),
+    }
+}
+
+/// Whether going from the `current` voronoi progress to `progress` draws a different frame.
+/// Committing identical frames costs the compositor a full repaint, wgpu damages the whole
+/// surface on every present.
+fn changes_frame(current: f32, progress: f32) -> bool {
+    current != progress
+}
+
+/// Eases in and out, 0 at the start and 1 once `duration` has passed.
+fn fade_progress(elapsed: Duration, duration: Duration) -> f32 {
+    if duration.is_zero() {
+        return 1.0;
+    }
+    let t = (elapsed.as_secs_f32() / duration.as_secs_f32()).min(1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// The position moved inside the surface, pointers at its very edge can be reported just
+/// outside of it.
+fn clamp_to_surface((x, y): (f64, f64), (width, height): (u32, u32)) -> (f64, f64) {
+    let clamp = |value: f64, size: u32| {
+        if value.is_nan() {
+            0.0
+        } else {
+            value.clamp(0.0, f64::from(size.saturating_sub(1)))
+        }
+    };
+    (clamp(x, width), clamp(y, height))
+}
+
+/// The buffer size for a logical size at a scale in 120ths, rounded like the protocol says.
+// https://wayland.app/protocols/fractional-scale-v1
+fn physical_size((width, height): (u32, u32), scale_120: u32) -> (u32, u32) {
+    let scale = |logical: u32| (logical * scale_120 + 60) / 120;
+    (scale(width), scale(height))
+}
+
+/// Larger configures are a misbehaving compositor, the buffers would take gigabytes.
+const MAX_SURFACE_SIZE: u32 = 16384;
+
+/// A zero dimension in a configure means we get to pick, so use the size of the output.
+/// Absurdly large sizes are replaced by the output size too, and never exceed
+/// [`MAX_SURFACE_SIZE`].
+fn resolve_surface_size(
+    configured: (u32, u32),
+    output_logical_size: Option<(i32, i32)>,
+) -> Option<(u32, u32)> {
+    let output_size = || {
+        let (width, height) = output_logical_size?;
+        let width = u32::try_from(width).ok().filter(|&w| w > 0)?;
+        let height = u32::try_from(height).ok().filter(|&h| h > 0)?;
+        Some((width.min(MAX_SURFACE_SIZE), height.min(MAX_SURFACE_SIZE)))
+    };
+    match configured {
+        (0, _) | (_, 0) => output_size(),
+        (width, height) if width > MAX_SURFACE_SIZE || height > MAX_SURFACE_SIZE => {
+            let size = output_size()
+                .unwrap_or((width.min(MAX_SURFACE_SIZE), height.min(MAX_SURFACE_SIZE)));
+            warn!(
+                "Got a configure for an absurd {width}x{height} surface, using {}x{} instead",
+                size.0, size.1
+            );
+            Some(size)
+        }
+        size => Some(size),
+    }
+}
+
+/// Whether this is the first time for the flag, which is set from then on.
+fn first_time(done: &mut bool) -> bool {
+    !std::mem::replace(done, true)
+}
+
+/// Only does something when started by systemd with `$NOTIFY_SOCKET`, which stays set so
+/// the later states arrive too.
+fn notify_systemd(state: sd_notify::NotifyState) {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    if let Err(err) = sd_notify::notify(false, &[state]) {
+        warn!("Failed to notify systemd: {err}");
+    }
+}
+
+/// `scale_120` is the largest output scale in 120ths, for the size of the icons.
+fn load_apps(config: &Config, scale_120: u32) -> Result<DesktopEntries> {
+    let now = Instant::now();
+    let desktop_files =
+        desktop::find_desktop_files(config, scale_120).wrap_err("loading .desktop files")?;
+    info!(
+        "Loaded {} desktop icons in {:?}",
+        desktop_files.count(),
+        now.elapsed()
+    );
+    if desktop_files.count() == 0 {
+        warn!(
+            "No apps found, check the discovery settings and that there are .desktop files \
+             with icons in the applications directories of $XDG_DATA_DIRS"
+        );
     }
+    Ok(desktop_files)
+}
+
+/// The clusters if apps are grouped, and the colors the voronoi diagram is made of with how
+/// much larger their cells are.
+fn voronoi_seeds(
+    desktop_files: &DesktopEntries,
+    config: &Config,
+    usage: &Usage,
+) -> (Option<Clusters>, Vec<Oklab>, Vec<f32>) {
+    let colors = desktop_files.colors().collect::<Vec<_>>();
+    let clusters = match config.render.clusters {
+        Some(count) if count < desktop_files.count() => Some(cluster::cluster(&colors, count)),
+        _ if config.matching.duplicates == DuplicateStrategy::Merge => Some(
+            cluster::merge_duplicates(&colors, config.matching.duplicate_distance),
+        ),
+        _ => None,
+    };
+    let seed_colors = match &clusters {
+        Some(clusters) => clusters.centroids.clone(),
+        None => colors,
+    };
+    // often launched apps get larger voronoi cells
+    let seed_weights = if config.render.popularity > 0.0 {
+        let half_life = config.render.popularity_half_life_days;
+        let scores = match &clusters {
+            Some(clusters) => clusters
+                .members
+                .iter()
+                .map(|members| {
+                    members
+                        .iter()
+                        .filter_map(|&member| desktop_files.nth(member))
+                        .map(|entry| usage.score(&entry.id, half_life))
+                        .sum()
+                })
+                .collect::<Vec<_>>(),
+            None => desktop_files
+                .iter()
+                .map(|entry| usage.score(&entry.id, half_life))
+                .collect(),
+        };
+        usage::weights(&scores, config.render.popularity)
+    } else {
+        vec![0.0; seed_colors.len()]
+    };
+    (clusters, seed_colors, seed_weights)
+}
 
-    fn surface_enter(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _output: &wayland_client::protocol::wl_output::WlOutput,
-    ) {
+/// Where the app icons go on a surface of that size, nowhere if they are disabled.
+/// The seeds only mean something for the generated gradient, not for an image.
+fn icon_sprites(
+    desktop_files: &DesktopEntries,
+    config: &Config,
+    gradient_offset: Oklab,
+    surface_size: (u32, u32),
+) -> Vec<Sprite> {
+    if !config.icons.sprites || config.render.mode == RenderMode::Image {
+        return Vec::new();
     }
+    sprites::layout_sprites(
+        desktop_files.colors(),
+        surface_size,
+        &config.gradient,
+        gradient_offset,
+    )
+}
 
-    fn surface_leave(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _output: &wayland_client::protocol::wl_output::WlOutput,
-    ) {
+/// The voronoi progress when the user isn't interacting with the wallpaper.
+fn idle_voronoi_progress(config: &Config) -> f32 {
+    match config.render.mode {
+        RenderMode::Gradient => 0.0,
+        RenderMode::Voronoi => 1.0,
+        // the image is shown as it is, picking doesn't need the diagram on screen
+        RenderMode::Image => 0.0,
     }
 }
 
-impl LayerShellHandler for App {
-    fn closed(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        layer: &smithay_client_toolkit::shell::wlr_layer::LayerSurface,
-    ) {
-        if let Some(surface_idx) = self
-            .layer_surfaces
-            .iter()
-            .position(|surface| surface.layer_surface == *layer)
-        {
-            self.layer_surfaces.swap_remove(surface_idx);
-        }
+/// The offset of the gradient from the time of day and the accent color.
+fn gradient_offset(config: &Config, accent: Option<Oklab>) -> Oklab {
+    let offset = time_of_day::current_offset(&config.time_of_day);
+    if config.gradient.accent {
+        offset + accent::offset(accent)
+    } else {
+        offset
     }
+}
 
-    fn configure(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        layer: &smithay_client_toolkit::shell::wlr_layer::LayerSurface,
-        configure: smithay_client_toolkit::shell::wlr_layer::LayerSurfaceConfigure,
-        _serial: u32,
-    ) {
-        let (width, height) = configure.new_size;
-        info!("Reconfiguring surface to {}x{}", width, height);
+/// Whether a click launching `id` at `now` repeats the last one, from a double click or a
+/// jittery touchpad. Other apps can always be launched right away.
+fn is_bounce(last: Option<&(String, Instant)>, id: &str, now: Instant, debounce: Duration) -> bool {
+    last.is_some_and(|(last_id, at)| last_id == id && now.saturating_duration_since(*at) < debounce)
+}
 
-        let Some(surface) = self
-            .layer_surfaces
-            .iter_mut()
-            .find(|surface| surface.layer_surface == *layer)
-        else {
-            return;
+/// The idle frame the GPU would draw, minus icons.
+fn render_cpu(
+    (width, height): (u32, u32),
+    config: &Config,
+    gradient_offset: Oklab,
+    seed_colors: &[Oklab],
+    seed_weights: &[f32],
+    wallpaper: Option<&Wallpaper>,
+) -> RgbaImage {
+    let voronoi_progress = idle_voronoi_progress(config);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let color = displayed_color(x, y, width, height, config, gradient_offset, wallpaper);
+        let color = mix_voronoi(color, seed_colors, seed_weights, config, voronoi_progress);
+        let color = if config.render.grain > 0.0 {
+            let l = color.l + (grain_noise(x, y) - 0.5) * config.render.grain;
+            Oklab::new(l, color.a, color.b)
+        } else {
+            color
         };
+        let srgb = if config.render.dither {
+            color::to_srgb8_dithered(color, color::dither_threshold(x, y))
+        } else {
+            color::to_srgb8(color)
+        };
+        image::Rgba([srgb.red, srgb.green, srgb.blue, 255])
+    })
+}
 
-        surface.width = width;
-        surface.height = height;
-
-        surface.gpu.resize(&self.gpu, width, height);
-        surface.gpu.draw(&self.gpu);
+/// The color mixed `progress` of the way toward the voronoi diagram, which is the color of
+/// the closest app, or washed out if no app is within `matching.max_distance`.
+// keep it in sync with the gpu implementation
+fn mix_voronoi(
+    color: Oklab,
+    seed_colors: &[Oklab],
+    seed_weights: &[f32],
+    config: &Config,
+    progress: f32,
+) -> Oklab {
+    match cluster::nearest_weighted(seed_colors, seed_weights, color, &config.matching) {
+        Some(seed) => color + (seed_colors[seed] - color) * progress,
+        // a plain gray, so it doesn't look like everything is fine
+        None if seed_colors.is_empty() => Oklab::new(config.gradient.lightness, 0.0, 0.0),
+        // too far from every app
+        None => color + (Oklab::new(color.l, 0.0, 0.0) - color) * progress,
     }
 }
 
+/// A stable pseudo-random value in [0, 1) for every pixel (PCG hash).
 // keep it in sync with the gpu implementation
-fn color_for_pixel(x: u32, y: u32, width: u32, height: u32) -> Oklab {
+fn grain_noise(x: u32, y: u32) -> f32 {
+    let state = x
+        .wrapping_mul(1973)
+        .wrapping_add(y.wrapping_mul(9277))
+        .wrapping_mul(747796405)
+        .wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    let hash = (word >> 22) ^ word;
+    (hash >> 8) as f32 / 16777216.0
+}
+
+/// The color that is displayed at a pixel, before the voronoi diagram is mixed in.
+fn displayed_color(
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    config: &Config,
+    gradient_offset: Oklab,
+    wallpaper: Option<&Wallpaper>,
+) -> Oklab {
+    wallpaper
+        .filter(|_| config.render.mode == RenderMode::Image)
+        .and_then(|wallpaper| wallpaper.color_at(x, y, width, height, config.image.fit))
+        .unwrap_or_else(|| color_for_pixel(x, y, width, height, config, gradient_offset))
+}
+
+// keep it in sync with the gpu implementation and sprites::seed_position
+fn color_for_pixel(
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    config: &Config,
+    offset: Oklab,
+) -> Oklab {
     let xf = x as f32 / width as f32;
     let yf = y as f32 / height as f32;
 
+    let gradient = &config.gradient;
     palette::Oklab {
-        l: 0.7,
-        a: xf * 0.8 - 0.4,
-        b: yf * 0.7 - 0.4,
-    }
+        l: gradient.lightness,
+        a: (xf * 0.8 - 0.4) * gradient.chroma + gradient.chroma_offset[0],
+        b: (yf * 0.7 - 0.4) * gradient.chroma + gradient.chroma_offset[1],
+    } + offset
 }
 
 impl ShmHandler for App {
@@ -344,6 +2441,25 @@ impl SeatHandler for App {
                 self.seat_state.get_pointer(qh, &seat).unwrap(),
             );
         }
+        if capability == smithay_client_toolkit::seat::Capability::Touch {
+            match self.seat_state.get_touch(qh, &seat) {
+                Ok(touch) => {
+                    self.touches
+                        .insert(seat.clone(), (touch, TapTracker::default()));
+                }
+                Err(err) => error!("Failed to get touch: {err}"),
+            }
+        }
+        if capability == smithay_client_toolkit::seat::Capability::Keyboard
+            && (self.config.keyboard.enabled || self.config.surface.toggle)
+        {
+            match self.seat_state.get_keyboard(qh, &seat, None) {
+                Ok(keyboard) => {
+                    self.keyboards.insert(seat.clone(), keyboard);
+                }
+                Err(err) => error!("Failed to get keyboard: {err}"),
+            }
+        }
     }
 
     fn remove_capability(
@@ -355,6 +2471,13 @@ impl SeatHandler for App {
     ) {
         if capability == smithay_client_toolkit::seat::Capability::Pointer {
             self.pointers.remove(&seat);
+            self.close_seat_overlay(&seat);
+        }
+        if capability == smithay_client_toolkit::seat::Capability::Keyboard {
+            self.keyboards.remove(&seat);
+        }
+        if capability == smithay_client_toolkit::seat::Capability::Touch {
+            self.touches.remove(&seat);
         }
     }
 
@@ -362,8 +2485,12 @@ impl SeatHandler for App {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _seat: wayland_client::protocol::wl_seat::WlSeat,
+        seat: wayland_client::protocol::wl_seat::WlSeat,
     ) {
+        self.pointers.remove(&seat);
+        self.keyboards.remove(&seat);
+        self.touches.remove(&seat);
+        self.close_seat_overlay(&seat);
     }
 }
 
@@ -372,70 +2499,138 @@ impl PointerHandler for App {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _pointer: &wayland_client::protocol::wl_pointer::WlPointer,
+        pointer: &wayland_client::protocol::wl_pointer::WlPointer,
         events: &[smithay_client_toolkit::seat::pointer::PointerEvent],
     ) {
+        let seat = self
+            .pointers
+            .iter()
+            .find(|(_, seat_pointer)| *seat_pointer == pointer)
+            .map(|(seat, _)| seat.clone());
         for event in events {
+            // the menu or cycle belongs to another seat's pointer
+            let foreign = self
+                .overlay_seat
+                .as_ref()
+                .is_some_and(|owner| Some(owner) != seat.as_ref());
             let Some(surface) = self
                 .layer_surfaces
                 .iter_mut()
                 .find(|surface| *surface.layer_surface.wl_surface() == event.surface)
             else {
-                return;
+                continue;
             };
+            let position = clamp_to_surface(event.position, (surface.width, surface.height));
+            let (x, y) = (position.0 as u32, position.1 as u32);
 
             match event.kind {
                 PointerEventKind::Release {
                     button: BTN_LEFT, ..
                 } => {
-                    let oklab = color_for_pixel(
-                        event.position.0 as u32,
-                        event.position.1 as u32,
-                        surface.width,
-                        surface.height,
-                    );
-
-                    let best_match = self.desktop_files.find_entry(oklab);
-
-                    if let Some(best_match) = best_match
-                        && let EntryType::Application(app) = &best_match.file.entry.entry_type
-                        && let Some(exec) = &app.exec
-                    {
-                        // lol terrible implementation that works well enough
-                        // https://specifications.freedesktop.org/desktop-entry/latest/exec-variables.html
-                        let exec = exec.replace("%U", "").replace("%F", "");
-                        if exec.contains("%") {
-                            warn!(
-                                "Trying to execute insuffiently substituded command-line, refusing: {}",
-                                exec
-                            );
-                            return;
-                        }
-                        if let Err(err) = spawn(&exec) {
-                            error!("Failed to spawn program: {}: {:?}", exec, err);
+                    let surface_size = (surface.width, surface.height);
+                    // while the menu is open, clicking anywhere closes it
+                    if !foreign && let Some(menu) = self.close_action_menu() {
+                        if menu.surface == event.surface
+                            && let Some(index) = menu.item_at(x, y)
+                            && let Some(entry) = self.desktop_files.get(&menu.entry_id)
+                            && let Some(exec) = &entry.actions[index].exec
+                        {
+                            self.launch(exec);
+                            self.hide();
                         }
+                        continue;
+                    }
+
+                    // scrolling picked a different app than the nearest one
+                    if !foreign
+                        && let Some(cycle) = self.close_scroll_cycle()
+                        && cycle.surface == event.surface
+                        && let Some(entry) =
+                            cycle.selected().and_then(|id| self.desktop_files.get(id))
+                    {
+                        self.launch_entry(entry);
+                        self.redraw_idle_overlays();
+                        self.hide();
+                        continue;
                     }
+
+                    // the recent apps are drawn over the diagram
+                    if !self.has_overlay(&event.surface)
+                        && self.hint_alpha() == 0.0
+                        && let Some(recent_apps) = self.recent_apps()
+                        && let Some(index) = recent_apps.item_at((x, y), surface_size)
+                        && let Some(entry) = self.desktop_files.get(&recent_apps.ids[index])
+                    {
+                        self.launch_entry(entry);
+                        self.redraw_idle_overlays();
+                        self.hide();
+                        continue;
+                    }
+
+                    self.pick(PickKind::Launch, &event.surface, position, seat.clone());
                 }
                 PointerEventKind::Press {
                     button: BTN_RIGHT, ..
                 } => {
-                    surface.voronoi_progress = 1.0;
+                    surface.set_voronoi_progress(&self.gpu, &self.qh, 1.0);
 
-                    surface
-                        .gpu
-                        .set_voronoi_progress(&self.gpu, surface.voronoi_progress);
-                    surface.gpu.draw(&self.gpu);
+                    if !foreign {
+                        self.close_action_menu();
+                    }
                 }
                 PointerEventKind::Release {
                     button: BTN_RIGHT, ..
+                } => {
+                    let progress = idle_voronoi_progress(&surface.config);
+                    surface.set_voronoi_progress(&self.gpu, &self.qh, progress);
+
+                    self.pick(PickKind::ActionMenu, &event.surface, position, seat.clone());
+                }
+                PointerEventKind::Axis { vertical, .. } => {
+                    // touchpads only scroll continuously, which has no steps to go by
+                    let amount_120 = if vertical.value120 != 0 {
+                        vertical.value120
+                    } else {
+                        vertical.discrete * 120
+                    };
+                    if amount_120 != 0 && !foreign {
+                        self.scroll_apps(&event.surface, (x, y), amount_120);
+                        self.overlay_seat = seat.clone();
+                    }
+                }
+                PointerEventKind::Enter { .. } => {
+                    let color =
+                        surface.color_at((x, y), self.gradient_offset, self.wallpaper.as_ref());
+                    self.prefetch_launch(color);
+                }
+                PointerEventKind::Motion { .. } if !foreign => {
+                    let color =
+                        surface.color_at((x, y), self.gradient_offset, self.wallpaper.as_ref());
+                    self.prefetch_launch(color);
+                    if let Some(cycle) = &self.scroll_cycle
+                        && cycle.surface == event.surface
+                        && !cycle.is_near(color)
+                    {
+                        self.close_scroll_cycle();
+                    }
+                    if let Some(menu) = &mut self.action_menu
+                        && menu.surface == event.surface
+                    {
+                        let hovered = menu.item_at(x, y);
+                        if hovered != menu.hovered {
+                            menu.hovered = hovered;
+                            self.draw_action_menu();
+                        }
+                    }
                 }
-                | PointerEventKind::Leave { .. } => {
-                    surface.voronoi_progress = 0.0;
+                PointerEventKind::Leave { .. } => {
+                    let progress = idle_voronoi_progress(&surface.config);
+                    surface.set_voronoi_progress(&self.gpu, &self.qh, progress);
 
-                    surface
-                        .gpu
-                        .set_voronoi_progress(&self.gpu, surface.voronoi_progress);
-                    surface.gpu.draw(&self.gpu);
+                    if !foreign {
+                        self.close_action_menu();
+                        self.close_scroll_cycle();
+                    }
                 }
                 _ => (),
             }
@@ -443,23 +2638,302 @@ impl PointerHandler for App {
     }
 }
 
-fn spawn(cmd: &str) -> Result<()> {
-    info!("Spawning program: {cmd}");
-    let output = std::process::Command::new("niri")
-        .arg("msg")
-        .arg("action")
-        .arg("spawn-sh")
-        .arg("--")
-        .arg(cmd)
-        .output()
-        .wrap_err("executing niri msg action spawn-sh")?;
-    if !output.status.success() {
-        bail!(
-            "niri returned error: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+impl App {
+    fn tap_tracker(&mut self, touch: &WlTouch) -> Option<&mut TapTracker> {
+        self.touches
+            .values_mut()
+            .find(|(seat_touch, _)| seat_touch == touch)
+            .map(|(_, tracker)| tracker)
+    }
+}
+
+impl TouchHandler for App {
+    fn down(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        touch: &WlTouch,
+        _serial: u32,
+        _time: u32,
+        surface: WlSurface,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        if let Some(tracker) = self.tap_tracker(touch) {
+            tracker.down(surface, id, position);
+        }
+    }
+
+    fn up(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        touch: &WlTouch,
+        _serial: u32,
+        _time: u32,
+        id: i32,
+    ) {
+        let Some((wl_surface, (x, y))) = self.tap_tracker(touch).and_then(|tracker| tracker.up(id))
+        else {
+            return;
+        };
+        self.pick(PickKind::Launch, &wl_surface, (x, y), None);
+    }
+
+    fn motion(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        touch: &WlTouch,
+        _time: u32,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        if let Some(tracker) = self.tap_tracker(touch) {
+            tracker.motion(id, position);
+        }
+    }
+
+    fn shape(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        _id: i32,
+        _major: f64,
+        _minor: f64,
+    ) {
+    }
+
+    fn orientation(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        _id: i32,
+        _orientation: f64,
+    ) {
+    }
+
+    fn cancel(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, touch: &WlTouch) {
+        if let Some(tracker) = self.tap_tracker(touch) {
+            tracker.cancel();
+        }
+    }
+}
+
+impl KeyboardHandler for App {
+    fn enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        surface: &WlSurface,
+        _serial: u32,
+        _raw: &[u32],
+        _keysyms: &[Keysym],
+    ) {
+        self.keyboard_focus = Some(surface.clone());
+    }
+
+    fn leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _surface: &WlSurface,
+        _serial: u32,
+    ) {
+        self.keyboard_focus = None;
+        self.close_app_search();
+    }
+
+    fn press_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        event: KeyEvent,
+    ) {
+        let Some(focus) = self.keyboard_focus.clone() else {
+            return;
+        };
+
+        match event.keysym {
+            Keysym::Escape => {
+                if self.close_app_search().is_none() {
+                    self.hide();
+                }
+            }
+            Keysym::Return | Keysym::KP_Enter => {
+                if let Some(search) = self.close_app_search()
+                    && let Some(entry) =
+                        search::filter_apps(&self.desktop_files, &search.query).first()
+                {
+                    self.launch_entry(entry);
+                    self.redraw_idle_overlays();
+                    self.hide();
+                }
+            }
+            Keysym::BackSpace => {
+                if let Some(search) = &mut self.app_search {
+                    search.query.pop();
+                    self.draw_app_search();
+                }
+            }
+            _ => {
+                let Some(text) = event
+                    .utf8
+                    .filter(|text| !text.chars().any(char::is_control))
+                else {
+                    return;
+                };
+                self.app_search
+                    .get_or_insert_with(|| AppSearch::new(focus))
+                    .query
+                    .push_str(&text);
+                self.draw_app_search();
+            }
+        }
+    }
+
+    fn release_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _event: KeyEvent,
+    ) {
+    }
+
+    fn update_modifiers(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _modifiers: Modifiers,
+        _raw_modifiers: RawModifiers,
+        _layout: u32,
+    ) {
+    }
+}
+
+fn format_app_list(entries: &DesktopEntries) -> String {
+    let id_width = entries
+        .iter()
+        .map(|entry| entry.id.len())
+        .max()
+        .unwrap_or(0);
+    let name_width = entries
+        .iter()
+        .map(|entry| entry.file.entry.name.default.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    for entry in entries.iter() {
+        let color = entry.avg_icon_color;
+        let srgb = color::to_srgb8(color);
+        writeln!(
+            out,
+            "{:id_width$}  {:name_width$}  {:>6.3} {:>6.3} {:>6.3}  #{:02x}{:02x}{:02x}  {}",
+            entry.id,
+            entry.file.entry.name.default,
+            color.l,
+            color.a,
+            color.b,
+            srgb.red,
+            srgb.green,
+            srgb.blue,
+            entry
+                .icon_path
+                .as_deref()
+                .map_or_else(|| "<no icon>".into(), |path| path.display().to_string()),
+        )
+        .unwrap();
+        for action in &entry.actions {
+            writeln!(out, "{:id_width$}  -> {}", "", action.name).unwrap();
+        }
     }
-    Ok(())
+    out
+}
+
+#[derive(Serialize)]
+struct StatusJson {
+    apps: usize,
+    /// The apps are still loading in the background.
+    loading: bool,
+    render_mode: RenderMode,
+    /// The surfaces are up, they can be hidden with `surface.toggle`.
+    shown: bool,
+    /// `None` if the config was never reloaded.
+    seconds_since_reload: Option<f64>,
+    outputs: Vec<OutputStatusJson>,
+    /// The most recently launched apps, most recent first.
+    recent: Vec<RecentJson>,
+}
+
+#[derive(Serialize)]
+struct OutputStatusJson {
+    name: Option<String>,
+    logical_size: [u32; 2],
+    physical_size: [u32; 2],
+    scale: f32,
+    render_mode: RenderMode,
+}
+
+#[derive(Serialize)]
+struct RecentJson {
+    id: String,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct AppJson<'a> {
+    id: &'a str,
+    name: &'a str,
+    exec: Option<&'a str>,
+    icon: Option<&'a Path>,
+    oklab: OklabJson,
+    srgb_hex: String,
+}
+
+#[derive(Serialize)]
+struct OklabJson {
+    l: f32,
+    a: f32,
+    b: f32,
+}
+
+/// The apps sorted by id, so the output only changes when the apps do.
+fn app_list_json(entries: &DesktopEntries) -> Result<String> {
+    let mut apps = entries
+        .iter()
+        .map(|entry| {
+            let color = entry.avg_icon_color;
+            let srgb = color::to_srgb8(color);
+            AppJson {
+                id: &entry.id,
+                name: &entry.file.entry.name.default,
+                exec: match &entry.file.entry.entry_type {
+                    EntryType::Application(app) => app.exec.as_deref(),
+                    _ => None,
+                },
+                icon: entry.icon_path.as_deref(),
+                oklab: OklabJson {
+                    l: color.l,
+                    a: color.a,
+                    b: color.b,
+                },
+                srgb_hex: format!("#{:02x}{:02x}{:02x}", srgb.red, srgb.green, srgb.blue),
+            }
+        })
+        .collect::<Vec<_>>();
+    apps.sort_by(|a, b| a.id.cmp(b.id));
+    serde_json::to_string_pretty(&apps).wrap_err("serializing the apps")
 }
 
 smithay_client_toolkit::delegate_registry!(App);
@@ -468,5 +2942,247 @@ smithay_client_toolkit::delegate_compositor!(App);
 smithay_client_toolkit::delegate_layer!(App);
 smithay_client_toolkit::delegate_shm!(App);
 wayland_client::delegate_noop!(App: ignore wl_buffer::WlBuffer);
+wayland_client::delegate_noop!(App: ignore WpFractionalScaleManagerV1);
+wayland_client::delegate_noop!(App: ignore WpViewporter);
+wayland_client::delegate_noop!(App: ignore WpViewport);
+
+impl Dispatch<WpFractionalScaleV1, WlSurface> for App {
+    fn event(
+        app: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface: &WlSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        if let Some(output_surface) = app
+            .layer_surfaces
+            .iter_mut()
+            .find(|output_surface| output_surface.layer_surface.wl_surface() == surface)
+        {
+            output_surface.scale_120 = scale;
+            app.resize_surface(surface);
+            app.update_icon_scale();
+        }
+    }
+}
 smithay_client_toolkit::delegate_seat!(App);
 smithay_client_toolkit::delegate_pointer!(App);
+smithay_client_toolkit::delegate_keyboard!(App);
+smithay_client_toolkit::delegate_touch!(App);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::MatchingConfig, desktop::EntryAction};
+
+    #[test]
+    fn clamp_to_surface_keeps_positions_inside() {
+        assert_eq!(clamp_to_surface((10.5, 20.0), (100, 50)), (10.5, 20.0));
+        assert_eq!(clamp_to_surface((-0.5, 50.0), (100, 50)), (0.0, 49.0));
+        assert_eq!(clamp_to_surface((100.0, -3.0), (100, 50)), (99.0, 0.0));
+        assert_eq!(clamp_to_surface((f64::NAN, 1.0), (100, 50)), (0.0, 1.0));
+        // not configured yet
+        assert_eq!(clamp_to_surface((5.0, 5.0), (0, 0)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn physical_size_rounds_half_up() {
+        assert_eq!(physical_size((1920, 1080), 120), (1920, 1080));
+        assert_eq!(physical_size((1920, 1080), 180), (2880, 1620));
+        // 1707.5 and 960.0 at 1.25
+        assert_eq!(physical_size((1366, 768), 150), (1708, 960));
+        assert_eq!(physical_size((1, 1), 150), (1, 1));
+    }
+
+    #[test]
+    fn resolve_surface_size_falls_back_to_the_output() {
+        assert_eq!(resolve_surface_size((800, 600), None), Some((800, 600)));
+        assert_eq!(
+            resolve_surface_size((0, 0), Some((1920, 1080))),
+            Some((1920, 1080))
+        );
+        assert_eq!(resolve_surface_size((1920, 0), Some((0, 1080))), None);
+        assert_eq!(resolve_surface_size((0, 600), None), None);
+        assert_eq!(
+            resolve_surface_size((100_000, 600), Some((2560, 1440))),
+            Some((2560, 1440))
+        );
+        assert_eq!(
+            resolve_surface_size((100_000, 600), None),
+            Some((MAX_SURFACE_SIZE, 600))
+        );
+    }
+
+    #[test]
+    fn fade_progress_eases_from_0_to_1() {
+        let duration = Duration::from_millis(400);
+        assert_eq!(fade_progress(Duration::ZERO, duration), 0.0);
+        assert_eq!(fade_progress(Duration::from_millis(200), duration), 0.5);
+        assert_eq!(fade_progress(duration, duration), 1.0);
+        assert_eq!(fade_progress(Duration::from_secs(5), duration), 1.0);
+        assert_eq!(fade_progress(Duration::ZERO, Duration::ZERO), 1.0);
+        assert!(fade_progress(Duration::from_millis(40), duration) < 0.1);
+    }
+
+    #[test]
+    fn is_bounce_only_for_the_same_app() {
+        let debounce = Duration::from_millis(300);
+        let at = Instant::now();
+        let last = ("a.desktop".to_owned(), at);
+        assert!(!is_bounce(None, "a.desktop", at, debounce));
+        assert!(is_bounce(
+            Some(&last),
+            "a.desktop",
+            at + Duration::from_millis(100),
+            debounce
+        ));
+        assert!(!is_bounce(
+            Some(&last),
+            "a.desktop",
+            at + Duration::from_millis(300),
+            debounce
+        ));
+        assert!(!is_bounce(
+            Some(&last),
+            "b.desktop",
+            at + Duration::from_millis(100),
+            debounce
+        ));
+    }
+
+    #[test]
+    fn reconnect_delay_doubles_until_giving_up() {
+        assert_eq!(reconnect_delay(0), Some(Duration::from_millis(500)));
+        assert_eq!(reconnect_delay(1), Some(Duration::from_secs(1)));
+        assert_eq!(
+            reconnect_delay(MAX_RECONNECTS - 1),
+            Some(Duration::from_millis(500 * 2_u64.pow(MAX_RECONNECTS - 1)))
+        );
+        assert_eq!(reconnect_delay(MAX_RECONNECTS), None);
+    }
+
+    /// A launchable app and one with an icon and an action, in that order.
+    fn two_apps() -> DesktopEntries {
+        let firefox = DesktopEntry::for_test(
+            "firefox.desktop",
+            "Name=Firefox\nExec=firefox %u",
+            Oklab::new(1.0, 0.0, 0.0),
+        );
+        let mut files = DesktopEntry::for_test(
+            "org.gnome.Nautilus.desktop",
+            "Name=Files\nExec=nautilus --new-window %U\nDBusActivatable=true",
+            Oklab::new(0.0, 0.0, 0.0),
+        );
+        files.icon_path = Some("/usr/share/icons/hicolor/48x48/apps/org.gnome.Nautilus.png".into());
+        files.actions.push(EntryAction {
+            id: "new-window".into(),
+            name: "New Window".into(),
+            exec: Some("nautilus --new-window".into()),
+            color: Oklab::new(0.0, 0.0, 0.0),
+        });
+        DesktopEntries::from_entries(vec![firefox, files], &MatchingConfig::default())
+    }
+
+    #[test]
+    fn format_app_list_is_stable() {
+        assert_eq!(
+            format_app_list(&two_apps()),
+            concat!(
+                "firefox.desktop             Firefox   1.000  0.000  0.000  #ffffff  <no icon>\n",
+                "org.gnome.Nautilus.desktop  Files     0.000  0.000  0.000  #000000  ",
+                "/usr/share/icons/hicolor/48x48/apps/org.gnome.Nautilus.png\n",
+                "                            -> New Window\n",
+            )
+        );
+    }
+
+    #[test]
+    fn unchanged_voronoi_progress_skips_the_frame() {
+        assert!(!changes_frame(0.0, 0.0));
+        assert!(!changes_frame(1.0, 1.0));
+        assert!(changes_frame(0.0, 1.0));
+        assert!(changes_frame(
+            1.0,
+            idle_voronoi_progress(&Config::default())
+        ));
+    }
+
+    #[test]
+    fn mix_voronoi_washes_out_colors_without_an_app() {
+        let mut config = Config::default();
+        config.matching.max_distance = Some(0.1);
+        let seeds = [Oklab::new(0.7, 0.2, 0.0)];
+
+        let near = mix_voronoi(Oklab::new(0.7, 0.15, 0.0), &seeds, &[], &config, 1.0);
+        assert!((near.a - 0.2).abs() < 1e-6, "{near:?}");
+        let far = Oklab::new(0.7, -0.2, 0.1);
+        let washed = mix_voronoi(far, &seeds, &[], &config, 1.0);
+        assert_eq!(washed.into_components(), (0.7, 0.0, 0.0));
+        let halfway = mix_voronoi(far, &seeds, &[], &config, 0.5);
+        assert!((halfway.a + 0.1).abs() < 1e-6 && (halfway.b - 0.05).abs() < 1e-6);
+
+        let gray = mix_voronoi(far, &[], &[], &config, 0.0);
+        assert_eq!(
+            gray.into_components(),
+            (config.gradient.lightness, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn app_list_json_schema() {
+        let json = app_list_json(&two_apps()).unwrap();
+        let apps: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            apps,
+            serde_json::json!([
+                {
+                    "id": "firefox.desktop",
+                    "name": "Firefox",
+                    "exec": "firefox %u",
+                    "icon": null,
+                    "oklab": { "l": 1.0, "a": 0.0, "b": 0.0 },
+                    "srgb_hex": "#ffffff",
+                },
+                {
+                    "id": "org.gnome.Nautilus.desktop",
+                    "name": "Files",
+                    "exec": "nautilus --new-window %U",
+                    "icon": "/usr/share/icons/hicolor/48x48/apps/org.gnome.Nautilus.png",
+                    "oklab": { "l": 0.0, "a": 0.0, "b": 0.0 },
+                    "srgb_hex": "#000000",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn reveal_fades_in_once_loaded() {
+        // while loading, only the gradient is shown
+        assert_eq!(Reveal::Hidden.progress(250), 0.0);
+
+        let fading = Reveal::loaded(true, 250);
+        assert!(matches!(fading, Reveal::Fading(_)));
+        assert!(fading.progress(250) < 1.0);
+        let faded = Reveal::Fading(Instant::now() - Duration::from_secs(1));
+        assert_eq!(faded.progress(250), 1.0);
+
+        // nothing was drawn to fade from, or fading is off
+        assert!(matches!(Reveal::loaded(false, 250), Reveal::Shown));
+        assert!(matches!(Reveal::loaded(true, 0), Reveal::Shown));
+        assert_eq!(Reveal::Shown.progress(250), 1.0);
+    }
+
+    #[test]
+    fn ready_is_only_notified_once() {
+        let mut notified_ready = false;
+        // the first configure
+        assert!(first_time(&mut notified_ready));
+        // reconfigures and other outputs
+        assert!(!first_time(&mut notified_ready));
+        assert!(!first_time(&mut notified_ready));
+    }
+}
@@ -0,0 +1,112 @@
+use image::{Rgba, RgbaImage};
+use palette::Oklab;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use crate::{color, desktop::DesktopEntry, text};
+
+const PADDING: u32 = 8;
+const SWATCH_SIZE: u32 = text::GLYPH_SIZE;
+const ROW_HEIGHT: u32 = text::GLYPH_SIZE + PADDING;
+
+const BACKGROUND: Rgba<u8> = Rgba([30, 30, 30, 230]);
+const HOVERED_BACKGROUND: Rgba<u8> = Rgba([80, 80, 80, 240]);
+const TEXT_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// A list of an app's Desktop Actions, opened by right-clicking.
+pub struct ActionMenu {
+    pub surface: WlSurface,
+    pub entry_id: String,
+    /// Top left corner in surface coordinates.
+    pub origin: (u32, u32),
+    pub hovered: Option<usize>,
+    items: Vec<(String, Oklab)>,
+}
+
+impl ActionMenu {
+    /// Opens the menu at the cursor, moved so it stays on the surface.
+    pub fn new(
+        surface: WlSurface,
+        entry: &DesktopEntry,
+        cursor: (u32, u32),
+        surface_size: (u32, u32),
+    ) -> Self {
+        let mut menu = Self {
+            surface,
+            entry_id: entry.id.clone(),
+            origin: (0, 0),
+            hovered: None,
+            items: entry
+                .actions
+                .iter()
+                .map(|action| (action.name.clone(), action.color))
+                .collect(),
+        };
+        let (width, height) = menu.size();
+        menu.origin = (
+            cursor.0.min(surface_size.0.saturating_sub(width)),
+            cursor.1.min(surface_size.1.saturating_sub(height)),
+        );
+        menu
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        let text_width = self
+            .items
+            .iter()
+            .map(|(name, _)| text::text_width(name))
+            .max()
+            .unwrap_or(0);
+        (
+            PADDING + SWATCH_SIZE + PADDING + text_width + PADDING,
+            self.items.len() as u32 * ROW_HEIGHT + PADDING,
+        )
+    }
+
+    /// The index of the item at the surface coordinates.
+    pub fn item_at(&self, x: u32, y: u32) -> Option<usize> {
+        let (width, _) = self.size();
+        let x = x.checked_sub(self.origin.0)?;
+        let y = y.checked_sub(self.origin.1 + PADDING / 2)?;
+        if x >= width {
+            return None;
+        }
+        let index = (y / ROW_HEIGHT) as usize;
+        (index < self.items.len()).then_some(index)
+    }
+
+    pub fn render(&self) -> RgbaImage {
+        let (width, height) = self.size();
+        let mut image = RgbaImage::from_pixel(width, height, BACKGROUND);
+
+        for (i, (name, color)) in self.items.iter().enumerate() {
+            let row_y = PADDING / 2 + i as u32 * ROW_HEIGHT;
+
+            if self.hovered == Some(i) {
+                for y in row_y..row_y + ROW_HEIGHT {
+                    for x in 0..width {
+                        image.put_pixel(x, y, HOVERED_BACKGROUND);
+                    }
+                }
+            }
+
+            let content_y = row_y + PADDING / 2;
+            let swatch = color::to_srgb8(*color);
+            let swatch = Rgba([swatch.red, swatch.green, swatch.blue, 255]);
+            for y in content_y..content_y + SWATCH_SIZE {
+                for x in PADDING..PADDING + SWATCH_SIZE {
+                    image.put_pixel(x, y, swatch);
+                }
+            }
+
+            text::draw_text(
+                &mut image,
+                PADDING + SWATCH_SIZE + PADDING,
+                content_y,
+                name,
+                TEXT_COLOR,
+            );
+        }
+
+        image
+    }
+}
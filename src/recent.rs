@@ -0,0 +1,100 @@
+use image::{Rgba, RgbaImage, imageops::FilterType};
+
+use crate::{color, config::Edge, desktop::DesktopEntry};
+
+const PADDING: u32 = 8;
+const SWATCH_SIZE: u32 = 32;
+
+const BACKGROUND: Rgba<u8> = Rgba([30, 30, 30, 200]);
+
+/// The most recently launched apps as a row of swatches along one edge of the surface,
+/// shown whenever nothing else is. Clicking one launches it again.
+pub struct RecentApps {
+    pub ids: Vec<String>,
+    edge: Edge,
+    swatches: Vec<RgbaImage>,
+}
+
+impl RecentApps {
+    pub fn new(entries: &[&DesktopEntry], edge: Edge) -> Self {
+        Self {
+            ids: entries.iter().map(|entry| entry.id.clone()).collect(),
+            edge,
+            swatches: entries.iter().map(|entry| swatch(entry)).collect(),
+        }
+    }
+
+    fn is_vertical(&self) -> bool {
+        matches!(self.edge, Edge::Left | Edge::Right)
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        let length = self.ids.len() as u32 * (SWATCH_SIZE + PADDING) + PADDING;
+        let thickness = SWATCH_SIZE + 2 * PADDING;
+        if self.is_vertical() {
+            (thickness, length)
+        } else {
+            (length, thickness)
+        }
+    }
+
+    /// Top left corner in surface coordinates, centered along the edge.
+    pub fn origin(&self, (surface_width, surface_height): (u32, u32)) -> (u32, u32) {
+        let (width, height) = self.size();
+        let center_x = surface_width.saturating_sub(width) / 2;
+        let center_y = surface_height.saturating_sub(height) / 2;
+        match self.edge {
+            Edge::Top => (center_x, 0),
+            Edge::Bottom => (center_x, surface_height.saturating_sub(height)),
+            Edge::Left => (0, center_y),
+            Edge::Right => (surface_width.saturating_sub(width), center_y),
+        }
+    }
+
+    /// The index of the app at the surface coordinates, the gaps between them don't count.
+    pub fn item_at(&self, (x, y): (u32, u32), surface_size: (u32, u32)) -> Option<usize> {
+        let (origin_x, origin_y) = self.origin(surface_size);
+        let (width, height) = self.size();
+        let x = x.checked_sub(origin_x).filter(|&x| x < width)?;
+        let y = y.checked_sub(origin_y).filter(|&y| y < height)?;
+        let (along, across) = if self.is_vertical() { (y, x) } else { (x, y) };
+
+        let along = along.checked_sub(PADDING)?;
+        let across = across.checked_sub(PADDING)?;
+        let index = (along / (SWATCH_SIZE + PADDING)) as usize;
+        let in_swatch = along % (SWATCH_SIZE + PADDING) < SWATCH_SIZE && across < SWATCH_SIZE;
+        (in_swatch && index < self.ids.len()).then_some(index)
+    }
+
+    pub fn render(&self) -> RgbaImage {
+        let (width, height) = self.size();
+        let mut image = RgbaImage::from_pixel(width, height, BACKGROUND);
+        for (i, swatch) in self.swatches.iter().enumerate() {
+            let along = i64::from(PADDING + i as u32 * (SWATCH_SIZE + PADDING));
+            let across = i64::from(PADDING);
+            let (x, y) = if self.is_vertical() {
+                (across, along)
+            } else {
+                (along, across)
+            };
+            image::imageops::replace(&mut image, swatch, x, y);
+        }
+        image
+    }
+}
+
+/// The app's color with its icon on top, if the icon is kept around.
+fn swatch(entry: &DesktopEntry) -> RgbaImage {
+    let color = color::to_srgb8(entry.avg_icon_color);
+    let mut swatch = RgbaImage::from_pixel(
+        SWATCH_SIZE,
+        SWATCH_SIZE,
+        Rgba([color.red, color.green, color.blue, 255]),
+    );
+    if let Some(thumbnail) = &entry.thumbnail {
+        let icon =
+            image::imageops::resize(thumbnail, SWATCH_SIZE, SWATCH_SIZE, FilterType::Triangle);
+        image::imageops::overlay(&mut swatch, &icon, 0, 0);
+    }
+    swatch
+}
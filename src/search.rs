@@ -0,0 +1,110 @@
+use image::{Rgba, RgbaImage};
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use crate::{
+    color,
+    desktop::{DesktopEntries, DesktopEntry},
+    text,
+};
+
+const PADDING: u32 = 12;
+const MIN_WIDTH: u32 = 400;
+
+const BACKGROUND: Rgba<u8> = Rgba([30, 30, 30, 230]);
+const TEXT_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const MATCH_COLOR: Rgba<u8> = Rgba([180, 180, 180, 255]);
+
+/// Typing on the focused wallpaper to search for an app by name.
+pub struct AppSearch {
+    pub surface: WlSurface,
+    pub query: String,
+}
+
+impl AppSearch {
+    pub fn new(surface: WlSurface) -> Self {
+        Self {
+            surface,
+            query: String::new(),
+        }
+    }
+
+    /// The query with the best match below it, on a color swatch of the match.
+    pub fn render(&self, best_match: Option<&DesktopEntry>) -> RgbaImage {
+        let prompt = format!("> {}_", self.query);
+        let match_name =
+            best_match.map_or("no match", |entry| entry.file.entry.name.default.as_str());
+
+        let width = (text::text_width(&prompt).max(text::text_width(match_name)) + 2 * PADDING)
+            .max(MIN_WIDTH);
+        let height = 2 * text::GLYPH_SIZE + 3 * PADDING;
+        let mut image = RgbaImage::from_pixel(width, height, BACKGROUND);
+
+        if let Some(entry) = best_match {
+            let swatch = color::to_srgb8(entry.avg_icon_color);
+            let swatch = Rgba([swatch.red, swatch.green, swatch.blue, 255]);
+            let swatch_top = text::GLYPH_SIZE + PADDING + PADDING / 2;
+            for y in swatch_top..height {
+                for x in 0..width {
+                    image.put_pixel(x, y, swatch);
+                }
+            }
+        }
+
+        text::draw_text(&mut image, PADDING, PADDING, &prompt, TEXT_COLOR);
+        text::draw_text(
+            &mut image,
+            PADDING,
+            text::GLYPH_SIZE + 2 * PADDING,
+            match_name,
+            if best_match.is_some() {
+                TEXT_COLOR
+            } else {
+                MATCH_COLOR
+            },
+        );
+
+        image
+    }
+
+    /// Horizontally centered in the upper third of the surface.
+    pub fn origin(surface_size: (u32, u32), image_size: (u32, u32)) -> (u32, u32) {
+        (
+            surface_size.0.saturating_sub(image_size.0) / 2,
+            (surface_size.1 / 3).saturating_sub(image_size.1 / 2),
+        )
+    }
+}
+
+/// Apps whose name contains the query, best matches first.
+///
+/// Names starting with the query rank above names with a word starting with the query,
+/// which rank above names that just contain it somewhere. Shorter names win ties.
+pub fn filter_apps<'a>(entries: &'a DesktopEntries, query: &str) -> Vec<&'a DesktopEntry> {
+    let query = query.to_lowercase();
+
+    let mut matches = entries
+        .iter()
+        .filter_map(|entry| {
+            let name = entry.file.entry.name.default.to_lowercase();
+            let position = name.find(&query)?;
+            let rank = if position == 0 {
+                0
+            } else if name.split_whitespace().any(|word| word.starts_with(&query)) {
+                1
+            } else {
+                2
+            };
+            Some((rank, name.len(), entry))
+        })
+        .collect::<Vec<_>>();
+
+    matches.sort_by(|(rank_a, len_a, a), (rank_b, len_b, b)| {
+        (rank_a, len_a, &a.file.entry.name.default).cmp(&(
+            rank_b,
+            len_b,
+            &b.file.entry.name.default,
+        ))
+    });
+
+    matches.into_iter().map(|(_, _, entry)| entry).collect()
+}
@@ -1,13 +1,77 @@
 struct Input {
     size: vec2<f32>,
     voronoi_progress: f32,
+    dither: u32,
+    lightness: f32,
+    use_image: u32,
+    image_origin: vec2<f32>,
+    image_size: vec2<f32>,
+    use_overlay: u32,
+    overlay_origin: vec2<f32>,
+    overlay_size: vec2<f32>,
+    gradient_offset: vec3<f32>,
+    chroma: f32,
+    chroma_offset: vec2<f32>,
+    // a wl_output.transform
+    transform: u32,
+    // how many entries of desktop_colors are used, the buffer is never empty
+    seed_count: u32,
+    // how far the film grain moves the lightness, 0 disables it
+    grain: f32,
+    // 0 is oklab, 1 cielab and 2 weighted oklab
+    metric: u32,
+    lightness_weight: f32,
+    // a ring around where was clicked, in buffer pixels
+    ripple_radius: f32,
+    ripple_center: vec2<f32>,
+    // 0 if there is no ripple
+    ripple_alpha: f32,
+    // colors farther than this from every seed match none, 0 if there is no limit
+    max_distance: f32,
+};
+
+struct Seed {
+    // oklab, and the weight taken off the squared distance in w
+    color: vec4f,
+    // the color in the space of the matching metric
+    matched: vec4f,
+};
+
+struct IconSprite {
+    center: vec2<f32>,
+    // zero if the sprite isn't drawn
+    size: f32,
+    uv_origin: vec2<f32>,
+    uv_size: vec2<f32>,
 };
 
 @group(0) @binding(0)
 var<uniform> input: Input;
+@group(0) @binding(1)
+var<storage, read> icon_sprites: array<IconSprite>;
 
 @group(1) @binding(0)
-var<storage, read> desktop_colors: array<vec4f>;
+var<storage, read> desktop_colors: array<Seed>;
+@group(1) @binding(1)
+var icon_atlas: texture_2d<f32>;
+@group(1) @binding(2)
+var icon_sampler: sampler;
+
+@group(2) @binding(0)
+var wallpaper_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var wallpaper_sampler: sampler;
+
+@group(3) @binding(0)
+var overlay_texture: texture_2d<f32>;
+@group(3) @binding(1)
+var overlay_sampler: sampler;
+
+// only bound for cs_pick, which doesn't use the overlay
+@group(3) @binding(2)
+var<uniform> pick_position: vec2<f32>;
+@group(3) @binding(3)
+var<storage, read_write> pick_result: u32;
 
 @vertex
 fn vs_main(
@@ -27,36 +91,227 @@ fn vs_main(
 }
 
 @fragment
-fn fs_main(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
-    var posf = pos.xy / input.size;
+fn fs_main(@builtin(position) buffer_pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let pos = surface_position(buffer_pos.xy);
+    var color = base_color(pos);
+
+    // too far from every app, washed out so it's clear that clicking does nothing
+    var voronoi_color = vec3f(color.x, 0.0, 0.0);
+    let best = nearest_color_index(color);
+    if (best < input.seed_count) {
+        voronoi_color = desktop_colors[best].color.xyz;
+    }
+     
+    color = mix(color, voronoi_color, input.voronoi_progress);
+
+    // keep it in sync with the cpu implementation
+    // a plain gray without apps, so it doesn't look like everything is fine
+    if (input.seed_count == 0u) {
+        color = vec3f(input.lightness, 0.0, 0.0);
+    }
+
+    // keep it in sync with the cpu implementation
+    // only the displayed color, picking works on the clean one
+    if (input.grain > 0.0) {
+        let pixel = vec2u(max(pos, vec2f(0.0)));
+        color.x += (grain_noise(pixel.x, pixel.y) - 0.5) * input.grain;
+    }
+
+    // keep it in sync with the cpu implementation
+    var srgbcolor = oklab_to_linear_srgb(color);
+
+    // the icons fade in with the voronoi diagram, the atlas is premultiplied
+    let icon = icon_at(pos);
+    if (icon.a > 0.0) {
+        let progress = input.voronoi_progress;
+        let background = clamp(srgbcolor, vec3f(0.0), vec3f(1.0));
+        srgbcolor = background * (1.0 - icon.a * progress) + icon.rgb * progress;
+    }
+
+    if (input.ripple_alpha > 0.0) {
+        // antialiased, about 3 pixels wide
+        let ring = abs(distance(pos, input.ripple_center) - input.ripple_radius);
+        let coverage = clamp(2.0 - ring, 0.0, 1.0);
+        let ripple_alpha = coverage * input.ripple_alpha;
+        srgbcolor = mix(clamp(srgbcolor, vec3f(0.0), vec3f(1.0)), vec3f(1.0), ripple_alpha);
+    }
+
+    let overlay_uv = (pos - input.overlay_origin) / input.overlay_size;
+    let overlay = textureSample(overlay_texture, overlay_sampler, overlay_uv);
+    let in_overlay = all(overlay_uv >= vec2f(0.0)) && all(overlay_uv < vec2f(1.0));
+    if (input.use_overlay != 0u && in_overlay) {
+        srgbcolor = mix(clamp(srgbcolor, vec3f(0.0), vec3f(1.0)), overlay.rgb, overlay.a);
+    }
+
+    if (input.dither != 0u) {
+        srgbcolor = dither(srgbcolor, buffer_pos.xy);
+    }
+
+    return vec4<f32>(srgbcolor.x, srgbcolor.y, srgbcolor.z, 1.0);
+}
+
+// The index of the app at `pick_position` (a pixel center), chosen exactly like fs_main does.
+@compute @workgroup_size(1)
+fn cs_pick() {
+    pick_result = nearest_color_index(base_color(pick_position));
+}
+
+// Undoes the buffer transform, turning a position in the buffer into one on the surface.
+// The surface is `input.size` large, the buffer has width and height swapped for 90 and 270.
+fn surface_position(pos: vec2f) -> vec2f {
+    let size = input.size;
+    switch input.transform {
+        // 90
+        case 1u: { return vec2f(size.x - pos.y, pos.x); }
+        // 180
+        case 2u: { return size - pos; }
+        // 270
+        case 3u: { return vec2f(pos.y, size.y - pos.x); }
+        // flipped
+        case 4u: { return vec2f(size.x - pos.x, pos.y); }
+        // flipped 90
+        case 5u: { return pos.yx; }
+        // flipped 180
+        case 6u: { return vec2f(pos.x, size.y - pos.y); }
+        // flipped 270
+        case 7u: { return size - pos.yx; }
+        default: { return pos; }
+    }
+}
+
+// The first icon sprite covering the pixel, transparent if there is none.
+fn icon_at(pos: vec2f) -> vec4f {
+    for (var i: u32 = 0; i < arrayLength(&icon_sprites); i++) {
+        let sprite = icon_sprites[i];
+        let uv = (pos - sprite.center) / sprite.size + 0.5;
+        if (sprite.size > 0.0 && all(uv >= vec2f(0.0)) && all(uv < vec2f(1.0))) {
+            let atlas_uv = sprite.uv_origin + uv * sprite.uv_size;
+            return textureSampleLevel(icon_atlas, icon_sampler, atlas_uv, 0.0);
+        }
+    }
+    return vec4f(0.0);
+}
+
+// The color at the pixel, before the voronoi diagram is mixed in.
+fn base_color(pos: vec2f) -> vec3f {
+    var posf = pos / input.size;
 
     var color = vec3<f32>(
-        0.7,
-        posf.x * 0.8 - 0.4,
-        posf.y * 0.7 - 0.4,
-    );
+        input.lightness,
+        (posf.x * 0.8 - 0.4) * input.chroma + input.chroma_offset.x,
+        (posf.y * 0.7 - 0.4) * input.chroma + input.chroma_offset.y,
+    ) + input.gradient_offset;
+
+    // keep it in sync with the cpu implementation
+    let image_uv = (pos - input.image_origin) / input.image_size;
+    // the texture has no mipmaps, an explicit level also works in the compute shader
+    let image_color = textureSampleLevel(wallpaper_texture, wallpaper_sampler, image_uv, 0.0).rgb;
+    let in_image = all(image_uv >= vec2f(0.0)) && all(image_uv < vec2f(1.0));
+    if (input.use_image != 0u && in_image) {
+        color = linear_srgb_to_oklab(image_color);
+    }
+    return color;
+}
 
-    var best = vec3f(0.0, 0.0, 0.0);
+// The index of the closest desktop color, or 0xffffffff if there are none within
+// input.max_distance.
+fn nearest_color_index(color: vec3f) -> u32 {
+    let matched = match_coordinates(color);
+    var best = 0xffffffffu;
     var best_score = 1000000000000.0;
-    for (var i: u32 = 0; i < arrayLength(&desktop_colors); i++) {
-        var elem = desktop_colors[i].xyz;
-        var score = diff_colors(elem, color);
+    var best_distance = 0.0;
+    for (var i: u32 = 0; i < input.seed_count; i++) {
+        // popular apps get larger cells
+        let seed = desktop_colors[i];
+        let distance = diff_colors(seed.matched.xyz, matched);
+        var score = distance - seed.color.w;
         if (score < best_score) {
-            best = elem;
+            best = i;
             best_score = score;
+            best_distance = distance;
         }
     }
-    var voronoi_color = best;
-     
-    color = mix(color, voronoi_color, input.voronoi_progress);
-
     // keep it in sync with the cpu implementation
-    var srgbcolor = oklab_to_linear_srgb(color);
+    if (input.max_distance > 0.0 && best_distance > input.max_distance * input.max_distance) {
+        return 0xffffffffu;
+    }
+    return best;
+}
 
-    return vec4<f32>(srgbcolor.x, srgbcolor.y, srgbcolor.z, 1.0);
+// Ordered (4x4 Bayer) dithering. The render target quantizes to 8-bit sRGB,
+// so the offset has to be applied in sRGB space to be exactly one step wide.
+// keep it in sync with color::dither_threshold and color::to_srgb8_dithered
+fn dither(linear: vec3f, pos: vec2f) -> vec3f {
+    var bayer = array<f32, 16>(
+        0.0, 8.0, 2.0, 10.0,
+        12.0, 4.0, 14.0, 6.0,
+        3.0, 11.0, 1.0, 9.0,
+        15.0, 7.0, 13.0, 5.0,
+    );
+    let x = u32(pos.x) % 4u;
+    let y = u32(pos.y) % 4u;
+    let threshold = (bayer[y * 4u + x] + 0.5) / 16.0;
+
+    let srgb = linear_to_srgb(clamp(linear, vec3f(0.0), vec3f(1.0)));
+    return srgb_to_linear(srgb + (threshold - 0.5) / 255.0);
+}
+
+fn linear_to_srgb(linear: vec3f) -> vec3f {
+    let low = linear * 12.92;
+    let high = 1.055 * pow(linear, vec3f(1.0 / 2.4)) - 0.055;
+    return select(high, low, linear <= vec3f(0.0031308));
 }
 
+fn srgb_to_linear(srgb: vec3f) -> vec3f {
+    let low = srgb / 12.92;
+    let high = pow((max(srgb, vec3f(0.0)) + 0.055) / 1.055, vec3f(2.4));
+    return select(high, low, srgb <= vec3f(0.04045));
+}
+
+// keep it in sync with the cpu implementation
+// Where the color is in the space the closest app is found in, distances there are Euclidean.
 // keep it in sync with the cpu implementation
+fn match_coordinates(oklab: vec3f) -> vec3f {
+    switch input.metric {
+        // cielab, scaled to about the range of oklab
+        case 1u: {
+            let rgb = oklab_to_linear_srgb(oklab);
+            let xyz = vec3f(
+                0.4124564 * rgb.x + 0.3575761 * rgb.y + 0.1804375 * rgb.z,
+                0.2126729 * rgb.x + 0.7151522 * rgb.y + 0.0721750 * rgb.z,
+                0.0193339 * rgb.x + 0.1191920 * rgb.y + 0.9503041 * rgb.z,
+            ) / vec3f(0.95047, 1.0, 1.08883);
+            let f = vec3f(lab_f(xyz.x), lab_f(xyz.y), lab_f(xyz.z));
+            return vec3f(1.16 * f.y - 0.16, 5.0 * (f.x - f.y), 2.0 * (f.y - f.z));
+        }
+        // weighted oklab
+        case 2u: {
+            return vec3f(oklab.x * sqrt(input.lightness_weight), oklab.yz);
+        }
+        default: {
+            return oklab;
+        }
+    }
+}
+
+fn lab_f(t: f32) -> f32 {
+    let epsilon = 216.0 / 24389.0;
+    let kappa = 24389.0 / 27.0;
+    if (t > epsilon) {
+        return pow(t, 1.0 / 3.0);
+    }
+    return (kappa * t + 16.0) / 116.0;
+}
+
+// A stable pseudo-random value in [0, 1) for every pixel (PCG hash).
+// keep it in sync with the cpu implementation
+fn grain_noise(x: u32, y: u32) -> f32 {
+    let state = (x * 1973u + y * 9277u) * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    let hash = (word >> 22u) ^ word;
+    return f32(hash >> 8u) / 16777216.0;
+}
+
 fn diff_colors(oklab_a: vec3f, oklab_b: vec3f) -> f32 {
     var diff = oklab_a - oklab_b;
     var diff_sq = diff * diff;
@@ -76,3 +331,17 @@ fn oklab_to_linear_srgb(oklab: vec3f) -> vec3f {
         1.7076147010 * s + (-0.0041960863 * l + -0.7034186147 * m),
     );
 }
+
+fn linear_srgb_to_oklab(rgb: vec3f) -> vec3f {
+    let l = 0.4122214708 * rgb.x + 0.5363325363 * rgb.y + 0.0514459929 * rgb.z;
+    let m = 0.2119034982 * rgb.x + 0.6806995451 * rgb.y + 0.1073969566 * rgb.z;
+    let s = 0.0883024619 * rgb.x + 0.2817188376 * rgb.y + 0.6299787005 * rgb.z;
+    let l_ = pow(max(l, 0.0), 1.0 / 3.0);
+    let m_ = pow(max(m, 0.0), 1.0 / 3.0);
+    let s_ = pow(max(s, 0.0), 1.0 / 3.0);
+    return vec3<f32>(
+        0.2104542553 * l_ + 0.7936177850 * m_ - 0.0040720468 * s_,
+        1.9779984951 * l_ - 2.4285922050 * m_ + 0.4505937099 * s_,
+        0.0259040371 * l_ + 0.7827717662 * m_ - 0.8086757660 * s_,
+    );
+}
@@ -0,0 +1,182 @@
+use image::RgbaImage;
+use palette::Oklab;
+
+use crate::{
+    color,
+    config::GradientConfig,
+    desktop::{DesktopEntries, THUMBNAIL_SIZE},
+};
+
+/// Icons smaller than this aren't recognizable anymore, so they aren't drawn at all.
+const MIN_SPRITE_SIZE: f32 = 16.0;
+const MAX_SPRITE_SIZE: f32 = THUMBNAIL_SIZE as f32;
+/// Transparent space around every icon in the atlas, so filtering doesn't bleed neighbors in.
+const ATLAS_PADDING: u32 = 1;
+
+/// All app thumbnails in one texture with premultiplied alpha, in the same order as the apps.
+pub struct IconAtlas {
+    pub image: RgbaImage,
+    /// The uv origin and size of every app's icon, `None` for apps without a thumbnail.
+    pub uvs: Vec<Option<([f32; 2], [f32; 2])>>,
+}
+
+/// An icon drawn on the surface, centered on the app's seed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+    pub center: [f32; 2],
+    pub size: f32,
+}
+
+/// Packs the thumbnails into a square grid, `None` if no app has one.
+pub fn build_atlas(entries: &DesktopEntries) -> Option<IconAtlas> {
+    if entries.iter().all(|entry| entry.thumbnail.is_none()) {
+        return None;
+    }
+
+    let cell = THUMBNAIL_SIZE + 2 * ATLAS_PADDING;
+    let columns = (entries.count() as f32).sqrt().ceil() as u32;
+    let rows = (entries.count() as u32).div_ceil(columns);
+    let (width, height) = (columns * cell, rows * cell);
+    let mut image = RgbaImage::new(width, height);
+
+    let uvs = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let thumbnail = entry.thumbnail.as_ref()?;
+            let x = (i as u32 % columns) * cell + ATLAS_PADDING;
+            let y = (i as u32 / columns) * cell + ATLAS_PADDING;
+            image::imageops::replace(&mut image, thumbnail, x.into(), y.into());
+
+            Some((
+                [x as f32 / width as f32, y as f32 / height as f32],
+                [
+                    thumbnail.width() as f32 / width as f32,
+                    thumbnail.height() as f32 / height as f32,
+                ],
+            ))
+        })
+        .collect();
+
+    // the atlas is sampled with linear filtering
+    for pixel in image.pixels_mut() {
+        pixel.0 = color::premultiply(pixel.0);
+    }
+
+    Some(IconAtlas { image, uvs })
+}
+
+/// Where the gradient has the app's color, ignoring lightness. This is where the app's voronoi
+/// cell is, as long as the seed isn't pulled elsewhere by an app with a closer lightness.
+// keep it in sync with color_for_pixel
+pub fn seed_position(
+    color: Oklab,
+    (width, height): (u32, u32),
+    gradient: &GradientConfig,
+    offset: Oklab,
+) -> [f32; 2] {
+    let a = (color.a - offset.a - gradient.chroma_offset[0]) / gradient.chroma;
+    let b = (color.b - offset.b - gradient.chroma_offset[1]) / gradient.chroma;
+    let xf = (a + 0.4) / 0.8;
+    let yf = (b + 0.4) / 0.7;
+    [xf * width as f32, yf * height as f32]
+}
+
+/// One sprite per color. Sprites get half the distance to the nearest other seed so they
+/// don't overlap, sprites that would be too small or are off the surface get a size of zero.
+pub fn layout_sprites(
+    colors: impl Iterator<Item = Oklab>,
+    surface_size: (u32, u32),
+    gradient: &GradientConfig,
+    offset: Oklab,
+) -> Vec<Sprite> {
+    let seeds = colors
+        .map(|color| seed_position(color, surface_size, gradient, offset))
+        .collect::<Vec<_>>();
+
+    seeds
+        .iter()
+        .enumerate()
+        .map(|(i, &center)| {
+            let nearest = seeds
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, other)| (other[0] - center[0]).hypot(other[1] - center[1]))
+                .fold(f32::INFINITY, f32::min);
+
+            let size = (nearest / 2.0).min(MAX_SPRITE_SIZE);
+            let on_surface = (0.0..surface_size.0 as f32).contains(&center[0])
+                && (0.0..surface_size.1 as f32).contains(&center[1]);
+            Sprite {
+                center,
+                size: if on_surface && size >= MIN_SPRITE_SIZE {
+                    size
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const SIZE: (u32, u32) = (800, 600);
+
+    /// The color of the default gradient at a position, which can be off the surface.
+    fn color_at(x: f32, y: f32) -> Oklab {
+        Oklab::new(
+            0.7,
+            x / SIZE.0 as f32 * 0.8 - 0.4,
+            y / SIZE.1 as f32 * 0.7 - 0.4,
+        )
+    }
+
+    #[test]
+    fn seed_position_is_where_color_for_pixel_has_the_color() {
+        let mut config = Config::default();
+        config.gradient.chroma = 1.3;
+        config.gradient.chroma_offset = [0.02, -0.05];
+        let offset = Oklab::new(0.05, -0.03, 0.04);
+
+        for (x, y) in [(0, 0), (300, 200), (799, 599)] {
+            let color = crate::color_for_pixel(x, y, SIZE.0, SIZE.1, &config, offset);
+            let [seed_x, seed_y] = seed_position(color, SIZE, &config.gradient, offset);
+            assert!((seed_x - x as f32).abs() < 0.01, "{seed_x} != {x}");
+            assert!((seed_y - y as f32).abs() < 0.01, "{seed_y} != {y}");
+        }
+    }
+
+    #[test]
+    fn layout_sprites_hides_small_and_off_surface_sprites() {
+        let colors = [
+            // 50 apart, they get half of that each
+            color_at(100.0, 100.0),
+            color_at(150.0, 100.0),
+            // too close to each other to show
+            color_at(600.0, 400.0),
+            color_at(610.0, 400.0),
+            // off the surface
+            color_at(-50.0, 300.0),
+            // far from everything, as large as sprites get
+            color_at(400.0, 580.0),
+        ];
+        let sprites = layout_sprites(
+            colors.into_iter(),
+            SIZE,
+            &Config::default().gradient,
+            Oklab::new(0.0, 0.0, 0.0),
+        );
+
+        let sizes = sprites.iter().map(|sprite| sprite.size).collect::<Vec<_>>();
+        let expected = [25.0, 25.0, 0.0, 0.0, 0.0, MAX_SPRITE_SIZE];
+        for (size, expected) in sizes.iter().zip(expected) {
+            assert!((size - expected).abs() < 0.01, "{sizes:?}");
+        }
+        assert!((sprites[0].center[0] - 100.0).abs() < 0.01);
+    }
+}
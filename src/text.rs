@@ -0,0 +1,54 @@
+use font8x8::{BASIC_FONTS, UnicodeFonts};
+use image::{Rgba, RgbaImage};
+
+/// Every glyph pixel is drawn as a square of this many pixels.
+pub const SCALE: u32 = 2;
+pub const GLYPH_SIZE: u32 = 8 * SCALE;
+
+const LABEL_PADDING: u32 = 12;
+const LABEL_BACKGROUND: Rgba<u8> = Rgba([30, 30, 30, 230]);
+const LABEL_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+pub fn text_width(text: &str) -> u32 {
+    text.chars().count() as u32 * GLYPH_SIZE
+}
+
+/// Draws `text` with its top left corner at `x`, `y`, clipping at the image bounds.
+/// Characters the font doesn't have are drawn as `?`.
+pub fn draw_text(image: &mut RgbaImage, x: u32, y: u32, text: &str, color: Rgba<u8>) {
+    for (i, c) in text.chars().enumerate() {
+        let Some(glyph) = BASIC_FONTS.get(c).or_else(|| BASIC_FONTS.get('?')) else {
+            continue;
+        };
+        let glyph_x = x + i as u32 * GLYPH_SIZE;
+
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..8 {
+                // bit 0 is the leftmost pixel
+                if bits & (1 << col) == 0 {
+                    continue;
+                }
+                for dy in 0..SCALE {
+                    for dx in 0..SCALE {
+                        let px = glyph_x + col * SCALE + dx;
+                        let py = y + row as u32 * SCALE + dy;
+                        if px < image.width() && py < image.height() {
+                            image.put_pixel(px, py, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A single line of text on a dark background.
+pub fn label(text: &str) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(
+        text_width(text) + 2 * LABEL_PADDING,
+        GLYPH_SIZE + 2 * LABEL_PADDING,
+        LABEL_BACKGROUND,
+    );
+    draw_text(&mut image, LABEL_PADDING, LABEL_PADDING, text, LABEL_COLOR);
+    image
+}
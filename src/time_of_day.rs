@@ -0,0 +1,91 @@
+use palette::Oklab;
+
+use crate::config::{Interpolation, TimeOfDayConfig};
+
+/// How far the gradient is currently shifted, no shift at all if the feature is disabled.
+pub fn current_offset(config: &TimeOfDayConfig) -> Oklab {
+    if !config.enabled {
+        return Oklab::new(0.0, 0.0, 0.0);
+    }
+    let now = jiff::Zoned::now();
+    let hour = now.hour() as f32 + now.minute() as f32 / 60.0 + now.second() as f32 / (60.0 * 60.0);
+    offset_at(config, hour)
+}
+
+/// The offset at `hour` (fractional, `0.0..24.0`), interpolated between the surrounding
+/// keypoints. The keypoints wrap around midnight.
+pub fn offset_at(config: &TimeOfDayConfig, hour: f32) -> Oklab {
+    let mut keypoints = config.keypoints.iter().collect::<Vec<_>>();
+    keypoints.sort_by(|a, b| a.hour.total_cmp(&b.hour));
+    let (Some(&first), Some(&last)) = (keypoints.first(), keypoints.last()) else {
+        return Oklab::new(0.0, 0.0, 0.0);
+    };
+
+    let (before, after) = match keypoints.iter().position(|keypoint| keypoint.hour > hour) {
+        Some(0) | None => (last, first),
+        Some(i) => (keypoints[i - 1], keypoints[i]),
+    };
+
+    let span = (after.hour - before.hour).rem_euclid(24.0);
+    let t = if span == 0.0 {
+        0.0
+    } else {
+        (hour - before.hour).rem_euclid(24.0) / span
+    };
+    let t = match config.interpolation {
+        Interpolation::Linear => t,
+        Interpolation::Smooth => t * t * (3.0 - 2.0 * t),
+        Interpolation::Step => 0.0,
+    };
+
+    let lerp = |from: f32, to: f32| from + (to - from) * t;
+    Oklab::new(
+        lerp(before.lightness, after.lightness),
+        lerp(before.a, after.a),
+        lerp(before.b, after.b),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TimeKeypoint;
+
+    fn config(interpolation: Interpolation, keypoints: &[(f32, f32)]) -> TimeOfDayConfig {
+        TimeOfDayConfig {
+            interpolation,
+            keypoints: keypoints
+                .iter()
+                .map(|&(hour, lightness)| TimeKeypoint {
+                    hour,
+                    lightness,
+                    a: 0.0,
+                    b: 0.0,
+                })
+                .collect(),
+            ..TimeOfDayConfig::default()
+        }
+    }
+
+    #[test]
+    fn offset_at_interpolates_around_midnight() {
+        // out of order on purpose, they are sorted first
+        let linear = config(Interpolation::Linear, &[(18.0, -0.1), (6.0, 0.1)]);
+        assert!((offset_at(&linear, 6.0).l - 0.1).abs() < 1e-6);
+        assert!(offset_at(&linear, 12.0).l.abs() < 1e-6);
+        assert!(offset_at(&linear, 0.0).l.abs() < 1e-6);
+        assert!((offset_at(&linear, 3.0).l - 0.05).abs() < 1e-6);
+        assert!((offset_at(&linear, 21.0).l + 0.05).abs() < 1e-6);
+    }
+
+    #[test]
+    fn offset_at_steps_and_without_keypoints() {
+        let step = config(Interpolation::Step, &[(6.0, 0.1), (18.0, -0.1)]);
+        assert_eq!(offset_at(&step, 17.9).l, 0.1);
+        assert_eq!(offset_at(&step, 18.0).l, -0.1);
+        assert_eq!(offset_at(&step, 2.0).l, -0.1);
+
+        let empty = config(Interpolation::Linear, &[]);
+        assert_eq!(offset_at(&empty, 12.0).l, 0.0);
+    }
+}
@@ -0,0 +1,59 @@
+use wayland_client::protocol::wl_surface::WlSurface;
+
+/// How far a finger may move, in surface pixels, for it to still count as a tap.
+const MAX_TAP_DISTANCE: f64 = 16.0;
+
+/// Turns the touch events of a seat into taps, ignoring drags and multi-finger gestures.
+#[derive(Default)]
+pub struct TapTracker {
+    active_touches: usize,
+    candidate: Option<TapCandidate>,
+}
+
+struct TapCandidate {
+    surface: WlSurface,
+    id: i32,
+    start: (f64, f64),
+}
+
+impl TapTracker {
+    pub fn down(&mut self, surface: WlSurface, id: i32, position: (f64, f64)) {
+        self.active_touches += 1;
+        self.candidate = if self.active_touches == 1 {
+            Some(TapCandidate {
+                surface,
+                id,
+                start: position,
+            })
+        } else {
+            // a second finger makes it a gesture
+            None
+        };
+    }
+
+    pub fn motion(&mut self, id: i32, position: (f64, f64)) {
+        if let Some(candidate) = &self.candidate
+            && candidate.id == id
+        {
+            let (dx, dy) = (
+                position.0 - candidate.start.0,
+                position.1 - candidate.start.1,
+            );
+            if dx.hypot(dy) > MAX_TAP_DISTANCE {
+                self.candidate = None;
+            }
+        }
+    }
+
+    /// Returns the surface and position of the tap if this finished one.
+    pub fn up(&mut self, id: i32) -> Option<(WlSurface, (f64, f64))> {
+        self.active_touches = self.active_touches.saturating_sub(1);
+        let candidate = self.candidate.take_if(|candidate| candidate.id == id)?;
+        Some((candidate.surface, candidate.start))
+    }
+
+    pub fn cancel(&mut self) {
+        self.active_touches = 0;
+        self.candidate = None;
+    }
+}
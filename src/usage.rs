@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// At most this much is added to the squared distance of the most launched app in the voronoi
+/// diagram, at `render.popularity = 1`.
+const MAX_WEIGHT: f32 = 0.01;
+
+/// How often and when apps were launched, with older launches counting less. Stored in
+/// `$XDG_STATE_HOME/colouncher/usage.toml`.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Usage {
+    apps: HashMap<String, AppUsage>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AppUsage {
+    /// The number of launches, decayed up to `updated`.
+    score: f64,
+    /// When it was last launched, in seconds since the epoch.
+    updated: u64,
+    /// The number of launches, without decay.
+    #[serde(default)]
+    count: u64,
+}
+
+impl Usage {
+    /// Empty if nothing was launched yet or the file can't be read.
+    pub fn load() -> Self {
+        usage_path()
+            .filter(|path| path.exists())
+            .map(|path| -> Result<Self> {
+                let contents = std::fs::read_to_string(&path)
+                    .wrap_err_with(|| format!("reading {}", path.display()))?;
+                toml::from_str(&contents).wrap_err_with(|| format!("parsing {}", path.display()))
+            })
+            .transpose()
+            .unwrap_or_else(|err| {
+                warn!("Ignoring the app usage: {err:?}");
+                None
+            })
+            .unwrap_or_default()
+    }
+
+    /// Counts a launch of the app and writes the usage back.
+    pub fn record(&mut self, id: &str, half_life_days: f32) {
+        // other instances (on other seats, or before a restart) might have launched apps since
+        self.merge(Self::load());
+
+        let now = now();
+        let app = self.apps.entry(id.to_owned()).or_insert(AppUsage {
+            score: 0.0,
+            updated: now,
+            count: 0,
+        });
+        app.score = decay(app.score, now.saturating_sub(app.updated), half_life_days) + 1.0;
+        app.updated = now;
+        app.count += 1;
+
+        if let Err(err) = self.write() {
+            warn!("Failed to write the app usage: {err:?}");
+        }
+    }
+
+    /// How often the app was launched.
+    pub fn count(&self, id: &str) -> u64 {
+        self.apps.get(id).map_or(0, |app| app.count)
+    }
+
+    /// The ids of the `n` most recently launched apps, most recent first.
+    pub fn recent(&self, n: usize) -> Vec<&str> {
+        let mut apps = self.apps.iter().collect::<Vec<_>>();
+        apps.sort_by(|(a_id, a), (b_id, b)| b.updated.cmp(&a.updated).then(a_id.cmp(b_id)));
+        apps.into_iter()
+            .take(n)
+            .map(|(id, _)| id.as_str())
+            .collect()
+    }
+
+    /// Keeps the more recently launched of every app.
+    fn merge(&mut self, other: Self) {
+        for (id, app) in other.apps {
+            match self.apps.get(&id) {
+                Some(existing) if existing.updated >= app.updated => {}
+                _ => {
+                    self.apps.insert(id, app);
+                }
+            }
+        }
+    }
+
+    /// The decayed number of launches of the app.
+    pub fn score(&self, id: &str, half_life_days: f32) -> f64 {
+        self.apps.get(id).map_or(0.0, |app| {
+            decay(app.score, now().saturating_sub(app.updated), half_life_days)
+        })
+    }
+
+    fn write(&self) -> Result<()> {
+        let Some(path) = usage_path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).wrap_err_with(|| format!("creating {}", dir.display()))?;
+        }
+        let contents = toml::to_string(self).wrap_err("serializing the usage")?;
+        // written next to it and renamed, so another instance never reads half a file
+        let tmp = path.with_extension("toml.tmp");
+        std::fs::write(&tmp, contents).wrap_err_with(|| format!("writing {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).wrap_err_with(|| format!("renaming to {}", path.display()))
+    }
+}
+
+/// How much larger the voronoi cells of the seeds are, from their decayed launch counts.
+/// The most launched one gets `popularity * MAX_WEIGHT`, the others proportionally less.
+pub fn weights(scores: &[f64], popularity: f32) -> Vec<f32> {
+    let max = scores.iter().copied().fold(0.0, f64::max);
+    scores
+        .iter()
+        .map(|&score| {
+            if max > 0.0 {
+                (score / max) as f32 * popularity * MAX_WEIGHT
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+/// Halves the score every `half_life_days`.
+fn decay(score: f64, elapsed_secs: u64, half_life_days: f32) -> f64 {
+    let half_life_secs = f64::from(half_life_days) * 24.0 * 60.0 * 60.0;
+    score * 0.5_f64.powf(elapsed_secs as f64 / half_life_secs)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn usage_path() -> Option<PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_home.join("colouncher").join("usage.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decay_halves_every_half_life() {
+        const DAY: u64 = 24 * 60 * 60;
+        assert_eq!(decay(4.0, 0, 1.0), 4.0);
+        assert!((decay(4.0, DAY, 1.0) - 2.0).abs() < 1e-9);
+        assert!((decay(4.0, 3 * DAY, 1.5) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn weights_are_relative_to_the_most_launched() {
+        assert_eq!(
+            weights(&[2.0, 1.0, 0.0], 1.0),
+            [MAX_WEIGHT, MAX_WEIGHT / 2.0, 0.0]
+        );
+        assert_eq!(weights(&[0.0, 0.0], 1.0), [0.0, 0.0]);
+    }
+
+    fn usage(apps: &[(&str, u64, u64)]) -> Usage {
+        Usage {
+            apps: apps
+                .iter()
+                .map(|&(id, updated, count)| {
+                    let app = AppUsage {
+                        score: count as f64,
+                        updated,
+                        count,
+                    };
+                    (id.to_owned(), app)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn merge_keeps_the_more_recent_launch() {
+        let mut ours = usage(&[("a.desktop", 10, 1), ("b.desktop", 30, 3)]);
+        let theirs = usage(&[
+            ("a.desktop", 20, 2),
+            ("b.desktop", 25, 5),
+            ("c.desktop", 5, 1),
+        ]);
+        ours.merge(theirs);
+        assert_eq!(ours.count("a.desktop"), 2);
+        assert_eq!(ours.count("b.desktop"), 3);
+        assert_eq!(ours.count("c.desktop"), 1);
+        assert_eq!(ours.recent(2), ["b.desktop", "a.desktop"]);
+    }
+
+    #[test]
+    fn popularity_weights_fade_with_time() {
+        const DAY: u64 = 24 * 60 * 60;
+        let now = now();
+        // more launches, but three half-lives ago
+        let usage = usage(&[("old.desktop", now - 3 * DAY, 8), ("new.desktop", now, 2)]);
+        let scores = [
+            usage.score("old.desktop", 1.0),
+            usage.score("new.desktop", 1.0),
+        ];
+        let [old, new] = weights(&scores, 1.0)[..] else {
+            unreachable!()
+        };
+        assert!((old - MAX_WEIGHT / 2.0).abs() < 1e-6, "{old}");
+        assert!((new - MAX_WEIGHT).abs() < 1e-6, "{new}");
+        assert_eq!(usage.score("never.desktop", 1.0), 0.0);
+    }
+
+    #[test]
+    fn usage_survives_writing_and_reading() {
+        let usage = usage(&[("a.desktop", 10, 1), ("b.desktop", 30, 3)]);
+        let read: Usage = toml::from_str(&toml::to_string(&usage).unwrap()).unwrap();
+        assert_eq!(read.count("a.desktop"), 1);
+        assert_eq!(read.count("b.desktop"), 3);
+        assert_eq!(read.recent(2), ["b.desktop", "a.desktop"]);
+        assert!((read.score("b.desktop", 1e9) - 3.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use eyre::{Context, Result};
+use palette::{IntoColor, Oklab, Srgba};
+
+use crate::config::ImageFit;
+
+/// An image shown instead of the generated gradient.
+pub struct Wallpaper {
+    pub image: image::RgbaImage,
+}
+
+/// Where the image ends up on the surface, in surface pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImagePlacement {
+    pub origin: [f32; 2],
+    pub size: [f32; 2],
+}
+
+impl Wallpaper {
+    pub fn load(path: &Path) -> Result<Self> {
+        let image = image::ImageReader::open(path)
+            .wrap_err_with(|| format!("{}", path.display()))?
+            .with_guessed_format()
+            .wrap_err_with(|| format!("{}", path.display()))?
+            .decode()
+            .wrap_err_with(|| format!("decoding {}", path.display()))?;
+        Ok(Self {
+            image: image.to_rgba8(),
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    /// The color of the image at the surface pixel, or `None` if the image doesn't cover it.
+    // keep it in sync with the gpu implementation
+    pub fn color_at(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        fit: ImageFit,
+    ) -> Option<Oklab> {
+        let placement = place_image((width, height), self.size(), fit);
+        let u = (x as f32 - placement.origin[0]) / placement.size[0];
+        let v = (y as f32 - placement.origin[1]) / placement.size[1];
+        if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return None;
+        }
+
+        let (image_width, image_height) = self.size();
+        let px = ((u * image_width as f32) as u32).min(image_width - 1);
+        let py = ((v * image_height as f32) as u32).min(image_height - 1);
+        let [r, g, b, a] = self.image.get_pixel(px, py).0;
+        let color: palette::Oklaba = Srgba::new(r, g, b, a).into_linear().into_color();
+        Some(color.color)
+    }
+}
+
+pub fn place_image(surface: (u32, u32), image: (u32, u32), fit: ImageFit) -> ImagePlacement {
+    let (surface_width, surface_height) = (surface.0 as f32, surface.1 as f32);
+    let (image_width, image_height) = (image.0 as f32, image.1 as f32);
+
+    let scale_x = surface_width / image_width;
+    let scale_y = surface_height / image_height;
+    let scale = match fit {
+        ImageFit::Cover => scale_x.max(scale_y),
+        ImageFit::Contain => scale_x.min(scale_y),
+    };
+
+    let size = [image_width * scale, image_height * scale];
+    ImagePlacement {
+        origin: [
+            (surface_width - size[0]) / 2.0,
+            (surface_height - size[1]) / 2.0,
+        ],
+        size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn place_image_covers_or_contains() {
+        let cover = place_image((1920, 1080), (960, 960), ImageFit::Cover);
+        assert_eq!(cover.size, [1920.0, 1920.0]);
+        assert_eq!(cover.origin, [0.0, -420.0]);
+
+        let contain = place_image((1920, 1080), (960, 960), ImageFit::Contain);
+        assert_eq!(contain.size, [1080.0, 1080.0]);
+        assert_eq!(contain.origin, [420.0, 0.0]);
+    }
+}